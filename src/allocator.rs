@@ -45,6 +45,23 @@ pub fn init_heap(
     Ok(())
 }
 
+/// Lets the heap grow by `size` bytes past `HEAP_START + HEAP_SIZE` when it runs out.
+///
+/// Used by `swap::init` to hand the swappable area to the heap, so allocations that don't fit
+/// any more spill into pages that can go out to the disk.
+///
+/// # Safety
+///
+/// The `size` bytes after the heap must be mapped (or brought in on a page fault) and unused.
+pub unsafe fn set_spill_size(size: usize) {
+    ALLOCATOR.lock().set_spill_size(size);
+}
+
+/// Bytes of the heap currently in use.
+pub fn heap_used() -> usize {
+    ALLOCATOR.lock().used()
+}
+
 pub struct Dummy;
 
 unsafe impl GlobalAlloc for Dummy {
@@ -19,6 +19,14 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// How many bytes an allocation with the given layout takes up.
+fn allocated_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
+    }
+}
+
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
@@ -26,6 +34,9 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    used: usize,
+    // bytes past the end of the heap it may still grow into, see `set_spill_size`
+    spill_size: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -35,9 +46,16 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            used: 0,
+            spill_size: 0,
         }
     }
 
+    /// Bytes currently handed out, counting whole blocks for the small size classes.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
     /// Initialize the allocator with the given heap bounds.
     ///
     /// This function is unsafe because the caller must guarantee that the given
@@ -47,8 +65,25 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
-    /// Allocates using the fallback allocator.
+    /// Lets the heap grow by `size` bytes once it runs out, into the memory right after it.
+    ///
+    /// This function is unsafe because the caller must guarantee that the memory is valid and
+    /// unused.
+    pub unsafe fn set_spill_size(&mut self, size: usize) {
+        self.spill_size = size;
+    }
+
+    /// Allocates using the fallback allocator, growing the heap into the spill area the first
+    /// time it's full.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        if self.spill_size == 0 {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_allocator.extend(self.spill_size) };
+        self.spill_size = 0;
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
@@ -59,7 +94,7 @@ impl FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -77,11 +112,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.used += allocated_size(&layout);
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.used -= allocated_size(&layout);
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
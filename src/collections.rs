@@ -0,0 +1,108 @@
+/// A fixed-capacity buffer that drops its oldest item to make room for a new one.
+///
+/// Lives entirely inline, so it can back statics that are used before the heap is set up.
+pub struct RingBuffer<T, const N: usize> {
+    items: [Option<T>; N],
+    // index of the oldest item
+    start: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    const NONE: Option<T> = None;
+
+    pub const fn new() -> Self {
+        RingBuffer { items: [Self::NONE; N], start: 0, len: 0 }
+    }
+
+    /// Adds an item after the newest one, returning the oldest item if it had to make room.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if N == 0 {
+            return Some(item);
+        }
+        let end = (self.start + self.len) % N;
+        let evicted = self.items[end].replace(item);
+        if self.len < N {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % N;
+        }
+        evicted
+    }
+
+    /// The `i`th item, counting from the oldest.
+    pub fn get(&self, i: usize) -> Option<&T> {
+        if i >= self.len {
+            return None;
+        }
+        self.items[(self.start + i) % N].as_ref()
+    }
+
+    pub fn newest(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    /// Iterates from the oldest item to the newest.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+
+    pub fn clear(&mut self) {
+        for item in self.items.iter_mut() {
+            *item = None;
+        }
+        self.start = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+#[test_case]
+fn test_ring_buffer_fills_up() {
+    let mut ring: RingBuffer<u32, 3> = RingBuffer::new();
+    assert!(ring.is_empty());
+    assert_eq!(ring.push(1), None);
+    assert_eq!(ring.push(2), None);
+    assert_eq!(ring.len(), 2);
+    assert_eq!(ring.capacity(), 3);
+    assert_eq!(ring.newest(), Some(&2));
+    assert!(ring.iter().copied().eq([1, 2]));
+}
+
+#[test_case]
+fn test_ring_buffer_evicts_oldest() {
+    let mut ring: RingBuffer<u32, 3> = RingBuffer::new();
+    for i in 0..3 {
+        ring.push(i);
+    }
+    assert_eq!(ring.push(3), Some(0));
+    assert_eq!(ring.push(4), Some(1));
+    assert_eq!(ring.len(), 3);
+    assert!(ring.iter().copied().eq([2, 3, 4]));
+    assert!(ring.iter().rev().copied().eq([4, 3, 2]));
+    assert_eq!(ring.get(0), Some(&2));
+    assert_eq!(ring.get(3), None);
+}
+
+#[test_case]
+fn test_ring_buffer_wraps_many_times() {
+    let mut ring: RingBuffer<u32, 4> = RingBuffer::new();
+    for i in 0..103 {
+        ring.push(i);
+    }
+    assert!(ring.iter().copied().eq([99, 100, 101, 102]));
+    ring.clear();
+    assert!(ring.is_empty());
+    assert_eq!(ring.newest(), None);
+}
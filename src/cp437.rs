@@ -0,0 +1,35 @@
+/// What code page 437, the VGA text mode font, draws for the control bytes 0x00 to 0x1F.
+static CONTROL_GLYPHS: [char; 32] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+/// What it draws for 0x7F.
+const DELETE_GLYPH: char = '⌂';
+
+/// The character the VGA font shows for a control byte (or DEL), so it can go through a `str`.
+/// Other bytes are returned as the ASCII character they are.
+pub fn glyph(byte: u8) -> char {
+    match byte {
+        0x00..=0x1F => CONTROL_GLYPHS[byte as usize],
+        0x7F => DELETE_GLYPH,
+        _ => byte as char,
+    }
+}
+
+/// The control byte whose glyph `c` is, for writing it to the screen as that glyph.
+pub fn control_byte(c: char) -> Option<u8> {
+    if c == DELETE_GLYPH {
+        return Some(0x7F);
+    }
+    // 0x00 draws as a blank, which is just a space
+    CONTROL_GLYPHS.iter().skip(1).position(|glyph| *glyph == c).map(|i| i as u8 + 1)
+}
+
+#[test_case]
+fn test_control_glyphs_round_trip() {
+    for byte in (0x01..=0x1F).chain([0x7F]) {
+        assert_eq!(control_byte(glyph(byte)), Some(byte));
+    }
+    assert_eq!(glyph(b'a'), 'a');
+    assert_eq!(control_byte('a'), None);
+}
@@ -0,0 +1,76 @@
+use alloc::{string::String, vec::Vec};
+use core::arch::x86_64::__cpuid;
+use core::ptr::{read_volatile, write_volatile};
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+/// The CPU vendor string from CPUID leaf 0, e.g. `GenuineIntel`.
+pub fn vendor() -> String {
+    let leaf = __cpuid(0);
+    let mut bytes = Vec::with_capacity(12);
+    for reg in [leaf.ebx, leaf.edx, leaf.ecx] {
+        bytes.extend_from_slice(&reg.to_le_bytes());
+    }
+    String::from_utf8_lossy(&bytes).into()
+}
+
+/// The processor brand string from the extended CPUID leaves, if the CPU has them.
+pub fn brand() -> Option<String> {
+    let max_extended = __cpuid(0x8000_0000).eax;
+    if max_extended < 0x8000_0004 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(48);
+    for leaf in 0x8000_0002..=0x8000_0004 {
+        let leaf = __cpuid(leaf);
+        for reg in [leaf.eax, leaf.ebx, leaf.ecx, leaf.edx] {
+            bytes.extend_from_slice(&reg.to_le_bytes());
+        }
+    }
+    let brand = String::from_utf8_lossy(&bytes);
+    Some(brand.trim_matches(|c| c == '\0' || c == ' ').into())
+}
+
+/// Names of the interesting feature flags from CPUID leaf 1.
+pub fn features() -> Vec<&'static str> {
+    const EDX_FEATURES: [(u32, &str); 6] =
+        [(0, "fpu"), (4, "tsc"), (5, "msr"), (9, "apic"), (25, "sse"), (26, "sse2")];
+    const ECX_FEATURES: [(u32, &str); 6] =
+        [(0, "sse3"), (19, "sse4.1"), (20, "sse4.2"), (28, "avx"), (30, "rdrand"), (31, "hypervisor")];
+
+    let leaf = __cpuid(1);
+    let mut features = Vec::new();
+    for (bit, name) in EDX_FEATURES {
+        if leaf.edx & (1 << bit) != 0 { features.push(name); }
+    }
+    for (bit, name) in ECX_FEATURES {
+        if leaf.ecx & (1 << bit) != 0 { features.push(name); }
+    }
+    features
+}
+
+/// Tests whether the A20 gate is enabled.
+///
+/// With A20 disabled, physical addresses wrap at 1 MiB, so a write to `0x10_0500` would show up
+/// at `0x500`. We write to the alias and check whether the low address changed, then put both
+/// values back. Needs the physical memory mapping from `memory::init`.
+pub fn a20_enabled() -> bool {
+    let low = memory::phys_to_virt(PhysAddr::new(0x500))
+        .expect("a20_enabled called before memory::init")
+        .as_mut_ptr::<u32>();
+    let high = memory::phys_to_virt(PhysAddr::new(0x10_0500))
+        .expect("a20_enabled called before memory::init")
+        .as_mut_ptr::<u32>();
+
+    // nothing else may run, and maybe read 0x500, while the test value is in place
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        let saved_low = read_volatile(low);
+        let saved_high = read_volatile(high);
+        write_volatile(high, !saved_low);
+        let enabled = read_volatile(low) == saved_low;
+        write_volatile(high, saved_high);
+        write_volatile(low, saved_low);
+        enabled
+    })
+}
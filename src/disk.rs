@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 pub static ATA_IO_PORT_PRIMARY: u16 = 0x01F0;
 pub static ATA_IO_PORT_SECONDARY: u16 = 0x0170;
 
@@ -5,4 +7,38 @@ pub static ATA_CONTROL_PORT_PRIMARY: u16 = 0x03F6;
 pub static ATA_CONTROL_PORT_SECONDARY: u16 = 0x0376;
 
 
-pub mod pio;
\ No newline at end of file
+pub mod irq;
+pub mod pio;
+pub mod ram;
+
+/// Something sectors can be read from and written to by LBA: a drive, or a stand-in for one
+/// such as `ram::RamDisk`.
+pub trait BlockDevice {
+    /// Words in each sector.
+    fn sector_words(&self) -> usize;
+    /// Reads `count` sectors starting at `lba` into `buf`.
+    fn read_sectors(&mut self, buf: &mut [u16], lba: u64, count: u32) -> Result<(), pio::DiskError>;
+    /// Writes `count` sectors from `data` starting at `lba`.
+    fn write_sectors(&mut self, data: &mut [u16], lba: u64, count: u32) -> Result<(), pio::DiskError>;
+}
+
+/// The bytes held in disk words, low byte first, the order the drive stores them in.
+pub fn words_to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Packs bytes into disk words, low byte first. An odd last byte gets a zero high byte.
+pub fn bytes_to_words(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .collect()
+}
+
+#[test_case]
+fn test_word_byte_round_trip() {
+    assert_eq!(bytes_to_words(b"abc"), [u16::from_le_bytes([b'a', b'b']), b'c' as u16]);
+    assert_eq!(words_to_bytes(&bytes_to_words(b"abcd")), b"abcd");
+    // the padding comes back as a zero, which ends the text on the disk
+    assert_eq!(words_to_bytes(&bytes_to_words(b"abc")), b"abc\0");
+    assert!(bytes_to_words(b"").is_empty());
+}
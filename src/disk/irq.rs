@@ -0,0 +1,150 @@
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::MutexGuard;
+use x86_64::instructions::port::Port;
+
+use super::pio::{retry_delay_ms, sectors_in, Bus, DiskError, Driver, IOPortRead, BUS_IO_BASES, DRIVER};
+use crate::{klog, klog::Level, task::semaphore::{Permit, Semaphore}};
+use crate::time::{read_timer, ticks_since, ticks_to_ms};
+
+/// How long `read_async` waits for a sector's interrupt before polling the drive instead.
+///
+/// PIO-mode interrupts don't arrive on every drive (or every QEMU configuration), so this is
+/// kept short; polling is only slower, not wrong.
+pub const IRQ_TIMEOUT_MS: u128 = 10;
+
+// set by the interrupt handlers, one per bus
+static IRQ_PENDING: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+static BY_IRQ: AtomicU64 = AtomicU64::new(0);
+static BY_POLLING: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the ATA interrupt handlers.
+///
+/// Must not block or allocate. Reading the status register acknowledges the interrupt.
+pub(crate) fn notify(bus: Bus) {
+    let mut status: Port<u8> = Port::new(BUS_IO_BASES[bus as u8 as usize] + IOPortRead::StatusRegister as u16);
+    unsafe { status.read() };
+    IRQ_PENDING[bus as u8 as usize].store(true, Ordering::Release);
+}
+
+/// How many sectors `read_async` has read after an interrupt, and how many it had to poll for.
+pub fn stats() -> (u64, u64) {
+    (BY_IRQ.load(Ordering::Relaxed), BY_POLLING.load(Ordering::Relaxed))
+}
+
+/// Held for every sequence of disk commands, so another one can't start in the middle of it:
+/// by `read_async` across the awaits of its transfer, and by a `DriverGuard` for commands
+/// sent without yielding.
+pub static DISK_ACCESS: Semaphore = Semaphore::new(1);
+
+/// `DRIVER`, locked while holding a `DISK_ACCESS` permit. Everything but `read_async` talks
+/// to the drive through one of these.
+pub struct DriverGuard {
+    // unlocked before the permit goes back and wakes whoever waits for it
+    driver: MutexGuard<'static, Driver>,
+    _permit: Permit<'static>,
+}
+
+impl Deref for DriverGuard {
+    type Target = Driver;
+
+    fn deref(&self) -> &Driver {
+        &self.driver
+    }
+}
+
+impl DerefMut for DriverGuard {
+    fn deref_mut(&mut self) -> &mut Driver {
+        &mut self.driver
+    }
+}
+
+/// Locks the driver, failing with `DiskError::Busy` while a task is in the middle of a
+/// `read_async` transfer.
+///
+/// Waiting for that task's permit here would never end, since it can only finish once the
+/// caller yields; async code should use `lock_driver_async` instead.
+pub fn lock_driver() -> Result<DriverGuard, DiskError> {
+    let permit = DISK_ACCESS.try_acquire().ok_or(DiskError::Busy)?;
+    Ok(DriverGuard { driver: DRIVER.lock(), _permit: permit })
+}
+
+/// Locks the driver once no task is in the middle of a transfer, yielding until then.
+///
+/// Don't hold the guard across an await, a sync `lock_driver` would spin on it forever.
+pub async fn lock_driver_async() -> DriverGuard {
+    let permit = DISK_ACCESS.acquire().await;
+    DriverGuard { driver: DRIVER.lock(), _permit: permit }
+}
+
+/// Like `lock_driver`, for interrupt handlers: `None` if the driver is busy or locked by the
+/// code that was interrupted, rather than waiting on it.
+pub fn try_lock_driver() -> Option<DriverGuard> {
+    let permit = DISK_ACCESS.try_acquire()?;
+    Some(DriverGuard { driver: DRIVER.try_lock()?, _permit: permit })
+}
+
+/// Like `Driver::read`, but yields to other tasks while the drive works, and retries a read
+/// that failed with a transient error.
+///
+/// Up to the driver's `max_retries` retries are made, sleeping `pio::retry_delay_ms` in
+/// between; a transient error that outlasts them is returned as `DiskError::GaveUp`.
+/// `DISK_ACCESS` isn't held while sleeping.
+pub async fn read_async(buf: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+    let max_retries = DRIVER.lock().max_retries();
+    let mut retries = 0;
+    loop {
+        let error = match read_once(buf, lba, sector_count).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e.transient().ok_or(e)?,
+        };
+        if retries == max_retries {
+            return Err(error.after(retries));
+        }
+        klog!(Level::Debug, "read of lba {} failed: {}, retry {} of {}", lba, DiskError::from(error), retries + 1, max_retries);
+        crate::task::sleep_ms(retry_delay_ms(retries)).await;
+        retries += 1;
+    }
+}
+
+/// One try at `read_async`'s read.
+///
+/// Each sector is waited for through its interrupt, falling back to polling the status
+/// register if the interrupt doesn't come within `IRQ_TIMEOUT_MS`, so the read finishes either
+/// way. The driver is only locked while talking to the drive, never across an await;
+/// `DISK_ACCESS` is held for the whole read instead.
+async fn read_once(buf: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+    let _permit = DISK_ACCESS.acquire().await;
+    let (bus, sector_words) = {
+        let mut driver = DRIVER.lock();
+        driver.check_buffer(buf.len(), sectors_in(sector_count))?;
+        IRQ_PENDING[driver.bus() as u8 as usize].store(false, Ordering::Release);
+        driver.start_read(lba, sector_count)?;
+        (driver.bus(), driver.sector_words())
+    };
+    for sector in 0..sectors_in(sector_count) {
+        if wait_for_irq(bus).await {
+            BY_IRQ.fetch_add(1, Ordering::Relaxed);
+        } else {
+            BY_POLLING.fetch_add(1, Ordering::Relaxed);
+        }
+        // polls BSY and DRQ itself, which is all the fallback needs
+        DRIVER.lock().read_sector(&mut buf[sector * sector_words..(sector + 1) * sector_words])?;
+    }
+    Ok(())
+}
+
+/// Waits for the bus to interrupt, returning false if `IRQ_TIMEOUT_MS` passed first.
+async fn wait_for_irq(bus: Bus) -> bool {
+    let start = read_timer();
+    loop {
+        // taken here, so the interrupt for the next sector is waited for afresh
+        if IRQ_PENDING[bus as u8 as usize].swap(false, Ordering::Acquire) {
+            return true;
+        }
+        if ticks_to_ms(ticks_since(start)) >= IRQ_TIMEOUT_MS {
+            return false;
+        }
+        crate::task::yield_now().await;
+    }
+}
@@ -1,4 +1,6 @@
-use crate::println;
+use crate::{klog, klog::Level};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
 
 use super::*;
 
@@ -8,6 +10,35 @@ use x86_64::instructions::port::{Port, PortGeneric, ReadWriteAccess};
 
 pub static WRITE_COMMAND: u8 = 0x30;
 pub static READ_COMMAND: u8 = 0x20;
+pub static WRITE_EXT_COMMAND: u8 = 0x34;
+pub static READ_EXT_COMMAND: u8 = 0x24;
+pub static SMART_COMMAND: u8 = 0xB0;
+pub static STANDBY_IMMEDIATE_COMMAND: u8 = 0xE0;
+pub static MEDIA_EJECT_COMMAND: u8 = 0xED;
+pub static PACKET_COMMAND: u8 = 0xA0;
+// SCSI START STOP UNIT with LoEj set and Start clear: stop the disc and open the tray
+const EJECT_PACKET: [u8; 12] = [0x1B, 0, 0, 0, 0x02, 0, 0, 0, 0, 0, 0, 0];
+const SMART_ENABLE_OPERATIONS: u8 = 0xD8;
+const SMART_RETURN_STATUS: u8 = 0xDA;
+// SMART commands need this in LBA mid/high, and RETURN STATUS answers in the same registers
+const SMART_SIGNATURE: (u8, u8) = (0x4F, 0xC2);
+const SMART_FAILING_SIGNATURE: (u8, u8) = (0xF4, 0x2C);
+/// How many times `identify` polls the status register before deciding nothing answered.
+pub const IDENTIFY_POLL_LIMIT: u32 = 1_000_000;
+/// The first LBA the 28-bit commands can't reach, from here on LBA48 is needed.
+pub const LBA28_LIMIT: u64 = 1 << 28;
+/// The first LBA past what LBA48 can address.
+pub const LBA48_LIMIT: u64 = 1 << 48;
+/// The most sectors `read_sectors` and `write_sectors` move with one command.
+pub const MAX_SECTORS_PER_COMMAND: u32 = 256;
+/// Words in a 512-byte sector, what drives use unless IDENTIFY says otherwise.
+pub const DEFAULT_SECTOR_WORDS: usize = 256;
+/// How many times a read or write that failed with a transient error is retried.
+pub const DEFAULT_MAX_RETRIES: u8 = 3;
+/// The most retries `Driver::set_max_retries` allows, so the last wait is at most 1.28 seconds.
+pub const MAX_RETRIES_LIMIT: u8 = 8;
+/// How long the first retry waits, doubled for every retry after.
+pub const RETRY_BASE_MS: u128 = 10;
 
 #[repr(u8)]
 pub enum IOPortRead {
@@ -57,7 +88,7 @@ pub mod status {
         BSY,
     }
 
-    #[derive(Clone, Copy)]
+    #[derive(Debug, Clone, Copy)]
     pub struct Status {
         pub val: u8,
     }
@@ -84,7 +115,7 @@ pub mod error {
         BBK,
     }
 
-    #[derive(Clone, Copy)]
+    #[derive(Debug, Clone, Copy)]
     pub struct Error {
         pub val: u8,
     }
@@ -97,11 +128,181 @@ pub mod error {
         pub fn media_changed(&self) -> bool { self.val & (1 << Bitflags::MC as u8) > 0 }
         pub fn uncorrectable_data(&self) -> bool { self.val & (1 << Bitflags::UNC as u8) > 0 }
         pub fn bad_block(&self) -> bool { self.val & (1 << Bitflags::BBK as u8) > 0 }
+        /// Whether the command was only aborted, which a busy or confused drive can do and a
+        /// retry may get past. Any other bit means the media or the address is bad.
+        pub fn transient(&self) -> bool { self.val == 1 << Bitflags::ABRT as u8 }
     }
 }
 
 type Disk = u8;
 
+/// How long to wait before retry `retry`, counting from 0.
+pub fn retry_delay_ms(retry: u8) -> u128 {
+    RETRY_BASE_MS << retry.min(MAX_RETRIES_LIMIT)
+}
+
+/// A failure that trying the command again might get past, see `DiskError::transient`.
+#[derive(Debug, Clone, Copy)]
+pub enum Transient {
+    /// The drive only aborted the command; holds the error register.
+    Aborted(error::Error),
+    /// The drive stayed busy instead of taking the command.
+    NotReady,
+}
+
+impl Transient {
+    /// The error for a command that still failed with this after `retries` retries.
+    pub fn after(self, retries: u8) -> DiskError {
+        match retries {
+            0 => self.into(),
+            _ => DiskError::GaveUp { retries, error: self },
+        }
+    }
+}
+
+impl From<Transient> for DiskError {
+    fn from(transient: Transient) -> DiskError {
+        match transient {
+            Transient::Aborted(err) => DiskError::Error(err),
+            Transient::NotReady => DiskError::NotReady,
+        }
+    }
+}
+
+/// Why a disk command failed.
+#[derive(Debug, Clone, Copy)]
+pub enum DiskError {
+    /// The drive set ERR; holds the error register.
+    Error(error::Error),
+    /// The drive set DF.
+    DriveFault,
+    /// Nothing is attached to the bus.
+    NoDevice,
+    /// The drive hasn't set RDY.
+    NotReady,
+    /// An ATAPI device such as a CD drive, which this driver can't write to.
+    ReadOnly,
+    /// The sectors asked for go past what LBA48 can address.
+    OutOfRange,
+    /// The buffer is too small for that many of the drive's sectors; holds their size in bytes.
+    SectorSize(usize),
+    /// A transient error that was still there after `retries` retries; holds the last one.
+    GaveUp { retries: u8, error: Transient },
+    /// The heap had no room for the sectors of a disk kept in memory.
+    OutOfMemory,
+    /// Another task is in the middle of a transfer, see `irq::lock_driver`.
+    Busy,
+}
+
+impl DiskError {
+    /// What trying the command again might get past, `None` if it's hopeless.
+    pub fn transient(&self) -> Option<Transient> {
+        match self {
+            DiskError::Error(err) if err.transient() => Some(Transient::Aborted(*err)),
+            DiskError::NotReady => Some(Transient::NotReady),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::DriveFault => write!(f, "drive fault"),
+            DiskError::NoDevice => write!(f, "no device on the bus"),
+            DiskError::NotReady => write!(f, "drive not ready"),
+            DiskError::ReadOnly => write!(f, "ATAPI device, which is read-only here"),
+            DiskError::OutOfRange => write!(f, "sector out of LBA48 range"),
+            DiskError::SectorSize(bytes) => write!(f, "buffer too small for the drive's {} byte sectors", bytes),
+            DiskError::GaveUp { retries, error } => write!(f, "{}, gave up after {} retries", DiskError::from(*error), retries),
+            DiskError::OutOfMemory => write!(f, "out of memory for the sectors"),
+            DiskError::Busy => write!(f, "the disk is busy with another task's transfer"),
+            DiskError::Error(err) => {
+                write!(f, "disk error {:#04x}", err.val)?;
+                let reasons = [
+                    (err.address_mark_not_found(), "address mark not found"),
+                    (err.track_zero_not_found(), "track zero not found"),
+                    (err.aborted_command(), "aborted command"),
+                    (err.media_change_request(), "media change request"),
+                    (err.id_not_found(), "ID not found"),
+                    (err.media_changed(), "media changed"),
+                    (err.uncorrectable_data(), "uncorrectable data"),
+                    (err.bad_block(), "bad block"),
+                ];
+                for (set, reason) in reasons {
+                    if set { write!(f, ", {}", reason)?; }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The parts of the IDENTIFY data we care about.
+pub struct DeviceInfo {
+    pub model: String,
+    /// Number of addressable sectors in LBA28 mode.
+    pub sectors: u32,
+    /// Words in each logical sector, `DEFAULT_SECTOR_WORDS` unless the drive says otherwise.
+    pub sector_words: usize,
+}
+
+/// What `Driver::identify` found on the selected drive.
+pub enum Identify {
+    /// Nothing answered IDENTIFY.
+    NoDrive,
+    Ata(DeviceInfo),
+    /// An ATAPI device such as a CD drive, which aborts IDENTIFY.
+    Atapi,
+    /// A drive is there, but it failed IDENTIFY; holds the error register.
+    Error(error::Error),
+}
+
+impl DeviceInfo {
+    /// Returns `None` for all-zero data, which no drive sends back.
+    pub fn from_identify(data: &[u16; 256]) -> Option<DeviceInfo> {
+        if data.iter().all(|w| *w == 0) {
+            return None;
+        }
+        // the model string is in words 27-46, with the two bytes of each word swapped
+        let mut model = String::new();
+        for word in &data[27..47] {
+            model.push((word >> 8) as u8 as char);
+            model.push((word & 0xFF) as u8 as char);
+        }
+        let model = String::from(model.trim());
+        let sectors = data[60] as u32 | (data[61] as u32) << 16;
+        Some(DeviceInfo { model, sectors, sector_words: logical_sector_words(data) })
+    }
+}
+
+/// Words per logical sector from IDENTIFY words 106 and 117-118.
+///
+/// Word 106 only counts if bit 14 is set and bit 15 clear; its bit 12 says the sectors are
+/// longer than 256 words, in which case 117-118 hold how long.
+fn logical_sector_words(data: &[u16; 256]) -> usize {
+    let info = data[106];
+    if info & 0xC000 == 0x4000 && info & (1 << 12) != 0 {
+        let words = data[117] as usize | (data[118] as usize) << 16;
+        if words > DEFAULT_SECTOR_WORDS {
+            return words;
+        }
+    }
+    DEFAULT_SECTOR_WORDS
+}
+
+/// What `Driver::smart_status` found out about the drive's health.
+#[derive(Debug, Clone, Copy)]
+pub enum SmartStatus {
+    /// None of the drive's thresholds are exceeded.
+    Ok,
+    /// The drive predicts it will fail.
+    Failing,
+    /// The drive aborted SMART, because it doesn't support it or has it turned off.
+    Unsupported,
+    Error(DiskError),
+}
+
 #[repr(u8)]
 #[derive(Clone, Copy)]
 pub enum Bus {
@@ -117,6 +318,9 @@ pub struct Driver {
     status: status::Status,
     disk: Disk,
     bus: Bus,
+    // words per sector of each drive, by bus * 2 + disk, as its last IDENTIFY said
+    sector_words: [usize; 4],
+    max_retries: u8,
 }
 
 impl Driver {
@@ -125,58 +329,157 @@ impl Driver {
         let bus = Bus::Primary;
         let mut p = Port::new(BUS_IO_BASES[bus as u8 as usize] + IOPortRead::StatusRegister as u16);
         let status = status::Status { val: unsafe { p.read() } };
-        Driver { status, disk, bus}
+        Driver { status, disk, bus, sector_words: [DEFAULT_SECTOR_WORDS; 4], max_retries: DEFAULT_MAX_RETRIES }
+    }
+    /// Whether the bus has nothing on it, in which case the status register reads 0xFF.
+    ///
+    /// BSY is one of those set bits, so `wait_bsy` would spin forever on an empty bus.
+    pub fn bus_floating(&mut self) -> bool {
+        self.read_status();
+        self.status.val == 0xFF
     }
     pub fn wait_bsy(&mut self) {
         self.read_status();
         while self.status.busy() {
+            core::hint::spin_loop();
             self.read_status();
         }
     }
-    pub fn wait_drq(&mut self) {
+    /// Waits for the drive to be ready to transfer data, or to report that it failed instead.
+    pub fn wait_drq(&mut self) -> Result<(), DiskError> {
         self.read_status();
         while !self.status.drive_request() {
-            //println!("{}", self.status.val);
+            if self.status.error() {
+                return Err(DiskError::Error(self.read_error()));
+            }
+            if self.status.drive_fault() {
+                return Err(DiskError::DriveFault);
+            }
+            core::hint::spin_loop();
             self.read_status();
         }
+        Ok(())
     }
     pub fn wait_rdy(&mut self) {
         self.read_status();
         while !self.status.ready() {
-            println!("{}", self.status.val);
+            klog!(Level::Debug, "waiting for RDY, status {:#04x}", self.status.val);
+            core::hint::spin_loop();
             self.read_status();
         }
     }
-    pub fn read(&mut self, buf: &mut [u16], lba: u32, sector_count: u8) {
-        self.wait_bsy();
+    /// Words in each of the selected drive's sectors.
+    ///
+    /// Learned from IDENTIFY, so it's `DEFAULT_SECTOR_WORDS` until `identify` has run on the
+    /// drive.
+    pub fn sector_words(&self) -> usize { self.sector_words[self.drive_index()] }
+    fn drive_index(&self) -> usize { self.bus as u8 as usize * 2 + self.disk as usize }
+    /// How many times a read or write that failed with a transient error is retried.
+    pub fn max_retries(&self) -> u8 { self.max_retries }
+    /// Sets `max_retries`, up to `MAX_RETRIES_LIMIT`.
+    pub fn set_max_retries(&mut self, retries: u8) { self.max_retries = retries.min(MAX_RETRIES_LIMIT); }
+    /// Runs `command` again while it fails with a transient error, up to `max_retries` times,
+    /// and returns its result or `DiskError::GaveUp`.
+    ///
+    /// Waits `retry_delay_ms` before each retry by busy-waiting, since the caller may have
+    /// interrupts off and can't sleep; `irq::read_async` does its own retries, asleep.
+    fn retry<T>(&mut self, mut command: impl FnMut(&mut Driver) -> Result<T, DiskError>) -> Result<T, DiskError> {
+        let mut retries = 0;
+        loop {
+            let error = match command(self) {
+                Ok(value) => return Ok(value),
+                Err(e) => e.transient().ok_or(e)?,
+            };
+            if retries == self.max_retries {
+                return Err(error.after(retries));
+            }
+            klog!(Level::Debug, "{}, retry {} of {}", DiskError::from(error), retries + 1, self.max_retries);
+            crate::time::io_wait_ms(retry_delay_ms(retries));
+            retries += 1;
+        }
+    }
+    /// Waits for the drive to clear BSY before a command, or reports it as not ready.
+    fn wait_not_busy(&mut self) -> Result<(), DiskError> {
+        match self.poll_status(|status| !status.busy()) {
+            true => Ok(()),
+            false => Err(DiskError::NotReady),
+        }
+    }
+    /// Checks that a buffer of `len` words holds `sectors` of the drive's sectors, before a
+    /// command is sent that would leave the drive waiting for the rest.
+    pub fn check_buffer(&self, len: usize, sectors: usize) -> Result<(), DiskError> {
+        if len < sectors * self.sector_words() {
+            return Err(DiskError::SectorSize(self.sector_words() * 2));
+        }
+        Ok(())
+    }
+    /// Reads `sector_count` sectors starting at `lba` into `buf`, `sector_words` words per
+    /// sector.
+    ///
+    /// As in the ATA protocol, a count of 0 means 256 sectors. Transient errors are retried,
+    /// see `retry`.
+    pub fn read(&mut self, buf: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+        self.retry(|driver| driver.read_once(buf, lba, sector_count))
+    }
+    /// One try at `read`.
+    fn read_once(&mut self, buf: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+        self.check_buffer(buf.len(), sectors_in(sector_count))?;
+        self.start_read(lba, sector_count)?;
+        for sector in buf.chunks_mut(self.sector_words()).take(sectors_in(sector_count)) {
+            self.read_sector(sector)?;
+        }
+        Ok(())
+    }
+    /// Sends a READ SECTORS command, leaving the data to be fetched a sector at a time with
+    /// `read_sector` once the drive has it ready.
+    pub fn start_read(&mut self, lba: u32, sector_count: u8) -> Result<(), DiskError> {
+        if self.bus_floating() {
+            return Err(DiskError::NoDevice);
+        }
+        self.wait_not_busy()?;
         let mut dsel_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
         let mut sec_count_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::SectorCountRegister as u16);
         let mut lba_lo_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBALow as u16);
         let mut lba_mid_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAMid as u16);
         let mut lba_high_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAHigh as u16);
         let mut cmd_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
-        let mut data_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
 
         unsafe {
-            let top_byte = (lba >> 24) & 0xF;
-            dsel_reg.write({self.disk << 4} | top_byte as u8 | (0x1 << 6)); 
+            dsel_reg.write(drive_select_lba28(self.disk, lba));
             sec_count_reg.write(sector_count);
             lba_lo_reg.write((lba & 0xFF) as u8);
             lba_mid_reg.write(((lba >> 8) & 0xFF) as u8);
             lba_high_reg.write(((lba >> 16) & 0xFF) as u8);
             cmd_reg.write(READ_COMMAND);
-
-            for sec in 0..sector_count as usize {
-                self.wait_bsy();
-                self.wait_drq();
-                for word in 0..256 {
-                    buf[sec * 256 + word as usize] = data_reg.read();
-                }
-            }
-        }   
+        }
+        Ok(())
     }
-    pub fn write(&mut self, data: &mut [u16], lba: u32, sector_count: u8) {
+    /// Waits for the next sector of a read started with `start_read` and copies its
+    /// `sector_words` words into `buf`.
+    pub fn read_sector(&mut self, buf: &mut [u16]) -> Result<(), DiskError> {
+        let mut data_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
         self.wait_bsy();
+        self.wait_drq()?;
+        for word in buf.iter_mut().take(self.sector_words()) {
+            *word = unsafe { data_reg.read() };
+        }
+        Ok(())
+    }
+    /// Writes `sector_count` sectors from `data` starting at `lba`, `sector_words` words per
+    /// sector.
+    ///
+    /// As in the ATA protocol, a count of 0 means 256 sectors. Transient errors are retried,
+    /// see `retry`.
+    pub fn write(&mut self, data: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+        self.retry(|driver| driver.write_once(data, lba, sector_count))
+    }
+    /// One try at `write`.
+    fn write_once(&mut self, data: &mut [u16], lba: u32, sector_count: u8) -> Result<(), DiskError> {
+        self.check_buffer(data.len(), sectors_in(sector_count))?;
+        if self.bus_floating() {
+            return Err(DiskError::NoDevice);
+        }
+        self.wait_not_busy()?;
         let mut dsel_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
         let mut sec_count_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::SectorCountRegister as u16);
         let mut lba_lo_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBALow as u16);
@@ -186,24 +489,116 @@ impl Driver {
         let mut data_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
 
         unsafe {
-            let top_byte = (lba >> 24) & 0xF;
-            dsel_reg.write({self.disk << 4} | top_byte as u8 | (0x1 << 6)); 
+            dsel_reg.write(drive_select_lba28(self.disk, lba));
             sec_count_reg.write(sector_count);
             lba_lo_reg.write((lba & 0xFF) as u8);
             lba_mid_reg.write((lba >> 8 & 0xFF) as u8);
             lba_high_reg.write((lba >> 16 & 0xFF) as u8);
             cmd_reg.write(WRITE_COMMAND);
 
-            for sec in 0..sector_count as usize {
+            for sector in data.chunks(self.sector_words()).take(sectors_in(sector_count)) {
                 self.wait_bsy();
-                self.wait_drq();
-                for word in 0..256 {
-                    data_reg.write(data[sec * 256 + word as usize]);
+                self.wait_drq()?;
+                for word in sector {
+                    data_reg.write(*word);
                 }
             }
         }   
+        Ok(())
+    }
+    /// Reads `count` sectors starting at `lba` into `buf`, `sector_words` words per sector, in
+    /// as many commands as it takes.
+    ///
+    /// Each command moves at most `MAX_SECTORS_PER_COMMAND` sectors, and is retried on its own
+    /// like `read`. Chunks below `LBA28_LIMIT` use the same commands as `read`, the others
+    /// LBA48's extended ones.
+    pub fn read_sectors(&mut self, buf: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        self.check_buffer(buf.len(), count as usize)?;
+        if lba + count as u64 > LBA48_LIMIT {
+            return Err(DiskError::OutOfRange);
+        }
+        for (first, sectors) in command_chunks(lba, count) {
+            let offset = (first - lba) as usize * self.sector_words();
+            let buf = &mut buf[offset..offset + sectors as usize * self.sector_words()];
+            if first + sectors as u64 <= LBA28_LIMIT {
+                // 256 is sent as 0
+                self.read(buf, first as u32, sectors as u8)?;
+            } else {
+                self.retry(|driver| {
+                    driver.start_command_ext(first, sectors, READ_EXT_COMMAND)?;
+                    let sector_words = driver.sector_words();
+                    buf.chunks_mut(sector_words).try_for_each(|sector| driver.read_sector(sector))
+                })?;
+            }
+        }
+        Ok(())
+    }
+    /// Writes `count` sectors from `data` starting at `lba`, chunked like `read_sectors`.
+    pub fn write_sectors(&mut self, data: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        self.check_buffer(data.len(), count as usize)?;
+        if lba + count as u64 > LBA48_LIMIT {
+            return Err(DiskError::OutOfRange);
+        }
+        for (first, sectors) in command_chunks(lba, count) {
+            let offset = (first - lba) as usize * self.sector_words();
+            let data = &mut data[offset..offset + sectors as usize * self.sector_words()];
+            if first + sectors as u64 <= LBA28_LIMIT {
+                self.write(data, first as u32, sectors as u8)?;
+            } else {
+                self.retry(|driver| driver.write_ext_once(data, first, sectors))?;
+            }
+        }
+        Ok(())
+    }
+    /// One try at writing a chunk of `write_sectors` with LBA48's WRITE SECTORS EXT.
+    fn write_ext_once(&mut self, data: &[u16], lba: u64, sector_count: u16) -> Result<(), DiskError> {
+        self.start_command_ext(lba, sector_count, WRITE_EXT_COMMAND)?;
+        let mut data_reg: Port<u16> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
+        for sector in data.chunks(self.sector_words()) {
+            self.wait_bsy();
+            self.wait_drq()?;
+            for word in sector {
+                unsafe { data_reg.write(*word) };
+            }
+        }
+        Ok(())
+    }
+    /// Sends an LBA48 command for `sector_count` sectors from `lba`.
+    ///
+    /// Each register takes two bytes, the high one written first.
+    fn start_command_ext(&mut self, lba: u64, sector_count: u16, command: u8) -> Result<(), DiskError> {
+        if self.bus_floating() {
+            return Err(DiskError::NoDevice);
+        }
+        self.wait_not_busy()?;
+        let mut dsel_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
+        let mut sec_count_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::SectorCountRegister as u16);
+        let mut lba_lo_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBALow as u16);
+        let mut lba_mid_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAMid as u16);
+        let mut lba_high_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAHigh as u16);
+        let mut cmd_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
+
+        let byte = |n: u32| (lba >> (8 * n)) as u8;
+        unsafe {
+            // the high LBA bits go through the registers' second bytes instead
+            dsel_reg.write(drive_select_lba28(self.disk, 0));
+            sec_count_reg.write((sector_count >> 8) as u8);
+            lba_lo_reg.write(byte(3));
+            lba_mid_reg.write(byte(4));
+            lba_high_reg.write(byte(5));
+            sec_count_reg.write(sector_count as u8);
+            lba_lo_reg.write(byte(0));
+            lba_mid_reg.write(byte(1));
+            lba_high_reg.write(byte(2));
+            cmd_reg.write(command);
+        }
+        Ok(())
     }
     pub fn status(&self) -> status::Status { self.status }
+    pub fn read_error(&mut self) -> error::Error {
+        let mut p = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::ErrorRegister as u16);
+        error::Error { val: unsafe { p.read() } }
+    }
     pub fn read_status(&mut self) {
         let mut p = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::StatusRegister as u16);
         self.status = status::Status { val: unsafe { p.read() } };
@@ -233,8 +628,11 @@ impl Driver {
         buf
     }
     */
-    pub fn identify(&mut self) -> [u16; 256] {
-        println!("Identifying device");
+    /// Sends IDENTIFY to the selected drive and works out what, if anything, is there.
+    ///
+    /// Never hangs: every wait gives up after `IDENTIFY_POLL_LIMIT` polls, and a drive that
+    /// sets ERR or DF instead of DRQ is reported rather than waited on.
+    pub fn identify(&mut self) -> Identify {
 
         let mut dh_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
         let mut sec_count_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::SectorCountRegister as u16);
@@ -244,42 +642,234 @@ impl Driver {
         let mut cmd_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
         let mut data_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
 
-        let mut data = [0; 256];
+        if self.bus_floating() {
+            return Identify::NoDrive;
+        }
         unsafe {
-            println!("Writing drive selection and port zeros");
             dh_reg.write(0xA0_u8 | (self.disk << 4) ); //if is_master_drive { 0xA0_u8 } else { 0xB0_u8 }
             sec_count_reg.write(0x0_u8);
             lba_lo_reg.write(0x0_u8);
             lba_mid_reg.write(0x0_u8);
             lba_high_reg.write(0x0_u8);
             
-            println!("Written those, writing command");
             cmd_reg.write(0xEC_u8);
+        }
+        // a status of 0 means there's no drive
+        self.read_status();
+        if self.status.val == 0 {
+            return Identify::NoDrive;
+        }
+        if !self.poll_status(|status| !status.busy()) {
+            klog!(Level::Warn, "disk {} stayed busy after IDENTIFY", self.disk);
+            return Identify::NoDrive;
+        }
+        // ATAPI devices abort IDENTIFY and leave their signature in the LBA registers, possibly
+        // without setting ERR
+        if self.atapi_signature() {
+            return Identify::Atapi;
+        }
+        if !self.poll_status(|status| status.drive_request() || status.error() || status.drive_fault()) {
+            klog!(Level::Warn, "disk {} never answered IDENTIFY", self.disk);
+            return Identify::NoDrive;
+        }
+        // a drive that set ERR or DF instead of DRQ has no data to send: reading the data
+        // register anyway (or waiting on DRQ) is what used to hang here
+        if self.status.error() || self.status.drive_fault() {
+            let err = self.read_error();
+            klog!(Level::Warn, "disk {} failed IDENTIFY, status {:#04x}: {}", self.disk, self.status.val, DiskError::Error(err));
+            return Identify::Error(err);
+        }
+
+        let mut data = [0; 256];
+        for word in data.iter_mut() {
+            *word = unsafe { data_reg.read() };
+        }
+        // bit 15 of word 0 is clear for ATA devices
+        if data[0] & 0x8000 != 0 {
+            return Identify::Atapi;
+        }
+        match DeviceInfo::from_identify(&data) {
+            Some(info) => {
+                self.sector_words[self.drive_index()] = info.sector_words;
+                Identify::Ata(info)
+            }
+            None => Identify::NoDrive,
+        }
+    }
+    /// Reads the status register until `done` accepts it, returning false if that takes more
+    /// than `IDENTIFY_POLL_LIMIT` reads.
+    fn poll_status(&mut self, done: impl Fn(&status::Status) -> bool) -> bool {
+        for _ in 0..IDENTIFY_POLL_LIMIT {
             self.read_status();
-            if self.status.val == 0 {
-                println!("No drive found");
+            if done(&self.status) {
+                return true;
             }
-            else {
-                println!("Written command, waiting");
-                self.wait_bsy();
-                println!("Busy signal low, waiting for drive ready");
-                self.wait_drq();
-                println!("Collecting data");
-                for i in 0..256 {
-                    data[i] = data_reg.read();
-                }
+            core::hint::spin_loop();
+        }
+        false
+    }
+    fn atapi_signature(&mut self) -> bool {
+        let mut lba_mid_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAMid as u16);
+        let mut lba_high_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAHigh as u16);
+        let signature = unsafe { (lba_mid_reg.read(), lba_high_reg.read()) };
+        matches!(signature, (0x14, 0xEB) | (0x69, 0x96))
+    }
+    /// Checks that the selected drive looks like it will take writes: it's there, ready, not
+    /// faulted, and an ATA disk rather than an ATAPI device such as a CD drive.
+    pub fn check_writable(&mut self) -> Result<(), DiskError> {
+        match self.identify() {
+            Identify::NoDrive => return Err(DiskError::NoDevice),
+            Identify::Atapi => return Err(DiskError::ReadOnly),
+            Identify::Error(err) => return Err(DiskError::Error(err)),
+            Identify::Ata(_) => {}
+        }
+        self.read_status();
+        if self.status.drive_fault() {
+            return Err(DiskError::DriveFault);
+        }
+        if !self.status.ready() {
+            return Err(DiskError::NotReady);
+        }
+        Ok(())
+    }
+    /// Asks the selected drive whether it predicts a failure, enabling SMART first.
+    ///
+    /// Like `identify`, gives up after `IDENTIFY_POLL_LIMIT` polls instead of hanging.
+    pub fn smart_status(&mut self) -> SmartStatus {
+        if self.bus_floating() {
+            return SmartStatus::Error(DiskError::NoDevice);
+        }
+        let result = self.smart_command(SMART_ENABLE_OPERATIONS)
+            .and_then(|(_, _)| self.smart_command(SMART_RETURN_STATUS));
+        match result {
+            Ok(SMART_SIGNATURE) => SmartStatus::Ok,
+            Ok(SMART_FAILING_SIGNATURE) => SmartStatus::Failing,
+            Ok((mid, high)) => {
+                klog!(Level::Warn, "disk {} answered SMART RETURN STATUS with {:#04x} {:#04x}", self.disk, mid, high);
+                SmartStatus::Unsupported
             }
+            Err(DiskError::Error(err)) if err.aborted_command() => SmartStatus::Unsupported,
+            Err(err) => SmartStatus::Error(err),
         }
-        println!("Exiting...");
-        return data;
     }
-    pub fn drive_selected(&self) -> Option<Disk> {
-        let mut da_reg = Port::new(BUS_CONTROL_BASES[self.bus as u8 as usize] + 1);
+    /// Sends the SMART subcommand `feature`, which transfers no data, and returns what the
+    /// drive left in LBA mid/high.
+    fn smart_command(&mut self, feature: u8) -> Result<(u8, u8), DiskError> {
+        let mut dh_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
+        let mut features_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::FeaturesRegister as u16);
+        let mut sec_count_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::SectorCountRegister as u16);
+        let mut lba_lo_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBALow as u16);
+        let mut lba_mid_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAMid as u16);
+        let mut lba_high_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAHigh as u16);
+        let mut cmd_reg = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
+
+        if !self.poll_status(|status| !status.busy()) {
+            return Err(DiskError::NotReady);
+        }
+        unsafe {
+            dh_reg.write(0xA0_u8 | (self.disk << 4));
+            features_reg.write(feature);
+            sec_count_reg.write(0_u8);
+            lba_lo_reg.write(0_u8);
+            lba_mid_reg.write(SMART_SIGNATURE.0);
+            lba_high_reg.write(SMART_SIGNATURE.1);
+            cmd_reg.write(SMART_COMMAND);
+        }
+        if !self.poll_status(|status| !status.busy()) {
+            return Err(DiskError::NotReady);
+        }
+        if self.status.error() {
+            return Err(DiskError::Error(self.read_error()));
+        }
+        if self.status.drive_fault() {
+            return Err(DiskError::DriveFault);
+        }
+        Ok(unsafe { (lba_mid_reg.read(), lba_high_reg.read()) })
+    }
+    /// Tells the selected drive to spin down and park its heads right away, so it can lose
+    /// power safely. It spins up again by itself on the next command.
+    pub fn standby(&mut self) -> Result<(), DiskError> {
+        if self.bus_floating() {
+            return Err(DiskError::NoDevice);
+        }
+        self.non_data_command(STANDBY_IMMEDIATE_COMMAND)
+    }
+    /// Ejects the selected drive's media: opens the tray of an ATAPI device such as a CD drive,
+    /// or sends MEDIA EJECT to an ATA drive, which fixed disks abort.
+    pub fn eject(&mut self) -> Result<(), DiskError> {
+        match self.identify() {
+            Identify::NoDrive => Err(DiskError::NoDevice),
+            Identify::Error(err) => Err(DiskError::Error(err)),
+            Identify::Ata(_) => self.non_data_command(MEDIA_EJECT_COMMAND),
+            Identify::Atapi => self.packet_command(&EJECT_PACKET),
+        }
+    }
+    /// Sends a command that moves no data, waiting (up to `IDENTIFY_POLL_LIMIT` polls) for
+    /// the drive to finish it.
+    fn non_data_command(&mut self, command: u8) -> Result<(), DiskError> {
+        let mut dh_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
+        let mut cmd_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
+
+        if !self.poll_status(|status| !status.busy()) {
+            return Err(DiskError::NotReady);
+        }
+        unsafe {
+            dh_reg.write(drive_select_lba28(self.disk, 0));
+            cmd_reg.write(command);
+        }
+        self.finish_command()
+    }
+    /// Sends an ATAPI PACKET command carrying `packet`, for a SCSI command that moves no data.
+    fn packet_command(&mut self, packet: &[u8; 12]) -> Result<(), DiskError> {
+        let mut dh_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DriveSelectRegister as u16);
+        let mut features_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::FeaturesRegister as u16);
+        let mut lba_mid_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAMid as u16);
+        let mut lba_high_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::LBAHigh as u16);
+        let mut cmd_reg: Port<u8> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortWrite::CommandRegister as u16);
+        let mut data_reg: Port<u16> = Port::new(BUS_IO_BASES[self.bus as u8 as usize] + IOPortRead::DataRegister as u16);
+
+        if !self.poll_status(|status| !status.busy()) {
+            return Err(DiskError::NotReady);
+        }
         unsafe {
-            let drive_addr: u8 = da_reg.read();
-            if drive_addr & 0x1 == drive_addr & 0x2 { None }
-            else { Some(drive_addr & 0x3) }
+            dh_reg.write(0xA0_u8 | (self.disk & 1) << 4);
+            // PIO, and no bytes to transfer per DRQ
+            features_reg.write(0_u8);
+            lba_mid_reg.write(0_u8);
+            lba_high_reg.write(0_u8);
+            cmd_reg.write(PACKET_COMMAND);
+        }
+        // the drive asks for the packet with DRQ
+        if !self.poll_status(|status| !status.busy() && (status.drive_request() || status.error())) {
+            return Err(DiskError::NotReady);
+        }
+        if self.status.error() {
+            return Err(DiskError::Error(self.read_error()));
+        }
+        for pair in packet.chunks(2) {
+            unsafe { data_reg.write(u16::from_le_bytes([pair[0], pair[1]])) };
+        }
+        self.finish_command()
+    }
+    /// Waits for the drive to clear BSY after a command, then reports whether it failed.
+    fn finish_command(&mut self) -> Result<(), DiskError> {
+        if !self.poll_status(|status| !status.busy()) {
+            return Err(DiskError::NotReady);
+        }
+        if self.status.error() {
+            return Err(DiskError::Error(self.read_error()));
+        }
+        if self.status.drive_fault() {
+            return Err(DiskError::DriveFault);
         }
+        Ok(())
+    }
+    pub fn disk(&self) -> Disk { self.disk }
+    /// Which drive the bus says is selected, from the drive address register. `None` if the
+    /// register doesn't name exactly one, as on a bus with nothing on it.
+    pub fn drive_selected(&self) -> Option<Disk> {
+        let mut da_reg = Port::new(BUS_CONTROL_BASES[self.bus as u8 as usize] + ControlPortRead::DriveAddressRegister as u16);
+        drive_from_address(unsafe { da_reg.read() })
     }
     pub fn change_disk(&mut self, disk: Disk) {
         self.disk = disk;
@@ -289,22 +879,146 @@ impl Driver {
         }
         self.read_status();
     }
+    /// The selected drive as the bus reports it, or the one this driver last selected if the
+    /// register doesn't say. Every command selects its drive again, so a stale register is
+    /// put right by the next one.
     pub fn current_disk(&self) -> Disk {
-        let mut daddr_reg = Port::new(BUS_CONTROL_BASES[self.bus as u8 as usize] 
-            + ControlPortRead::DriveAddressRegister as u16);
-        unsafe {
-            let addr: u8 = daddr_reg.read();
-            if addr & 1 == 0 { return 0; }
-            else if addr & 2 == 0 { return 1; }
-            else { panic!("Illegal drive address: {}", addr) }
-        }
+        self.drive_selected().unwrap_or(self.disk)
     }
+    pub fn bus(&self) -> Bus { self.bus }
     pub fn change_bus(&mut self, bus: Bus) {
         self.bus = bus;
     }
 }
 
+impl BlockDevice for Driver {
+    fn sector_words(&self) -> usize {
+        Driver::sector_words(self)
+    }
+    fn read_sectors(&mut self, buf: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        Driver::read_sectors(self, buf, lba, count)
+    }
+    fn write_sectors(&mut self, data: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        Driver::write_sectors(self, data, lba, count)
+    }
+}
+
+/// The drive/head register for an LBA28 command: the bits that are always set (7 and 5 are
+/// obsolete but expected, 6 selects LBA mode), the drive in bit 4 and LBA bits 24-27 below.
+fn drive_select_lba28(disk: Disk, lba: u32) -> u8 {
+    0xE0 | (disk & 1) << 4 | (lba >> 24 & 0xF) as u8
+}
+
+/// Decodes the drive address register: bit 0 is clear while drive 0 is selected and bit 1
+/// while drive 1 is, both set or both clear mean neither is.
+fn drive_from_address(value: u8) -> Option<Disk> {
+    match value & 0b11 {
+        0b10 => Some(0),
+        0b01 => Some(1),
+        _ => None,
+    }
+}
+
+/// How many sectors a sector count register value stands for: the drive reads 0 as 256.
+pub fn sectors_in(sector_count: u8) -> usize {
+    match sector_count {
+        0 => 256,
+        n => n as usize,
+    }
+}
+
+/// Splits `count` sectors from `lba` into the commands `read_sectors` sends: the first sector
+/// and sector count of each, at most `MAX_SECTORS_PER_COMMAND` apiece.
+fn command_chunks(lba: u64, count: u32) -> impl Iterator<Item = (u64, u16)> {
+    (0..count).step_by(MAX_SECTORS_PER_COMMAND as usize).map(move |done| {
+        (lba + done as u64, (count - done).min(MAX_SECTORS_PER_COMMAND) as u16)
+    })
+}
+
+/// Puts each ATA disk on the bus in standby, before the power goes, and says how each went.
+pub fn park_disks(driver: &mut Driver) -> Vec<(Disk, Result<(), DiskError>)> {
+    let selected = driver.disk();
+    let mut results = Vec::new();
+    for disk in 0..=1 {
+        driver.change_disk(disk);
+        if matches!(driver.identify(), Identify::Ata(_)) {
+            results.push((disk, driver.standby()));
+        }
+    }
+    driver.change_disk(selected);
+    results
+}
+
 lazy_static! {
+    /// Locked through `irq::lock_driver` and the like, which make sure no transfer is under
+    /// way. When the disk writer is needed too, lock it first, see `keyboard::lock_disk_writer`.
     pub static ref DRIVER: Mutex<Driver> = Mutex::new(Driver::new());
 }
 
+#[test_case]
+fn test_sector_count_zero_means_256() {
+    assert_eq!(sectors_in(0), 256);
+    assert_eq!(sectors_in(1), 1);
+    assert_eq!(sectors_in(255), 255);
+}
+
+#[test_case]
+fn test_command_chunks() {
+    let mut chunks = command_chunks(10, 600);
+    assert_eq!(chunks.next(), Some((10, 256)));
+    assert_eq!(chunks.next(), Some((266, 256)));
+    assert_eq!(chunks.next(), Some((522, 88)));
+    assert_eq!(chunks.next(), None);
+    assert_eq!(command_chunks(LBA28_LIMIT, 256).collect::<alloc::vec::Vec<_>>(), [(LBA28_LIMIT, 256)]);
+    assert_eq!(command_chunks(0, 0).next(), None);
+}
+
+#[test_case]
+fn test_logical_sector_words() {
+    let mut data = [0; 256];
+    assert_eq!(logical_sector_words(&data), DEFAULT_SECTOR_WORDS);
+    // 4096 byte sectors
+    data[106] = 0x5000;
+    data[117] = 2048;
+    assert_eq!(logical_sector_words(&data), 2048);
+    // the same, but word 106 isn't marked valid
+    data[106] = 0xD000;
+    assert_eq!(logical_sector_words(&data), DEFAULT_SECTOR_WORDS);
+}
+
+#[test_case]
+fn test_drive_select_lba28() {
+    assert_eq!(drive_select_lba28(0, 0), 0xE0);
+    assert_eq!(drive_select_lba28(1, 0x0ABC_DEF0), 0xFA);
+    // bits past 27 aren't the register's business
+    assert_eq!(drive_select_lba28(0, 0xF300_0000), 0xE3);
+}
+
+#[test_case]
+fn test_drive_from_address() {
+    // the head select and write gate bits above don't matter
+    assert_eq!(drive_from_address(0xFE), Some(0));
+    assert_eq!(drive_from_address(0xFD), Some(1));
+    assert_eq!(drive_from_address(0xFF), None);
+    assert_eq!(drive_from_address(0xC0), None);
+}
+
+#[test_case]
+fn test_aborted_and_not_ready_are_transient() {
+    let aborted = error::Error { val: 1 << error::Bitflags::ABRT as u8 };
+    assert!(DiskError::Error(aborted).transient().is_some());
+    assert!(DiskError::NotReady.transient().is_some());
+    let unreadable = error::Error { val: aborted.val | 1 << error::Bitflags::UNC as u8 };
+    assert!(DiskError::Error(unreadable).transient().is_none());
+    assert!(DiskError::DriveFault.transient().is_none());
+    assert!(DiskError::GaveUp { retries: 3, error: Transient::Aborted(aborted) }.transient().is_none());
+}
+
+#[test_case]
+fn test_retry_delay_is_capped() {
+    assert_eq!(retry_delay_ms(0), RETRY_BASE_MS);
+    assert_eq!(retry_delay_ms(u8::MAX), retry_delay_ms(MAX_RETRIES_LIMIT));
+    let mut driver = Driver::new();
+    driver.set_max_retries(u8::MAX);
+    assert_eq!(driver.max_retries(), MAX_RETRIES_LIMIT);
+}
@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use spin::Mutex;
+
+use super::{pio::{DiskError, DEFAULT_SECTOR_WORDS}, BlockDevice};
+
+/// The most sectors `RAM` holds, 32 KiB.
+pub const RAM_SECTORS: u64 = 64;
+
+/// Scratch sectors `copy` and `fill` reach with a `ram:` LBA, empty at every boot.
+pub static RAM: Mutex<RamDisk> = Mutex::new(RamDisk::new(RAM_SECTORS));
+
+/// Sectors held on the heap instead of a drive, for scratch data that shouldn't outlive the
+/// boot or touch the disk.
+///
+/// Starts out empty and grows as sectors are written, up to `max_sectors`. Sectors that were
+/// never written read back as zeroes, like a blank disk.
+pub struct RamDisk {
+    words: Vec<u16>,
+    max_sectors: u64,
+}
+
+impl RamDisk {
+    pub const fn new(max_sectors: u64) -> RamDisk {
+        RamDisk { words: Vec::new(), max_sectors }
+    }
+
+    /// How many sectors have been allocated so far, up to the last one written.
+    pub fn sectors(&self) -> u64 {
+        (self.words.len() / DEFAULT_SECTOR_WORDS) as u64
+    }
+
+    /// Where in `words` the `count` sectors from `lba` go, checking that a buffer of `len`
+    /// words holds them and that they end by `max_sectors`.
+    fn word_range(&self, len: usize, lba: u64, count: u32) -> Result<Range<usize>, DiskError> {
+        if len < count as usize * DEFAULT_SECTOR_WORDS {
+            return Err(DiskError::SectorSize(DEFAULT_SECTOR_WORDS * 2));
+        }
+        let end = match lba.checked_add(count as u64) {
+            Some(end) if end <= self.max_sectors => end,
+            _ => return Err(DiskError::OutOfRange),
+        };
+        // a large `max_sectors` can allow LBAs whose word index doesn't fit a usize
+        let to_words = |sectors: u64| usize::try_from(sectors).ok()?.checked_mul(DEFAULT_SECTOR_WORDS);
+        match (to_words(lba), to_words(end)) {
+            (Some(start), Some(end)) => Ok(start..end),
+            _ => Err(DiskError::OutOfRange),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_words(&self) -> usize {
+        DEFAULT_SECTOR_WORDS
+    }
+
+    fn read_sectors(&mut self, buf: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        let range = self.word_range(buf.len(), lba, count)?;
+        for (word, i) in buf.iter_mut().zip(range) {
+            *word = self.words.get(i).copied().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    /// Fails with `DiskError::OutOfRange` past `max_sectors`, and with `DiskError::OutOfMemory`
+    /// if the heap has no room left for the sectors.
+    fn write_sectors(&mut self, data: &mut [u16], lba: u64, count: u32) -> Result<(), DiskError> {
+        let Range { start, end } = self.word_range(data.len(), lba, count)?;
+        if end > self.words.len() {
+            self.words.try_reserve(end - self.words.len()).map_err(|_| DiskError::OutOfMemory)?;
+            self.words.resize(end, 0);
+        }
+        self.words[start..end].copy_from_slice(&data[..end - start]);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_ram_disk_grows_on_write() {
+    let mut disk = RamDisk::new(16);
+    let mut sector = alloc::vec![0xABCD; DEFAULT_SECTOR_WORDS];
+    disk.write_sectors(&mut sector, 3, 1).unwrap();
+    assert_eq!(disk.sectors(), 4);
+
+    let mut buf = alloc::vec![1; 2 * DEFAULT_SECTOR_WORDS];
+    disk.read_sectors(&mut buf, 2, 2).unwrap();
+    assert!(buf[..DEFAULT_SECTOR_WORDS].iter().all(|word| *word == 0));
+    assert!(buf[DEFAULT_SECTOR_WORDS..].iter().all(|word| *word == 0xABCD));
+    // never written, past what's allocated
+    disk.read_sectors(&mut buf, 10, 2).unwrap();
+    assert!(buf.iter().all(|word| *word == 0));
+
+    assert!(matches!(disk.write_sectors(&mut sector, 16, 1), Err(DiskError::OutOfRange)));
+    assert!(matches!(disk.read_sectors(&mut sector, 0, 2), Err(DiskError::SectorSize(512))));
+}
+
+#[test_case]
+fn test_ram_disk_huge_lbas() {
+    let mut disk = RamDisk::new(u64::MAX);
+    let mut sector = alloc::vec![0; DEFAULT_SECTOR_WORDS];
+    // allowed by `max_sectors`, but past any word index
+    assert!(matches!(disk.write_sectors(&mut sector, u64::MAX - 1, 1), Err(DiskError::OutOfRange)));
+    assert!(matches!(disk.read_sectors(&mut sector, u64::MAX - 1, 1), Err(DiskError::OutOfRange)));
+    // indexable, but far more than the heap has
+    assert!(matches!(disk.write_sectors(&mut sector, 1 << 40, 1), Err(DiskError::OutOfMemory)));
+    assert_eq!(disk.sectors(), 0);
+}
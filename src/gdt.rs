@@ -5,12 +5,27 @@ use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const GEN_PROT_FAULT_IST_INDEX: u16 = 1;
+pub const PAGE_FAULT_IST_INDEX: u16 = 2;
+
+/// Size of the stack the double fault handler switches to.
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+/// Size of the stack the page fault handler switches to. The handler needs its own stack
+/// so it can still run (and report) when the kernel stack overflows into its guard page.
+pub const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
+            const STACK_SIZE: usize = DOUBLE_FAULT_STACK_SIZE;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+            let stack_end = stack_start + STACK_SIZE;
+            stack_end
+        };
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = PAGE_FAULT_STACK_SIZE;
             static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
             let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
@@ -0,0 +1,59 @@
+/// The reflected CRC-32 polynomial used by zip, PNG and Ethernet.
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+// the CRC of every byte value, so each byte takes one lookup instead of eight shifts
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+/// A CRC-32 worked out a piece at a time, for data that doesn't fit in memory at once.
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { state: !0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = self.state >> 8 ^ CRC32_TABLE[(self.state as u8 ^ byte) as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// The CRC-32 of `bytes`, the same as `crc32` on Linux or zip's.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[test_case]
+fn test_crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+    let mut pieces = Crc32::new();
+    pieces.update(b"1234");
+    pieces.update(b"56789");
+    assert_eq!(pieces.finish(), 0xCBF4_3926);
+}
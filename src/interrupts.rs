@@ -1,4 +1,4 @@
-use crate::{gdt, hlt_loop, print, println, time::TIMER};
+use crate::{gdt, hlt_loop, memory, print, println, swap, time};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
@@ -45,7 +45,11 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         //unsafe {
         //    idt.general_protection_fault
         //    .set_handler_fn(general_protection_handler);
@@ -79,6 +83,57 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Clears the PIC mask bit for the given IRQ line (0-15) so it gets delivered.
+pub fn unmask_irq(irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let (mut data_port, bit): (Port<u8>, u8) =
+        if irq < 8 { (Port::new(0x21), irq) } else { (Port::new(0xA1), irq - 8) };
+    unsafe {
+        let mask = data_port.read();
+        data_port.write(mask & !(1 << bit));
+    }
+}
+
+// the command ports of the two PICs, and what they take to answer with the in-service register
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+const PIC_READ_ISR: u8 = 0x0B;
+const PIC_EOI: u8 = 0x20;
+// the lowest priority line on each PIC, where it reports an IRQ that went away before it was acked
+const SPURIOUS_MASTER_IRQ: u8 = 7;
+const SPURIOUS_SLAVE_IRQ: u8 = 15;
+
+/// Whether the PIC is actually servicing the given IRQ line (0-15), read from its in-service
+/// register.
+fn irq_in_service(irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+
+    let (mut command_port, bit): (Port<u8>, u8) = if irq < 8 {
+        (Port::new(PIC_1_COMMAND), irq)
+    } else {
+        (Port::new(PIC_2_COMMAND), irq - 8)
+    };
+    unsafe {
+        command_port.write(PIC_READ_ISR);
+        command_port.read() & (1 << bit) != 0
+    }
+}
+
+/// Handles a spurious IRQ 7. The master never set its in-service bit for it, so it gets no EOI.
+fn spurious_master() {}
+
+/// Handles a spurious IRQ 15. The slave never set its in-service bit, but the master did for the
+/// cascade line, so only the master gets an EOI.
+fn spurious_slave() {
+    use x86_64::instructions::port::Port;
+
+    let mut command_port: Port<u8> = Port::new(PIC_1_COMMAND);
+    unsafe {
+        command_port.write(PIC_EOI);
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
@@ -89,8 +144,22 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    let accessed = Cr2::read();
+    if memory::is_stack_guard_page(accessed) {
+        println!("EXCEPTION: STACK OVERFLOW");
+        println!("Accessed Address: {:?}", accessed);
+        println!("{:#?}", stack_frame);
+        hlt_loop();
+    }
+    if swap::in_area(accessed) && !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        match swap::handle_page_fault(accessed) {
+            Ok(()) => return,
+            Err(e) => println!("Couldn't swap in the page: {}", e),
+        }
+    }
+
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {:?}", accessed);
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
     hlt_loop();
@@ -112,7 +181,12 @@ extern "x86-interrupt" fn general_protection_handler(stack_frame: InterruptStack
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
     x86_64::instructions::interrupts::without_interrupts(||{
-        *TIMER.lock() += 1;
+        let now = time::tick();
+        if now % crate::task::TIME_SLICE_TICKS == 0 {
+            crate::task::end_time_slice();
+        }
+        crate::task::wake_sleepers(now);
+        crate::vga_buffer::flush_pending_output();
     });
     unsafe {
         PICS.lock()
@@ -135,6 +209,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 
 extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
     //x86_64::instructions::interrupts::without_interrupts(|| { println!("Primary ATA Interrupt") } );
+    crate::disk::irq::notify(crate::disk::pio::Bus::Primary);
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
@@ -142,7 +217,12 @@ extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptS
 }
 
 extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if !irq_in_service(SPURIOUS_SLAVE_IRQ) {
+        spurious_slave();
+        return;
+    }
     //x86_64::instructions::interrupts::without_interrupts(|| { println!("Secondary ATA Interrupt") } );
+    crate::disk::irq::notify(crate::disk::pio::Bus::Secondary);
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
@@ -158,7 +238,10 @@ extern "x86-interrupt" fn pic2_interrupt_handler(_stack_frame: InterruptStackFra
 }
 
 extern "x86-interrupt" fn serial1_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    x86_64::instructions::interrupts::without_interrupts(|| { println!("Serial 1 Interrupt") } );
+    // drain the receive buffer, the UART may have several bytes queued up
+    while let Some(byte) = crate::serial::try_receive() {
+        crate::task::serial_input::add_byte(byte);
+    }
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Serial1.as_u8());
@@ -190,6 +273,10 @@ extern "x86-interrupt" fn floppy_interrupt_handler(_stack_frame: InterruptStackF
 }
 
 extern "x86-interrupt" fn parallel_port1_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if !irq_in_service(SPURIOUS_MASTER_IRQ) {
+        spurious_master();
+        return;
+    }
     x86_64::instructions::interrupts::without_interrupts(|| { println!("Parallel Port 1 Interrupt") } );
     unsafe {
         PICS.lock()
@@ -250,3 +337,10 @@ fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_spurious_lines_not_in_service_outside_handlers() {
+    // nothing is being serviced while a test runs, so both lines would be taken as spurious
+    assert!(!irq_in_service(SPURIOUS_MASTER_IRQ));
+    assert!(!irq_in_service(SPURIOUS_SLAVE_IRQ));
+}
@@ -0,0 +1,170 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+
+use crate::{collections::RingBuffer, time::read_timer, vga_buffer::{self, Color}};
+
+/// How many entries the kernel log keeps before dropping the oldest.
+pub const LOG_CAPACITY: usize = 128;
+/// Longer messages are cut off when stored, they're still printed in full.
+pub const MAX_MESSAGE_LEN: usize = 96;
+
+/// How important a log message is, most important first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 0,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn from_u8(n: u8) -> Option<Level> {
+        match n {
+            0 => Some(Level::Error),
+            1 => Some(Level::Warn),
+            2 => Some(Level::Info),
+            3 => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    /// Parses a level given either as its number or its name, in any case.
+    pub fn parse(s: &str) -> Option<Level> {
+        if let Ok(n) = s.parse::<u8>() {
+            return Level::from_u8(n);
+        }
+        [Level::Error, Level::Warn, Level::Info, Level::Debug].into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(s))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    fn color(&self) -> Option<Color> {
+        match self {
+            Level::Error => Some(Color::LightRed),
+            Level::Warn => Some(Color::Yellow),
+            _ => None,
+        }
+    }
+}
+
+/// One stored log message.
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub level: Level,
+    /// Timer ticks when it was logged.
+    pub ticks: u128,
+    text: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry { level: Level::Debug, ticks: 0, text: [0; MAX_MESSAGE_LEN], len: 0 };
+
+    pub fn message(&self) -> &str {
+        // only whole characters are ever copied in, see `write_str`
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Entry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len + c.len_utf8() > MAX_MESSAGE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.text[self.len..]);
+            self.len += c.len_utf8();
+        }
+        Ok(())
+    }
+}
+
+static THRESHOLD: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// fixed size so logging works before the heap is up
+static LOG: Mutex<RingBuffer<Entry, LOG_CAPACITY>> = Mutex::new(RingBuffer::new());
+
+/// The least important level that still gets logged.
+pub fn threshold() -> Level {
+    Level::from_u8(THRESHOLD.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+}
+
+pub fn set_threshold(level: Level) {
+    THRESHOLD.store(level as u8, Ordering::Relaxed);
+}
+
+/// Calls `f` with every stored entry at `level` or more important, oldest first.
+pub fn for_each(level: Level, mut f: impl FnMut(&Entry)) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for entry in LOG.lock().iter().filter(|entry| entry.level <= level) {
+            f(entry);
+        }
+    });
+}
+
+/// Stores a message for `dmesg` without printing it, whatever the threshold.
+///
+/// For records the user asked for, such as `trace`, that would only clutter the screen.
+pub fn record(level: Level, args: fmt::Arguments) {
+    let mut entry = Entry { level, ticks: read_timer(), ..Entry::EMPTY };
+    let _ = entry.write_fmt(args);
+    x86_64::instructions::interrupts::without_interrupts(|| { LOG.lock().push(entry); });
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    if level > threshold() {
+        return;
+    }
+    record(level, args);
+
+    match level.color() {
+        Some(color) => vga_buffer::print_colored(color, format_args!("[{}] {}\n", level.name(), args)),
+        None => crate::println!("[{}] {}", level.name(), args),
+    }
+}
+
+/// Logs a message at the given `klog::Level`, storing it for `dmesg` and printing it.
+///
+/// Messages less important than the threshold set with `loglevel` are dropped.
+#[macro_export]
+macro_rules! klog {
+    ($level:expr, $($arg:tt)*) => ($crate::klog::_log($level, format_args!($($arg)*)));
+}
+
+#[test_case]
+fn test_level_parse() {
+    assert_eq!(Level::parse("0"), Some(Level::Error));
+    assert_eq!(Level::parse("WARN"), Some(Level::Warn));
+    assert_eq!(Level::parse("debug"), Some(Level::Debug));
+    assert_eq!(Level::parse("4"), None);
+}
+
+#[test_case]
+fn test_entry_truncates() {
+    let mut entry = Entry::EMPTY;
+    for _ in 0..MAX_MESSAGE_LEN {
+        write!(entry, "ab").unwrap();
+    }
+    assert_eq!(entry.message().len(), MAX_MESSAGE_LEN);
+}
+
+#[test_case]
+fn test_record_ignores_threshold() {
+    let old = threshold();
+    set_threshold(Level::Error);
+    record(Level::Debug, format_args!("test_record_ignores_threshold"));
+    set_threshold(old);
+    let mut found = false;
+    for_each(Level::Debug, |entry| found |= entry.message() == "test_record_ignores_threshold");
+    assert!(found);
+}
@@ -12,41 +12,73 @@
 extern crate alloc;
 use core::{panic::PanicInfo, fmt::Write};
 
-use crate::{vga_buffer::WRITER, task::keyboard::DISK_WRITER};
+use crate::vga_buffer::WRITER;
 
 pub mod allocator;
+pub mod collections;
+pub mod cp437;
+pub mod cpu;
 pub mod disk;
+pub mod framebuffer;
 pub mod gdt;
+pub mod hash;
 pub mod interrupts;
+pub mod klog;
 pub mod memory;
+pub mod ps2;
+pub mod rtc;
 pub mod serial;
+pub mod sink;
+pub mod speaker;
+pub mod swap;
+pub mod table;
 pub mod task;
 pub mod time;
 pub mod vga_buffer;
 
 pub fn init() {
+    init_arch();
+    println!();
+    print!("$> ");
+    vga_buffer::reset_cmd_start();
+}
+
+/// Sets up the GDT, the interrupts, the timer, the serial port and the keyboard controller,
+/// then enables interrupts. Nothing here needs the heap.
+pub fn init_arch() {
     disk::pio::DRIVER.lock().change_disk(1);
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
-    unsafe { DISK_WRITER.lock().init() }
-    println!();
-    print!("$> ");
-    WRITER.lock().reset_cmd_start();
+    time::init();
+    klog!(klog::Level::Debug, "PIT running at {} Hz", time::TICKS_PER_SECOND);
+    serial::init();
+    ps2::init();
     x86_64::instructions::interrupts::enable();
 }
+
+/// Finds where the text on the data disk ends, once the heap is up: the drive's IDENTIFY data
+/// is read into a `String`.
+pub fn init_disks() {
+    unsafe { task::keyboard::lock_disk_writer().init() }
+}
 pub trait Testable {
     fn run(&self) -> ();
 }
 
+// the test that's running, so the panic handler can say which one failed
+static CURRENT_TEST: spin::Mutex<&str> = spin::Mutex::new("");
+
 impl<T> Testable for T
 where
     T: Fn(),
 {
     fn run(&self) {
-        serial_print!("{}...\t", core::any::type_name::<T>());
+        let name = core::any::type_name::<T>();
+        *CURRENT_TEST.lock() = name;
+        serial_println!("test {} ...", name);
         self();
-        serial_println!("[ok]");
+        serial_println!("PASS {}", name);
     }
 }
 
@@ -55,16 +87,50 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     for test in tests {
         test.run();
     }
+    serial_println!("All {} tests passed", tests.len());
     exit_qemu(QemuExitCode::Success);
 }
 
+/// Reports the failed test by name, with the panic message (which for `assert_eq!` and the
+/// macros below includes what was expected and what came out), and exits QEMU with
+/// `QemuExitCode::Failed`.
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
+    // a failing test may have held it, and it's not worth hanging over
+    let name = CURRENT_TEST.try_lock().map_or("", |name| *name);
+    serial_println!("FAIL {}", name);
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
+/// Asserts that a `Result` is `Ok`, evaluating to the value inside.
+///
+/// Unlike `unwrap`, a failure names the expression along with the error, for tests.
+#[macro_export]
+macro_rules! assert_ok {
+    ($result:expr) => {
+        match $result {
+            ::core::result::Result::Ok(value) => value,
+            ::core::result::Result::Err(e) => {
+                panic!("assertion failed: expected `{}` to be Ok, got Err({:?})", stringify!($result), e)
+            }
+        }
+    };
+}
+
+/// Asserts that a `Result` is `Err`, evaluating to the error inside.
+#[macro_export]
+macro_rules! assert_err {
+    ($result:expr) => {
+        match $result {
+            ::core::result::Result::Err(e) => e,
+            ::core::result::Result::Ok(value) => {
+                panic!("assertion failed: expected `{}` to be Err, got Ok({:?})", stringify!($result), value)
+            }
+        }
+    };
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -81,23 +147,46 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     }
 }
 
+/// Turns the machine off through the ACPI ports QEMU and Bochs have, without parsing the
+/// ACPI tables. Elsewhere nothing answers, so it halts with interrupts off instead.
+pub fn power_off() -> ! {
+    use x86_64::instructions::port::Port;
+
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        // QEMU's PIIX4 and ICH9 machines, then older QEMU and Bochs
+        Port::new(0x604).write(0x2000_u16);
+        Port::new(0xB004).write(0x2000_u16);
+    }
+    println!("It's now safe to turn off the computer.");
+    hlt_loop();
+}
+
 pub fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
     }
 }
 
-/* 
 #[cfg(test)]
 use bootloader::{entry_point, BootInfo};
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
 
-/// Entry point for `cargo xtest`
+/// Entry point for `cargo test`
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
     init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    init_disks();
+
     test_main();
     hlt_loop();
 }
@@ -108,8 +197,16 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
+/// Reports running out of heap and stops, instead of a panic that doesn't say what happened.
+///
+/// The allocation can fail while the screen is locked (`snapshot` allocates with it held), so
+/// the screen is only written to if it's free; serial always gets the message.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("allocation error: {:?}", layout)
+    x86_64::instructions::interrupts::disable();
+    serial_println!("out of memory: couldn't allocate {} bytes (align {})", layout.size(), layout.align());
+    if let Some(mut writer) = WRITER.try_lock() {
+        let _ = writeln!(writer, "\nout of memory: couldn't allocate {} bytes, halting", layout.size());
+    }
+    hlt_loop();
 }
-*/
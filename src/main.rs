@@ -9,32 +9,111 @@ extern crate alloc;
 use rust_os::disk::pio::DRIVER;
 use rust_os::task::cli::dclear;
 use rust_os::task::keyboard::text_editor;
-use rust_os::{println, disk, print, hlt_loop};
+use rust_os::{allocator, klog, klog::Level, memory::{self, BootInfoFrameAllocator}, println, disk, print, hlt_loop};
+use rust_os::time::{read_timer, ticks_since, ticks_to_ms};
 use rust_os::task::{executor::Executor, keyboard, Task, cli};
 use bootloader::{entry_point, BootInfo};
 use x86_64::instructions::port::{Port, PortGeneric, ReadWriteAccess};
+use x86_64::structures::paging::{mapper::MapToError, OffsetPageTable, Size4KiB};
+use x86_64::VirtAddr;
 use core::panic::PanicInfo;
 
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
-    use rust_os::allocator;
-    use rust_os::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
+    let start = read_timer();
+    boot_phase("init_arch", init_arch);
+    let (mut mapper, mut frame_allocator) = boot_phase("init_memory", || init_memory(boot_info));
+    boot_phase("init_heap", || init_heap(&mut mapper, &mut frame_allocator));
+    boot_phase("probe_disks", probe_disks);
+    let mut executor = boot_phase("init_tasks", init_tasks);
+    klog!(Level::Info, "booted in {} ms", ticks_to_ms(ticks_since(start)));
+    executor.run();
+}
 
-    rust_os::init();
+/// Why a boot phase couldn't finish.
+#[derive(Debug)]
+enum BootError {
+    Heap(MapToError<Size4KiB>),
+}
+
+/// Runs one phase of the boot, logging when it starts and how long it took. If it fails, says
+/// which phase it was and halts, since the phases after it build on it.
+fn boot_phase<T>(name: &str, phase: impl FnOnce() -> Result<T, BootError>) -> T {
+    klog!(Level::Debug, "boot: {}", name);
+    let start = read_timer();
+    match phase() {
+        Ok(value) => {
+            klog!(Level::Debug, "boot: {} done in {} ms", name, ticks_to_ms(ticks_since(start)));
+            value
+        }
+        Err(e) => {
+            klog!(Level::Error, "boot: {} failed: {:?}", name, e);
+            println!("Boot failed in {}: {:?}", name, e);
+            hlt_loop();
+        }
+    }
+}
 
+/// The CPU tables, interrupts and the devices that don't need memory set up.
+fn init_arch() -> Result<(), BootError> {
+    rust_os::init_arch();
+    Ok(())
+}
+
+/// Takes over the bootloader's page tables and memory map, and sets up the framebuffer if
+/// there is one, which is reached through the physical memory mapping.
+fn init_memory(boot_info: &'static BootInfo) -> Result<(OffsetPageTable<'static>, BootInfoFrameAllocator), BootError> {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    // the text buffer is the default; the bootloader only sets up a framebuffer
+    // (mode 13h at 0xa0000) when built with the `framebuffer` feature
+    #[cfg(feature = "framebuffer")]
+    rust_os::framebuffer::init(rust_os::framebuffer::FramebufferInfo {
+        address: phys_mem_offset.as_u64() + 0xa0000,
+        width: 320,
+        height: 200,
+        stride: 320,
+        bytes_per_pixel: 1,
+        format: rust_os::framebuffer::PixelFormat::Indexed,
+    });
+    Ok((mapper, frame_allocator))
+}
+
+/// Maps the heap, and the swap area if there's room; the kernel does without swap.
+fn init_heap(mapper: &mut OffsetPageTable<'static>, frame_allocator: &mut BootInfoFrameAllocator) -> Result<(), BootError> {
+    allocator::init_heap(mapper, frame_allocator).map_err(BootError::Heap)?;
+    if let Err(e) = rust_os::swap::init(mapper, frame_allocator) {
+        klog!(Level::Error, "swap area not set up: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Identifies the data disk and finds the end of its text.
+fn probe_disks() -> Result<(), BootError> {
+    rust_os::init_disks();
+    Ok(())
+}
+
+/// Shows the prompt and spawns the terminal and the background tasks.
+fn init_tasks() -> Result<Executor, BootError> {
+    println!();
+    print!("$> ");
+    rust_os::vga_buffer::reset_cmd_start();
 
     //println!("Anything you type will be forwarded to the disk");
 
     let mut executor = Executor::new();
-    executor.spawn(Task::new(cli::cli()));
-    executor.run();
+    #[cfg(feature = "boot-pause")]
+    executor.spawn(Task::new(cli_after_key(executor.spawner())));
+    #[cfg(not(feature = "boot-pause"))]
+    executor.spawn(Task::new(cli::cli(executor.spawner())));
+    executor.spawn(Task::new(rust_os::task::clock::clock()));
+    executor.spawn(Task::new(rust_os::task::autopoweroff::autopoweroff()));
+    executor.spawn(Task::new(keyboard::report_dropped_scancodes()));
+    Ok(executor)
 }
 
 /// This function is called on panic.
@@ -51,6 +130,20 @@ fn panic(info: &PanicInfo) -> ! {
     rust_os::test_panic_handler(info)
 }
 
+/// Keeps the boot messages on the screen until a key is pressed, then runs the terminal.
+#[cfg(feature = "boot-pause")]
+async fn cli_after_key(spawner: rust_os::task::executor::Spawner) {
+    use futures_util::stream::StreamExt;
+
+    println!("\nPress any key to continue");
+    // dropped before the terminal takes the keyboard, the key's release goes to the terminal
+    // and is ignored there
+    keyboard::ScancodeStream::new().next().await;
+    print!("$> ");
+    rust_os::vga_buffer::reset_cmd_start();
+    cli::cli(spawner).await
+}
+
 async fn async_number() -> u32 {
     42
 }
@@ -1,9 +1,25 @@
+use alloc::vec::Vec;
+use core::ops::Range;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
 use x86_64::{
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    registers::control::Cr3,
+    structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PageTableIndex, PhysFrame, Size4KiB},
     PhysAddr, VirtAddr,
 };
 
+/// Where the bootloader places the kernel stack, must match `kernel-stack-address` in Cargo.toml.
+pub const KERNEL_STACK_START: u64 = 0x_5555_5555_0000;
+/// Size of the kernel stack in pages (including the guard page), must match `kernel-stack-size`
+/// in Cargo.toml.
+pub const KERNEL_STACK_PAGES: u64 = 512;
+/// Physical addresses of the legacy VGA memory and the BIOS ROMs, which are devices rather than
+/// RAM whatever the memory map says.
+pub const LEGACY_MMIO: Range<u64> = 0xA_0000..0x10_0000;
+
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+static MEMORY_MAP: OnceCell<&'static MemoryMap> = OnceCell::uninit();
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -11,8 +27,134 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let _ = PHYSICAL_MEMORY_OFFSET.try_init_once(|| physical_memory_offset);
     let level_4_table = active_level_4_table(physical_memory_offset);
-    OffsetPageTable::new(level_4_table, physical_memory_offset)
+    let mut mapper = OffsetPageTable::new(level_4_table, physical_memory_offset);
+    // the bootloader should already leave the guard page unmapped, but make sure of it
+    if let Ok((_frame, flush)) = mapper.unmap(stack_guard_page()) {
+        flush.flush();
+    }
+    mapper
+}
+
+/// Where all of physical memory is mapped, once `init` has run.
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.try_get().ok().copied()
+}
+
+/// Translates a physical address through the bootloader's complete physical memory mapping.
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    physical_memory_offset().map(|offset| offset + addr.as_u64())
+}
+
+/// Total bytes of usable RAM according to the bootloader's memory map, once
+/// `BootInfoFrameAllocator::init` has run.
+pub fn usable_memory() -> Option<u64> {
+    let memory_map = MEMORY_MAP.try_get().ok()?;
+    Some(memory_map.iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| r.range.end_addr() - r.range.start_addr())
+        .sum())
+}
+
+/// The lowest page of the kernel stack, kept unmapped so that overflowing the stack
+/// page faults instead of silently running into whatever is below it.
+pub fn stack_guard_page() -> Page<Size4KiB> {
+    Page::containing_address(VirtAddr::new(KERNEL_STACK_START))
+}
+
+/// Returns whether `addr` lies in the kernel stack's guard page.
+pub fn is_stack_guard_page(addr: VirtAddr) -> bool {
+    Page::<Size4KiB>::containing_address(addr) == stack_guard_page()
+}
+
+/// The entry an address goes through at one level of the page tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelEntry {
+    /// 4 for the top level table, down to 1 for the table that maps 4 KiB pages.
+    pub level: u8,
+    pub index: u16,
+    pub flags: PageTableFlags,
+}
+
+/// What `translate` found for an address.
+pub struct Translation {
+    /// Each level's entry, from level 4 down to the one that mapped the address or wasn't
+    /// present.
+    pub entries: Vec<LevelEntry>,
+    /// `None` if the address isn't mapped.
+    pub phys: Option<PhysAddr>,
+}
+
+/// Walks the active page tables for `addr` the way the MMU does, huge pages included.
+///
+/// Goes through the tables by hand, like the swap code, since the mapper belongs to
+/// `kernel_main`. Returns `None` before `init` has run.
+pub fn translate(addr: VirtAddr) -> Option<Translation> {
+    let (level_4_frame, _) = Cr3::read();
+    let indexes: [PageTableIndex; 4] = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    let mut table_addr = level_4_frame.start_address();
+    let mut entries = Vec::with_capacity(4);
+    for (level, index) in (1..=4u8).rev().zip(indexes) {
+        let table: &PageTable = unsafe { &*phys_to_virt(table_addr)?.as_ptr() };
+        let entry = &table[index];
+        entries.push(LevelEntry { level, index: u16::from(index), flags: entry.flags() });
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return Some(Translation { entries, phys: None });
+        }
+        // a huge page at level 3 or 2 maps the rest of the address itself
+        if level == 1 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let page_size = 1u64 << (12 + 9 * (level as u64 - 1));
+            let phys = entry.addr() + (addr.as_u64() & (page_size - 1));
+            return Some(Translation { entries, phys: Some(phys) });
+        }
+        table_addr = entry.addr();
+    }
+    unreachable!("level 1 always ends the walk")
+}
+
+/// Whether writing to `addr` could bring the kernel down: it isn't mapped, or it's the kernel
+/// image or stack, a page table or something other than RAM.
+///
+/// Goes by the physical address, so the same memory reached through the physical memory
+/// mapping is caught too. Only RAM the bootloader's memory map calls usable is safe, which is
+/// where the heap and the swap area live, minus the tables mapped into it since. Everything is
+/// dangerous before `init` and `BootInfoFrameAllocator::init` have run.
+pub fn is_dangerous_addr(addr: u64) -> bool {
+    let phys = match VirtAddr::try_new(addr).ok().and_then(translate).and_then(|t| t.phys) {
+        Some(phys) => phys.as_u64(),
+        None => return true,
+    };
+    let memory_map = match MEMORY_MAP.try_get() {
+        Ok(memory_map) => memory_map,
+        Err(_) => return true,
+    };
+    let usable = memory_map.iter().any(|r| {
+        r.region_type == MemoryRegionType::Usable && (r.range.start_addr()..r.range.end_addr()).contains(&phys)
+    });
+    !usable || LEGACY_MMIO.contains(&phys) || is_page_table_frame(PhysFrame::containing_address(PhysAddr::new(phys)))
+}
+
+/// Whether `frame` holds one of the active page tables, at any level.
+fn is_page_table_frame(frame: PhysFrame) -> bool {
+    fn walk(table_addr: PhysAddr, level: u8, frame: PhysFrame) -> bool {
+        if PhysFrame::containing_address(table_addr) == frame {
+            return true;
+        }
+        if level == 1 {
+            return false;
+        }
+        let table: &PageTable = match phys_to_virt(table_addr) {
+            Some(virt) => unsafe { &*virt.as_ptr() },
+            None => return false,
+        };
+        // a huge page maps memory, not another table
+        table.iter().any(|entry| {
+            entry.flags().contains(PageTableFlags::PRESENT) && !entry.flags().contains(PageTableFlags::HUGE_PAGE)
+                && walk(entry.addr(), level - 1, frame)
+        })
+    }
+    walk(Cr3::read().0.start_address(), 4, frame)
 }
 
 /// Returns a mutable reference to the active level 4 table.
@@ -22,8 +164,6 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
     let (level_4_table_frame, _) = Cr3::read();
 
     let phys = level_4_table_frame.start_address();
@@ -55,6 +195,7 @@ impl BootInfoFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let _ = MEMORY_MAP.try_init_once(|| memory_map);
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
@@ -82,3 +223,28 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         frame
     }
 }
+
+#[test_case]
+fn test_translate_follows_the_kernel_mapping() {
+    static VALUE: u64 = 0x1234_5678_9abc_def0;
+    let addr = VirtAddr::from_ptr(&VALUE);
+    let translation = translate(addr).unwrap();
+    assert_eq!(translation.entries[0].level, 4);
+    // read back through the physical memory mapping
+    let through_phys = phys_to_virt(translation.phys.unwrap()).unwrap();
+    assert_eq!(unsafe { *through_phys.as_ptr::<u64>() }, VALUE);
+
+    let guard = translate(stack_guard_page().start_address()).unwrap();
+    assert_eq!(guard.phys, None);
+}
+
+#[test_case]
+fn test_dangerous_addresses() {
+    static IN_KERNEL: u64 = 0;
+    assert!(is_dangerous_addr(&IN_KERNEL as *const u64 as u64));
+    let level_4_table = phys_to_virt(Cr3::read().0.start_address()).unwrap();
+    assert!(is_dangerous_addr(level_4_table.as_u64()));
+    assert!(is_dangerous_addr(stack_guard_page().start_address().as_u64()));
+    let on_heap = alloc::boxed::Box::new(0u64);
+    assert!(!is_dangerous_addr(&*on_heap as *const u64 as u64));
+}
@@ -0,0 +1,209 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{klog, klog::Level};
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, commands when written.
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const CONFIG_PORT1_INTERRUPT: u8 = 1 << 0;
+const CONFIG_PORT2_INTERRUPT: u8 = 1 << 1;
+// the keyboard decoder expects scancode set 1, which the controller translates to
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// How many times to poll the status register before giving up on the controller.
+const POLL_LIMIT: u32 = 100_000;
+
+/// What the keyboard answers a command with when it took it.
+pub const RESPONSE_ACK: u8 = 0xFA;
+/// What the keyboard answers a command with when it wants it sent again.
+pub const RESPONSE_RESEND: u8 = 0xFE;
+/// How many times a byte is sent again when the keyboard asks for it.
+const MAX_RESENDS: u32 = 3;
+
+// the last answer the keyboard interrupt handed over, 0 while there's none
+static RESPONSE: AtomicU8 = AtomicU8::new(0);
+
+/// Bits of the byte `set_leds` takes.
+pub const LED_SCROLL_LOCK: u8 = 1 << 0;
+pub const LED_NUM_LOCK: u8 = 1 << 1;
+pub const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Why the PS/2 controller or keyboard didn't come up.
+#[derive(Debug, Clone, Copy)]
+pub enum Ps2Error {
+    /// The controller never answered.
+    Timeout,
+    /// The controller self-test returned this instead of 0x55.
+    SelfTest(u8),
+    /// The first port's test returned this instead of 0x00.
+    PortTest(u8),
+    /// The keyboard answered a command with this instead of ACK.
+    Keyboard(u8),
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ps2Error::Timeout => write!(f, "no response from the PS/2 controller"),
+            Ps2Error::SelfTest(got) => write!(f, "PS/2 controller self-test failed ({:#04x})", got),
+            Ps2Error::PortTest(got) => write!(f, "PS/2 port 1 test failed ({:#04x})", got),
+            Ps2Error::Keyboard(got) => write!(f, "keyboard didn't acknowledge ({:#04x})", got),
+        }
+    }
+}
+
+/// Sets up the PS/2 controller and keyboard instead of trusting whatever the BIOS left.
+///
+/// Best effort: every wait gives up after `POLL_LIMIT` polls, and failures are only logged, so
+/// odd hardware (or none at all) can't hang the boot.
+pub fn init() {
+    match init_controller() {
+        Ok(()) => klog!(Level::Debug, "PS/2 controller and keyboard ready"),
+        Err(e) => klog!(Level::Error, "{}, keyboard input may not work", e),
+    }
+}
+
+fn init_controller() -> Result<(), Ps2Error> {
+    // disable both ports so nothing gets in the way of the tests
+    send_command(0xAD)?;
+    send_command(0xA7)?;
+    flush_output();
+
+    send_command(0x20)?;
+    let config = read_data()?;
+    let tested = self_test(config);
+
+    // turn port 1 back on whatever the tests said, the keyboard might work anyway
+    send_command(0xAE)?;
+    write_config((config | CONFIG_PORT1_INTERRUPT | CONFIG_TRANSLATION) & !CONFIG_PORT2_INTERRUPT)?;
+    tested?;
+
+    // enable scanning
+    write_data(0xF4)?;
+    match read_data()? {
+        0xFA => Ok(()),
+        got => Err(Ps2Error::Keyboard(got)),
+    }
+}
+
+/// Turns the keyboard LEDs in `leds` on and the others off.
+pub fn set_leds(leds: u8) -> Result<(), Ps2Error> {
+    send_to_keyboard(0xED)?;
+    send_to_keyboard(leds & (LED_SCROLL_LOCK | LED_NUM_LOCK | LED_CAPS_LOCK))
+}
+
+/// Called by the keyboard interrupt handler with an ACK or resend byte, which answers a
+/// command sent by `send_to_keyboard` rather than being a key.
+///
+/// Must not block or allocate.
+pub(crate) fn record_response(response: u8) {
+    RESPONSE.store(response, Ordering::Release);
+}
+
+/// Sends a command or data byte to the keyboard and waits for its ACK, sending the byte again
+/// (up to `MAX_RESENDS` times) when the keyboard asks for that.
+fn send_to_keyboard(byte: u8) -> Result<(), Ps2Error> {
+    let mut response = RESPONSE_RESEND;
+    for _ in 0..=MAX_RESENDS {
+        RESPONSE.store(0, Ordering::Release);
+        write_data(byte)?;
+        response = read_response()?;
+        if response != RESPONSE_RESEND {
+            break;
+        }
+    }
+    match response {
+        RESPONSE_ACK => Ok(()),
+        got => Err(Ps2Error::Keyboard(got)),
+    }
+}
+
+fn read_response() -> Result<u8, Ps2Error> {
+    // the interrupt handler can't run and take the answer, so it's still in the data port
+    if !interrupts::are_enabled() {
+        return read_data();
+    }
+    for _ in 0..POLL_LIMIT {
+        match RESPONSE.swap(0, Ordering::Acquire) {
+            0 => core::hint::spin_loop(),
+            response => return Ok(response),
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn self_test(config: u8) -> Result<(), Ps2Error> {
+    let quiet = config & !(CONFIG_PORT1_INTERRUPT | CONFIG_PORT2_INTERRUPT);
+    write_config(quiet)?;
+
+    send_command(0xAA)?;
+    match read_data()? {
+        0x55 => {}
+        got => return Err(Ps2Error::SelfTest(got)),
+    }
+    // the self-test can reset the controller, so put the configuration back
+    write_config(quiet)?;
+
+    send_command(0xAB)?;
+    match read_data()? {
+        0x00 => Ok(()),
+        got => Err(Ps2Error::PortTest(got)),
+    }
+}
+
+fn read_status() -> u8 {
+    let mut port: Port<u8> = Port::new(COMMAND_PORT);
+    unsafe { port.read() }
+}
+
+fn wait_for(ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    for _ in 0..POLL_LIMIT {
+        if ready(read_status()) {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn send_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    let mut port: Port<u8> = Port::new(COMMAND_PORT);
+    unsafe { port.write(command) };
+    Ok(())
+}
+
+fn write_data(data: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & STATUS_INPUT_FULL == 0)?;
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    unsafe { port.write(data) };
+    Ok(())
+}
+
+fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(|status| status & STATUS_OUTPUT_FULL != 0)?;
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    Ok(unsafe { port.read() })
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    send_command(0x60)?;
+    write_data(config)
+}
+
+/// Throws away anything a device sent before the tests started.
+fn flush_output() {
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..16 {
+        if read_status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { port.read() };
+    }
+}
@@ -0,0 +1,111 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+// set on every register select, NMIs stay off while we talk to the CMOS
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+// in 12 hour mode, the top bit of the hour register means PM
+const HOUR_PM: u8 = 1 << 7;
+
+/// The date and time as the real-time clock keeps it, usually UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Reads the current date and time from the CMOS real-time clock.
+///
+/// The clock updates its registers once a second, so they're read until two reads in a row
+/// agree, which means none of them changed halfway through.
+pub fn read() -> DateTime {
+    let mut last = read_raw();
+    loop {
+        let next = read_raw();
+        if next == last {
+            break;
+        }
+        last = next;
+    }
+    decode(last, read_register(REG_STATUS_B))
+}
+
+type Raw = [u8; 6];
+
+fn read_raw() -> Raw {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0 {
+        core::hint::spin_loop();
+    }
+    [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR].map(read_register)
+}
+
+/// Turns the registers into a `DateTime`, undoing BCD and 12 hour mode if `status_b` says
+/// they're in use.
+fn decode(raw: Raw, status_b: u8) -> DateTime {
+    let [second, minute, hour, day, month, year] = raw;
+    let binary = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 { value } else { (value >> 4) * 10 + (value & 0x0F) }
+    };
+    let mut hours = binary(hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is hour 0, 12 PM stays 12
+        hours %= 12;
+        if hour & HOUR_PM != 0 {
+            hours += 12;
+        }
+    }
+    DateTime {
+        // the century register isn't at the same place everywhere, this kernel won't see 2100
+        year: 2000 + binary(year) as u16,
+        month: binary(month),
+        day: binary(day),
+        hour: hours,
+        minute: binary(minute),
+        second: binary(second),
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(CMOS_ADDRESS);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    unsafe {
+        address.write(NMI_DISABLE | register);
+        data.read()
+    }
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    // 09:05:30 PM on 2024-03-07, in BCD
+    let time = decode([0x30, 0x05, HOUR_PM | 0x09, 0x07, 0x03, 0x24], 0);
+    assert_eq!(time, DateTime { year: 2024, month: 3, day: 7, hour: 21, minute: 5, second: 30 });
+    let midnight = decode([0, 0, 0x12, 1, 1, 0], 0);
+    assert_eq!(midnight.hour, 0);
+    let binary = decode([59, 59, 23, 31, 12, 99], STATUS_B_BINARY | STATUS_B_24_HOUR);
+    assert_eq!(binary, DateTime { year: 2099, month: 12, day: 31, hour: 23, minute: 59, second: 59 });
+}
@@ -1,6 +1,9 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+pub const COM1_BASE: u16 = 0x3F8;
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
@@ -10,16 +13,53 @@ lazy_static! {
     };
 }
 
+/// Sets up COM1 so that received bytes raise IRQ 4.
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+    let mut int_enable: Port<u8> = Port::new(COM1_BASE + 1);
+    let mut modem_control: Port<u8> = Port::new(COM1_BASE + 4);
+    unsafe {
+        // data-available interrupt only
+        int_enable.write(0x01);
+        // DTR, RTS and OUT2 (OUT2 gates the interrupt line to the PIC)
+        modem_control.write(0x0B);
+    }
+    crate::interrupts::unmask_irq(4);
+}
+
+/// Reads a byte from COM1 if one is waiting, without taking the `SERIAL1` lock.
+///
+/// Safe to call from the serial interrupt handler.
+pub fn try_receive() -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(COM1_BASE + 5);
+    let mut data: Port<u8> = Port::new(COM1_BASE);
+    unsafe {
+        if line_status.read() & 0x1 != 0 { Some(data.read()) }
+        else { None }
+    }
+}
+
+/// Writes a byte to COM1 as is.
+///
+/// `SerialPort::send` turns backspace and DEL into an erase sequence, which breaks binary data.
+pub fn send_raw(byte: u8) {
+    let mut line_status: Port<u8> = Port::new(COM1_BASE + 5);
+    let mut data: Port<u8> = Port::new(COM1_BASE);
+    unsafe {
+        // wait for the transmit holding register to empty
+        while line_status.read() & 0x20 == 0 { core::hint::spin_loop(); }
+        data.write(byte);
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
+        // the test panic handler reports through here, so don't panic on a formatting error
+        let _ = SERIAL1.lock().write_fmt(args);
     });
 }
 
@@ -0,0 +1,126 @@
+use core::fmt::{self, Write};
+use alloc::{collections::VecDeque, string::String};
+
+use crate::task::keyboard::DISK_WRITER;
+
+/// Prints to the screen, where command output goes unless it's piped or redirected.
+///
+/// Commands write to a `&mut dyn Write` instead of calling `println!`, and `handle_command`
+/// picks the sink from the pipes and redirections on the command line.
+pub struct ScreenSink {
+    _private: (),
+}
+
+impl ScreenSink {
+    pub fn new() -> ScreenSink {
+        ScreenSink { _private: () }
+    }
+}
+
+impl Write for ScreenSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::print!("{}", s);
+        Ok(())
+    }
+}
+
+/// Collects output in memory, used by tests and by sinks that need the whole output first.
+pub struct StringSink {
+    text: String,
+}
+
+impl StringSink {
+    pub fn new() -> StringSink {
+        StringSink { text: String::new() }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+impl Write for StringSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+/// Appends everything written to it to the text on the disk, after what's already there.
+pub struct DiskSink {
+    _private: (),
+}
+
+impl DiskSink {
+    pub fn new() -> DiskSink {
+        DiskSink { _private: () }
+    }
+}
+
+impl Write for DiskSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // the printing code might already hold the disk writer (e.g. to report a disk error),
+        // so don't wait for it; not waiting also means the lock order can't matter here
+        let mut writer = DISK_WRITER.try_lock().ok_or(fmt::Error)?;
+        writer.append_bytes(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Holds output back so it can be shown a screenful at a time, used by `| more`.
+///
+/// Nothing reaches the screen until the command is done; `into_lines` hands the output to the
+/// pager in the CLI.
+pub struct PagerSink {
+    output: StringSink,
+}
+
+impl PagerSink {
+    pub fn new() -> PagerSink {
+        PagerSink { output: StringSink::new() }
+    }
+
+    pub fn into_lines(self) -> VecDeque<String> {
+        self.output.as_str().lines().map(String::from).collect()
+    }
+}
+
+impl Write for PagerSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.output.write_str(s)
+    }
+}
+
+/// Forwards writes to two sinks.
+///
+/// The first sink is the one that matters: its errors are returned, while the second is best
+/// effort, so a failing disk doesn't stop output from reaching the screen.
+pub struct MultiSink<'a> {
+    primary: &'a mut dyn Write,
+    secondary: &'a mut dyn Write,
+}
+
+impl<'a> MultiSink<'a> {
+    pub fn new(primary: &'a mut dyn Write, secondary: &'a mut dyn Write) -> MultiSink<'a> {
+        MultiSink { primary, secondary }
+    }
+}
+
+impl Write for MultiSink<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = self.secondary.write_str(s);
+        self.primary.write_str(s)
+    }
+}
+
+#[test_case]
+fn test_multi_sink_copies_to_both() {
+    let mut first = StringSink::new();
+    let mut second = StringSink::new();
+    write!(MultiSink::new(&mut first, &mut second), "a{}", 1).unwrap();
+    assert_eq!(first.as_str(), "a1");
+    assert_eq!(second.as_str(), "a1");
+}
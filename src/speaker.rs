@@ -0,0 +1,34 @@
+use x86_64::instructions::port::Port;
+
+use crate::time::{self, read_timer, ticks_since, PIT_BASE_FREQUENCY};
+
+const BEEP_FREQUENCY: u32 = 880;
+const BEEP_MS: u128 = 50;
+
+/// Sounds a short beep on the PC speaker.
+///
+/// Busy-waits for the length of the beep, so interrupts have to be enabled.
+pub fn beep() {
+    let divisor = PIT_BASE_FREQUENCY / BEEP_FREQUENCY;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel2: Port<u8> = Port::new(0x42);
+    let mut gate: Port<u8> = Port::new(0x61);
+    unsafe {
+        // channel 2, lobyte/hibyte access, square wave
+        command.write(0xB6);
+        channel2.write((divisor & 0xFF) as u8);
+        channel2.write((divisor >> 8) as u8);
+        let state = gate.read();
+        gate.write(state | 0x3);
+    }
+
+    let start = read_timer();
+    while time::ticks_to_ms(ticks_since(start)) < BEEP_MS {
+        core::hint::spin_loop();
+    }
+
+    unsafe {
+        let state = gate.read();
+        gate.write(state & !0x3);
+    }
+}
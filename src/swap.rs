@@ -0,0 +1,253 @@
+use core::fmt;
+use spin::Mutex;
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, page_table::PageTableEntry, FrameAllocator, Mapper, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::{disk::{irq, pio::DiskError}, memory};
+
+/// Start of the swappable area, right after the heap so the heap can grow into it when it's
+/// full. It's in the same 2 MiB as the end of the heap, so all of its pages share one page
+/// table, which `init` makes sure exists.
+pub const SWAP_AREA_START: u64 = (crate::allocator::HEAP_START + crate::allocator::HEAP_SIZE) as u64;
+/// Size of the swappable area in pages.
+pub const SWAP_AREA_PAGES: usize = 16;
+/// Physical frames backing the area, so at most this many of its pages are in memory at once.
+pub const SWAP_FRAMES: usize = 4;
+/// First sector of the swap space. Every page of the area has its own slot of
+/// `SECTORS_PER_PAGE` sectors, and the last one ends right before the screenshot sectors.
+/// The text on the disk stops before it, see `keyboard::TEXT_END_LBA`.
+pub const SWAP_START_LBA: u32 = 1872;
+const SECTORS_PER_PAGE: u8 = 8;
+/// Set by us (the CPU ignores it) on a non-present entry whose page is in swap space.
+const SWAPPED: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Why a page of the swappable area couldn't be brought in.
+#[derive(Debug, Clone, Copy)]
+pub enum SwapError {
+    /// `init` hasn't run, or failed.
+    NotInitialized,
+    /// The interrupted code holds the disk driver or the swap state, or a task is in the
+    /// middle of a transfer; waiting would deadlock.
+    Busy,
+    /// The page tables for the area are missing.
+    NoPageTable,
+    Disk(DiskError),
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SwapError::NotInitialized => write!(f, "the swap area isn't set up"),
+            SwapError::Busy => write!(f, "the disk is in use by the code that faulted or a transfer"),
+            SwapError::NoPageTable => write!(f, "the swap area has no page table"),
+            SwapError::Disk(e) => write!(f, "disk error: {}", e),
+        }
+    }
+}
+
+/// Counters for `swap`, since boot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapStats {
+    /// Page faults in the area, each one brings a page in.
+    pub faults: u64,
+    /// Pages read back from the disk, the other faults were first touches.
+    pub swap_ins: u64,
+    /// Pages written out to make room.
+    pub swap_outs: u64,
+}
+
+struct Swap {
+    frames: [Option<PhysFrame>; SWAP_FRAMES],
+    // which page of the area each frame holds
+    owners: [Option<usize>; SWAP_FRAMES],
+    // where the search for a frame to evict continues
+    hand: usize,
+    stats: SwapStats,
+}
+
+static SWAP: Mutex<Swap> = Mutex::new(Swap {
+    frames: [None; SWAP_FRAMES],
+    owners: [None; SWAP_FRAMES],
+    hand: 0,
+    stats: SwapStats { faults: 0, swap_ins: 0, swap_outs: 0 },
+});
+
+/// Sets up the swappable area, taking `SWAP_FRAMES` frames for it, and hands it to the heap to
+/// spill into once it's full. Has to run after `allocator::init_heap`.
+///
+/// The first pages start out mapped (and zeroed); the rest are brought in by
+/// `handle_page_fault` when they're touched, evicting the least recently used page to the disk.
+///
+/// Heap memory in the area can be out on the disk at any time. A buffer that the disk driver
+/// fills or writes out while locked mustn't be in a page that's swapped out, since the fault
+/// would find the driver busy; with the area this small, that's only once the heap has spilled.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut swap = SWAP.lock();
+    for i in 0..SWAP_FRAMES {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page(i), frame, flags, frame_allocator)?.flush() };
+        frame_words(frame).fill(0);
+        swap.frames[i] = Some(frame);
+        swap.owners[i] = Some(i);
+    }
+    unsafe { crate::allocator::set_spill_size(SWAP_AREA_PAGES * 4096) };
+    Ok(())
+}
+
+/// Whether `addr` lies in the swappable area.
+pub fn in_area(addr: VirtAddr) -> bool {
+    page_index(addr).is_some()
+}
+
+pub fn is_initialized() -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| SWAP.lock().frames[0].is_some())
+}
+
+pub fn stats() -> SwapStats {
+    x86_64::instructions::interrupts::without_interrupts(|| SWAP.lock().stats)
+}
+
+/// Brings the page containing `addr` into memory, called by the page fault handler.
+///
+/// Evicts another page to its slot on the disk if all frames are taken. Must not allocate, and
+/// gives up instead of waiting on a lock the interrupted code might hold.
+pub fn handle_page_fault(addr: VirtAddr) -> Result<(), SwapError> {
+    let index = page_index(addr).ok_or(SwapError::NoPageTable)?;
+    let mut swap = SWAP.try_lock().ok_or(SwapError::Busy)?;
+    swap.stats.faults += 1;
+
+    let slot = swap.choose_frame()?;
+    let frame = swap.frames[slot].ok_or(SwapError::NotInitialized)?;
+    if let Some(owner) = swap.owners[slot] {
+        swap_out(owner, frame)?;
+        swap.owners[slot] = None;
+        swap.stats.swap_outs += 1;
+    }
+
+    let entry = leaf_entry(page(index)).ok_or(SwapError::NoPageTable)?;
+    if entry.flags().contains(SWAPPED) {
+        let lba = slot_lba(index);
+        irq::try_lock_driver().ok_or(SwapError::Busy)?
+            .read(frame_words(frame), lba, SECTORS_PER_PAGE)
+            .map_err(SwapError::Disk)?;
+        swap.stats.swap_ins += 1;
+    } else {
+        // never touched before
+        frame_words(frame).fill(0);
+    }
+    entry.set_addr(frame.start_address(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    tlb::flush(page(index).start_address());
+    swap.owners[slot] = Some(index);
+    Ok(())
+}
+
+impl Swap {
+    /// Picks the frame to bring a page into: a free one, or else the one whose page hasn't been
+    /// used for the longest time, as far as the accessed bits tell.
+    fn choose_frame(&mut self) -> Result<usize, SwapError> {
+        if self.frames[0].is_none() {
+            return Err(SwapError::NotInitialized);
+        }
+        if let Some(free) = self.owners.iter().position(|owner| owner.is_none()) {
+            return Ok(free);
+        }
+        // second chance: pages used since the hand last passed get their accessed bit cleared
+        // and are skipped, so after two rounds something is always found
+        for _ in 0..2 * SWAP_FRAMES {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % SWAP_FRAMES;
+            let page = page(self.owners[slot].unwrap());
+            let entry = leaf_entry(page).ok_or(SwapError::NoPageTable)?;
+            if !entry.flags().contains(PageTableFlags::ACCESSED) {
+                return Ok(slot);
+            }
+            entry.set_flags(entry.flags() - PageTableFlags::ACCESSED);
+            tlb::flush(page.start_address());
+        }
+        Ok(self.hand)
+    }
+}
+
+/// Writes the page at `index` to its slot and marks it as swapped out.
+fn swap_out(index: usize, frame: PhysFrame) -> Result<(), SwapError> {
+    irq::try_lock_driver().ok_or(SwapError::Busy)?
+        .write(frame_words(frame), slot_lba(index), SECTORS_PER_PAGE)
+        .map_err(SwapError::Disk)?;
+    let entry = leaf_entry(page(index)).ok_or(SwapError::NoPageTable)?;
+    // the frame address stays in the entry, but nothing reads it while it's not present
+    entry.set_flags(SWAPPED);
+    tlb::flush(page(index).start_address());
+    Ok(())
+}
+
+fn page(index: usize) -> Page<Size4KiB> {
+    Page::containing_address(VirtAddr::new(SWAP_AREA_START + index as u64 * 4096))
+}
+
+fn page_index(addr: VirtAddr) -> Option<usize> {
+    let offset = addr.as_u64().checked_sub(SWAP_AREA_START)?;
+    let index = (offset / 4096) as usize;
+    (index < SWAP_AREA_PAGES).then_some(index)
+}
+
+fn slot_lba(index: usize) -> u32 {
+    SWAP_START_LBA + index as u32 * SECTORS_PER_PAGE as u32
+}
+
+/// The contents of `frame`, through the complete physical memory mapping.
+fn frame_words(frame: PhysFrame) -> &'static mut [u16; 2048] {
+    let virt = memory::phys_to_virt(frame.start_address())
+        .expect("swap used before memory::init");
+    unsafe { &mut *virt.as_mut_ptr() }
+}
+
+/// Walks the active page tables down to the entry mapping `page`.
+///
+/// The fault handler can't borrow the mapper from `kernel_main`, so it goes through the tables
+/// by hand; only the level 1 entries of the swap area are ever changed through this.
+fn leaf_entry(page: Page<Size4KiB>) -> Option<&'static mut PageTableEntry> {
+    let (level_4_frame, _) = Cr3::read();
+    let indexes = [page.p4_index(), page.p3_index(), page.p2_index()];
+    let mut table_addr = level_4_frame.start_address();
+    for index in indexes {
+        let table: &PageTable = unsafe { &*memory::phys_to_virt(table_addr)?.as_ptr() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT)
+            || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table_addr = entry.addr();
+    }
+    let table: &mut PageTable = unsafe { &mut *memory::phys_to_virt(table_addr)?.as_mut_ptr() };
+    Some(&mut table[page.p1_index()])
+}
+
+#[test_case]
+fn test_page_index_bounds() {
+    assert_eq!(page_index(VirtAddr::new(SWAP_AREA_START)), Some(0));
+    assert_eq!(page_index(VirtAddr::new(SWAP_AREA_START + 4096 * 3 + 5)), Some(3));
+    assert_eq!(page_index(VirtAddr::new(SWAP_AREA_START - 1)), None);
+    assert_eq!(page_index(VirtAddr::new(SWAP_AREA_START + 4096 * SWAP_AREA_PAGES as u64)), None);
+    assert!(slot_lba(SWAP_AREA_PAGES - 1) + SECTORS_PER_PAGE as u32 <= crate::task::cli::SCREENSHOT_LBA);
+}
+
+#[test_case]
+fn test_area_shares_the_heap_page_table() {
+    let last = SWAP_AREA_START + SWAP_AREA_PAGES as u64 * 4096 - 1;
+    assert_eq!(SWAP_AREA_START % 4096, 0);
+    // the heap's last page maps through the same level 1 table
+    assert_eq!((SWAP_AREA_START - 1) / 0x20_0000, last / 0x20_0000);
+}
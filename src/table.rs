@@ -0,0 +1,107 @@
+use core::fmt::{self, Write};
+use alloc::{string::String, vec::Vec};
+
+use crate::vga_buffer::BUFFER_WIDTH;
+
+/// Spaces between two columns.
+const COLUMN_GAP: usize = 2;
+/// Ends a cell that was cut to fit.
+const CUT_MARK: char = '~';
+
+/// Lines up rows of text in columns, for commands that list things.
+///
+/// Each column is as wide as its widest cell. Rows wider than the screen are cut down, widest
+/// column first, with `CUT_MARK` at the end of every cell that lost something.
+pub struct Table {
+    rows: Vec<Vec<String>>,
+    width: usize,
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table { rows: Vec::new(), width: BUFFER_WIDTH }
+    }
+
+    /// A table for a different line length than the screen's.
+    pub fn with_width(width: usize) -> Table {
+        Table { rows: Vec::new(), width }
+    }
+
+    pub fn row<S: Into<String>>(&mut self, cells: impl IntoIterator<Item = S>) -> &mut Table {
+        self.rows.push(cells.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Writes the rows out, one per line, without spaces after the last cell.
+    pub fn print(&self, out: &mut dyn Write) -> fmt::Result {
+        let widths = self.column_widths();
+        for row in &self.rows {
+            let mut line = String::new();
+            for (column, cell) in row.iter().enumerate() {
+                if column > 0 {
+                    line.extend(core::iter::repeat(' ').take(COLUMN_GAP));
+                }
+                push_cell(&mut line, cell, widths[column], column + 1 == row.len());
+            }
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn column_widths(&self) -> Vec<usize> {
+        let mut widths = Vec::new();
+        for row in &self.rows {
+            for (column, cell) in row.iter().enumerate() {
+                let len = cell.chars().count();
+                match widths.get_mut(column) {
+                    Some(width) => *width = len.max(*width),
+                    None => widths.push(len),
+                }
+            }
+        }
+        let gaps = widths.len().saturating_sub(1) * COLUMN_GAP;
+        // a column keeps room for one character and the cut mark
+        while widths.iter().sum::<usize>() + gaps > self.width {
+            match widths.iter_mut().filter(|width| **width > 2).max() {
+                Some(widest) => *widest -= 1,
+                None => break,
+            }
+        }
+        widths
+    }
+}
+
+/// Appends `cell` to `line` in exactly `width` characters, or fewer if it's the last one.
+fn push_cell(line: &mut String, cell: &str, width: usize, last: bool) {
+    let len = cell.chars().count();
+    if len > width {
+        line.extend(cell.chars().take(width - 1));
+        line.push(CUT_MARK);
+        return;
+    }
+    line.push_str(cell);
+    if !last {
+        line.extend(core::iter::repeat(' ').take(width - len));
+    }
+}
+
+#[test_case]
+fn test_table_aligns_and_cuts() {
+    use crate::sink::StringSink;
+
+    let mut out = StringSink::new();
+    let mut table = Table::new();
+    table.row(["ID", "COMMAND"]).row(["1", "cat"]).row(["12", "fill -f 100 16 0"]);
+    table.print(&mut out).unwrap();
+    assert_eq!(out.as_str(), "ID  COMMAND\n1   cat\n12  fill -f 100 16 0\n");
+
+    let mut out = StringSink::new();
+    let mut table = Table::with_width(10);
+    table.row(["abc", "0123456789"]);
+    table.print(&mut out).unwrap();
+    assert_eq!(out.as_str(), "abc  0123~\n");
+}
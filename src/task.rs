@@ -2,13 +2,25 @@ use alloc::boxed::Box;
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicU64, Ordering},
-    task::{Context, Poll},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
 };
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
 
+use crate::time;
+
+pub mod autopoweroff;
+pub mod cancel;
 pub mod cli;
+pub mod clock;
 pub mod executor;
+pub mod jobs;
 pub mod keyboard;
+pub mod keymap;
+pub mod line;
+pub mod semaphore;
+pub mod serial_input;
 pub mod simple_executor;
 
 pub struct Task {
@@ -29,6 +41,115 @@ impl Task {
     }
 }
 
+/// Returns `Pending` once so the executor can run the other ready tasks before we continue.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// How many tasks can be in `sleep_ms` at once, more have to poll instead.
+const MAX_SLEEPERS: usize = 8;
+
+// deadline in timer ticks and who to wake then, checked by the timer interrupt
+static SLEEPERS: Mutex<[Option<(u128, Waker)>; MAX_SLEEPERS]> = {
+    const EMPTY: Option<(u128, Waker)> = None;
+    Mutex::new([EMPTY; MAX_SLEEPERS])
+};
+
+/// Waits at least `ms` milliseconds, letting the executor halt in the meantime.
+///
+/// Unlike a loop around `yield_now`, the task isn't polled again until the timer interrupt
+/// wakes it. If all `MAX_SLEEPERS` places are taken it falls back to yielding.
+pub fn sleep_ms(ms: u128) -> Sleep {
+    let ticks = ms.saturating_mul(time::TICKS_PER_SECOND as u128) / 1000;
+    Sleep { until: time::read_timer().saturating_add(ticks) }
+}
+
+pub struct Sleep {
+    until: u128,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if time::read_timer() >= self.until {
+            return Poll::Ready(());
+        }
+        let registered = without_interrupts(|| {
+            let mut sleepers = SLEEPERS.lock();
+            // polled again before the deadline, the old place is reused rather than taking another
+            let already = sleepers.iter().position(|sleeper| {
+                matches!(sleeper, Some((until, waker)) if *until == self.until && waker.will_wake(cx.waker()))
+            });
+            match already.or_else(|| sleepers.iter().position(|sleeper| sleeper.is_none())) {
+                Some(place) => {
+                    sleepers[place] = Some((self.until, cx.waker().clone()));
+                    true
+                }
+                None => false,
+            }
+        });
+        if !registered {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+/// Called by the timer interrupt handler with interrupts off, wakes every task whose sleep is
+/// over.
+pub(crate) fn wake_sleepers(now: u128) {
+    // only locked elsewhere with interrupts off, so this can't be held by what we interrupted
+    let mut sleepers = SLEEPERS.lock();
+    for sleeper in sleepers.iter_mut() {
+        if matches!(sleeper, Some((until, _)) if *until <= now) {
+            if let Some((_, waker)) = sleeper.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// How many timer ticks a task gets before `check_yield` makes it step aside.
+pub const TIME_SLICE_TICKS: u128 = 10;
+
+static SHOULD_YIELD: AtomicBool = AtomicBool::new(false);
+
+/// Called by the timer interrupt handler once the current time slice is used up.
+pub(crate) fn end_time_slice() {
+    SHOULD_YIELD.store(true, Ordering::Relaxed);
+}
+
+/// Yields if the running task has used up its time slice, otherwise carries straight on.
+///
+/// Long-running tasks call this at loop boundaries, which shares the CPU fairly without yielding
+/// on every iteration. It's still cooperative: a task that doesn't call it (or blocks in a
+/// synchronous loop) keeps the CPU, since preempting it would mean saving and restoring its
+/// context from the timer interrupt, which the executor doesn't do.
+pub async fn check_yield() {
+    if SHOULD_YIELD.swap(false, Ordering::Relaxed) {
+        yield_now().await;
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
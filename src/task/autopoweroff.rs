@@ -0,0 +1,61 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{disk::{irq, pio}, klog, klog::Level, println, time::{self, read_timer, ticks_since}};
+
+/// How often the idle time is checked. The shutdown can come this much later than asked.
+const POLL_MS: u128 = 1000;
+
+// 0 while off
+static TIMEOUT_MINUTES: AtomicU32 = AtomicU32::new(0);
+// timer ticks at the last key or serial byte
+static LAST_INPUT: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the keyboard and serial interrupt handlers on any input.
+///
+/// Must not block or allocate.
+pub(crate) fn note_input() {
+    LAST_INPUT.store(read_timer() as u64, Ordering::Relaxed);
+}
+
+/// After how many minutes without input the machine turns itself off, if at all.
+pub fn timeout() -> Option<u32> {
+    match TIMEOUT_MINUTES.load(Ordering::Relaxed) {
+        0 => None,
+        minutes => Some(minutes),
+    }
+}
+
+/// Sets the idle time before shutting down, or turns that off with `None`. The idle time
+/// counts from now, not from the last input.
+pub fn set_timeout(minutes: Option<u32>) {
+    note_input();
+    TIMEOUT_MINUTES.store(minutes.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Parks the disks and turns the machine off once there's been no input for `timeout`
+/// minutes.
+///
+/// It sleeps between checks, so the executor still halts while idle: the timer interrupt that
+/// ends the sleep is what lets the timeout fire with nothing else going on. A command that's
+/// still running doesn't count as input.
+pub async fn autopoweroff() {
+    loop {
+        super::sleep_ms(POLL_MS).await;
+        let minutes = match timeout() {
+            Some(minutes) => minutes,
+            None => continue,
+        };
+        let limit = minutes as u128 * 60 * time::TICKS_PER_SECOND as u128;
+        if ticks_since(LAST_INPUT.load(Ordering::Relaxed) as u128) < limit {
+            continue;
+        }
+        println!("\nNo input for {} minutes, shutting down", minutes);
+        let mut driver = irq::lock_driver_async().await;
+        for (disk, result) in pio::park_disks(&mut driver) {
+            if let Err(e) = result {
+                klog!(Level::Warn, "disk {} not parked: {}", disk, e);
+            }
+        }
+        crate::power_off();
+    }
+}
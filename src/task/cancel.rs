@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::println;
+
+static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Returned by long-running commands that stopped because of a Ctrl+C.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+/// Asks the running command to stop at its next checkpoint.
+///
+/// Called from interrupt context when Ctrl+C is seen, so must not block or allocate.
+pub(crate) fn request() {
+    CANCEL_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Forgets any earlier request, called before a command starts.
+pub fn clear() {
+    CANCEL_REQUESTED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_requested() -> bool {
+    CANCEL_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Cancelation point for long-running commands.
+///
+/// Yields to the executor if the time slice is up, then reports whether the command should stop.
+/// Commands call this once per unit of work (e.g. per sector) and bail out on `Err`.
+pub async fn checkpoint() -> Result<(), Canceled> {
+    super::check_yield().await;
+    if is_requested() {
+        println!("^C");
+        clear();
+        Err(Canceled)
+    } else {
+        Ok(())
+    }
+}
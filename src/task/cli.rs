@@ -1,302 +1,2593 @@
+use core::fmt::{self, Write};
+use core::future::Future;
+use core::pin::Pin;
 use core::str::SplitAsciiWhitespace;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
-use crate::{print, println, vga_buffer::{WRITER, Color, COLOR_LIST, ColorCode, BUFFER_HEIGHT, COLOR_NAME_LIST, BUFFER_WIDTH}, disk::pio::DRIVER};
+use crate::{allocator, cpu, hash::Crc32, klog::{self, Level}, memory, print, println, serial, sink::{DiskSink, MultiSink, PagerSink, ScreenSink}, speaker, swap, table::Table, vga_buffer::{self, WRITER, Color, CursorShape, COLOR_LIST, ColorCode, BUFFER_HEIGHT, COLOR_NAME_LIST, BUFFER_WIDTH}, disk::{bytes_to_words, irq, pio::{park_disks, DiskError, Identify, SmartStatus, MAX_RETRIES_LIMIT}, ram::{RAM, RAM_SECTORS}, words_to_bytes, BlockDevice}, time::{self, read_timer, ticks_since}};
 use conquer_once::spin::OnceCell;
 use lazy_static::lazy_static;
-use alloc::string::String;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use alloc::{boxed::Box, collections::{BTreeMap, VecDeque}, format, string::String, vec, vec::Vec};
+use pc_keyboard::DecodedKey;
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
 use futures_util::{
+    future::{self, Either},
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
 
-use super::keyboard::{ScancodeStream, DISK_WRITER, text_edit_process_key};
+use super::keyboard::{KeyDecoder, ScancodeStream, lock_disk_writer, DiskEnd, USER_DATA_START_LBA, signed_text_end, format_disk, edit_at, sync_leds, text_edit_process_key, update_editor_status};
+use super::autopoweroff;
+use super::cancel;
+use super::clock;
+use super::executor::Spawner;
+use super::jobs;
+use super::keymap::{Action, CLI_KEYMAP, EDITOR_KEYMAP, SEARCH_KEYMAP};
+use super::line::{InputLine, ReverseSearch, HISTORY};
+use super::serial_input::{self, AnsiDecoder, SerialStream};
 
 pub static ESC: char = 0x1B as char;
+/// The longest command line that can be typed, further input is refused with a beep.
+pub const MAX_LINE_LENGTH: usize = 512;
+/// Where `screenshot` stores the screen, near the end of the 1 MiB disk. The text is kept
+/// before it by `TEXT_END_LBA`.
+pub const SCREENSHOT_LBA: u32 = 2000;
+/// Sectors a screenshot takes: a width and height word, then one word per cell.
+pub const SCREENSHOT_SECTORS: u8 = ((2 + BUFFER_WIDTH * BUFFER_HEIGHT + 255) / 256) as u8;
+/// How many sectors past the end of the data `dcheck` looks at for stray data.
+pub const DCHECK_EXTRA_SECTORS: u32 = 16;
+/// Sectors `benchmark` transfers when no count is given.
+pub const BENCHMARK_SECTORS: u32 = 1000;
+/// First of the sectors `benchmark` reads and writes, right after the screenshot and well
+/// past the text, so timing the disk never touches anything on it.
+pub const BENCHMARK_START_LBA: u32 = SCREENSHOT_LBA + SCREENSHOT_SECTORS as u32;
+/// How many scratch sectors `benchmark` has. It goes over them again and again to transfer
+/// more than this.
+pub const BENCHMARK_SCRATCH_SECTORS: u32 = 32;
+/// Sectors per command in the multi-sector `benchmark` pass.
+pub const BENCHMARK_CHUNK: u8 = 8;
+/// Sectors per write command in `fill`.
+pub const FILL_CHUNK: u8 = 8;
+/// Sectors per read and write in `copy`.
+pub const COPY_CHUNK: u8 = 8;
+/// How many sectors `copy` does between progress lines.
+pub const COPY_PROGRESS_SECTORS: u32 = 256;
+/// The shortest run of printable bytes `strings` prints when no length is given.
+pub const STRINGS_MIN_LEN: usize = 4;
+/// The longest minimum length `strings` takes, since that many bytes are held back per run.
+pub const STRINGS_MAX_MIN_LEN: usize = 512;
+/// How many aliases can expand into each other before it's treated as a loop.
+pub const MAX_ALIAS_DEPTH: usize = 8;
+/// The most times `repeat` will run a command.
+pub const MAX_REPEAT: u32 = 10_000;
+/// How long `recv` waits for the next byte before deciding the transfer was cut short.
+pub const SERIAL_TIMEOUT_MS: u128 = 5000;
 pub static BUFFER_CHAR: char = 0x2 as char;
+const MORE_PROMPT: &str = "-- more -- (any key: next page, q: quit)";
+
+// whether typed characters show up on the screen
+static ECHO_INPUT: AtomicBool = AtomicBool::new(true);
+// whether every command run is recorded in the kernel log
+static TRACE: AtomicBool = AtomicBool::new(false);
+// the Ctrl+R search going on in place of the line, if any
+static SEARCH: Mutex<Option<ReverseSearch>> = Mutex::new(None);
 
 // just a hack to enable text editor, is not extensible at all
 lazy_static! {
     pub static ref IS_TEXT_MODE: Mutex<bool> = Mutex::new(false);
+    /// Shortcuts defined with `alias`, expanded by `handle_command`.
+    pub static ref ALIASES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+    /// Output from `| more` that hasn't been shown yet; while it's not empty, keys page through it.
+    pub static ref PAGED_OUTPUT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+    /// A destructive command waiting for `yes`; the next line typed answers it instead of running.
+    pub static ref PENDING_CONFIRMATION: Mutex<Option<String>> = Mutex::new(None);
 }
 
 
-pub async fn cli() {
+/// The terminal: reads keys from the keyboard and serial, edits the line and runs commands.
+///
+/// Commands run inside this task and are awaited, not spawned. While one waits on the disk or
+/// yields at a `cancel::checkpoint`, the executor runs the other tasks, and the interrupt
+/// handlers keep queueing scancodes and serial bytes; those are handled as type-ahead once the
+/// command returns. Ctrl+C is caught in the keyboard interrupt itself, so it reaches a running
+/// command without going through this queue. A line ending in `&` is the exception, it's
+/// started as a task of its own through `spawner` and the prompt comes straight back.
+pub async fn cli(spawner: Spawner) {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
-
-    while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                // just a garbage hack 
-                if *IS_TEXT_MODE.lock() {
-                    if let DecodedKey::Unicode(c) = key && c == ESC {
-                        // leave text edit mode
-                        *IS_TEXT_MODE.lock() = false;
-                        WRITER.lock().reset_screen();
-                        print!("$> ");
-                        WRITER.lock().reset_cmd_start();
-                    }
-                    else {
-                        text_edit_process_key(key);
+    let mut serial = SerialStream::new();
+    let mut keyboard = KeyDecoder::new();
+    let mut ansi = AnsiDecoder::new();
+    let mut line = InputLine::new(MAX_LINE_LENGTH);
+
+    // both input sources feed the same key handling, so editing works the same over serial
+    loop {
+        match future::select(scancodes.next(), serial.next()).await {
+            Either::Left((Some(scancode), _)) => {
+                if let Some(key) = keyboard.add_scancode(scancode) {
+                    handle_key(key, &mut line, &spawner).await;
+                }
+            },
+            Either::Right((Some(byte), _)) => {
+                if let Some(key) = ansi.add_byte(byte) {
+                    handle_key(key, &mut line, &spawner).await;
+                }
+            },
+            // the streams never end
+            _ => break,
+        }
+    }
+}
+
+async fn handle_key(key: DecodedKey, line: &mut InputLine, spawner: &Spawner) {
+    // just a garbage hack 
+    if *IS_TEXT_MODE.lock() {
+        if EDITOR_KEYMAP.action(key) == Action::Leave {
+            // leave text edit mode
+            *IS_TEXT_MODE.lock() = false;
+            vga_buffer::reset_screen();
+            vga_buffer::set_status_line("mode: command");
+            print!("$> ");
+            vga_buffer::reset_cmd_start();
+        }
+        else {
+            text_edit_process_key(key);
+        }
+    } else if !PAGED_OUTPUT.lock().is_empty() {
+        // `| more` is waiting: any key shows the next page, q stops
+        if let DecodedKey::Unicode(c) = key {
+            for _ in 0..MORE_PROMPT.len() {
+                vga_buffer::backspace();
+            }
+            if c == 'q' {
+                PAGED_OUTPUT.lock().clear();
+            }
+            if !show_page() {
+                print!("$> ");
+                vga_buffer::reset_cmd_start();
+            }
+        }
+    } else {
+        let action = if SEARCH.lock().is_some() { search_key(key, line) } else { CLI_KEYMAP.action(key) };
+        match action {
+            Action::InsertChar(character) => {
+                let at_end = line.cursor_at_end();
+                let shown = line.len();
+                if line.push(character) {
+                    if !at_end { redraw_line(line, shown); }
+                    else if echo_input() { print!("{}", character); }
+                    return;
+                }
+                speaker::beep();
+                // say why once, then redraw the line so typing can carry on
+                if line.refused() == 1 {
+                    println!("\n(lines are limited to {} characters)", MAX_LINE_LENGTH);
+                    print!("$> ");
+                    vga_buffer::reset_cmd_start();
+                    redraw_line(line, 0);
+                }
+            },
+            Action::Backspace => {
+                if !line.cursor_at_end() {
+                    edit_line(line, |line| { line.backspace(); });
+                }
+                else if line.backspace() && echo_input() { vga_buffer::backspace(); }
+            },
+            Action::Delete => {
+                if !line.cursor_at_end() {
+                    edit_line(line, |line| { line.move_right(); line.backspace(); });
+                }
+            },
+            Action::Submit => {
+                // don't leave the cursor mark behind on the screen
+                if !line.cursor_at_end() {
+                    edit_line(line, InputLine::move_to_end);
+                }
+                println!();
+                let command = line.take();
+                let pending = PENDING_CONFIRMATION.lock().take();
+                if let Some(confirmed) = pending {
+                    // run as is, an alias can't change what was agreed to
+                    if command.trim() == "yes" {
+                        if run_command(&confirmed, &mut ScreenSink::new()).await.is_err() {
+                            println!("Error: could not write all of the output");
+                        }
+                    } else {
+                        println!("Canceled");
                     }
                 } else {
-                    match key {
-                        DecodedKey::Unicode(character) => {
-                            if character as u32 == 8 {
-                                let mut writer = WRITER.lock();
-                                let cur_pos = writer.current_pos();
-                                let start = writer.cmd_start();
-                                let pos = |pos: (usize, usize)| pos.0 * BUFFER_WIDTH + pos.1;
-                                if pos(cur_pos) > pos(start) { writer.backspace(); }
-                            }
-                            else if character == '\n' as char {
-                                println!();
-                                let command = WRITER.lock().scan_cmd();
-                                handle_command(command);
-                                //println!("{}", command);
-                                if !*IS_TEXT_MODE.lock() {
-                                    print!("$> ");
-                                    WRITER.lock().reset_cmd_start();
-                                }
-                            } 
-                            else {
-                                print!("{}", character);
-                            }
-                        },
-                        DecodedKey::RawKey(key) => print!("{:?} ", key),
+                    // hidden input (a password, say) isn't kept around
+                    if echo_input() {
+                        HISTORY.lock().push(&command);
                     }
+                    handle_command(command, spawner, "").await;
+                }
+                //println!("{}", command);
+                if PENDING_CONFIRMATION.lock().is_some() {
+                    // the answer goes right after the question
+                    vga_buffer::reset_cmd_start();
+                } else if !*IS_TEXT_MODE.lock() && PAGED_OUTPUT.lock().is_empty() {
+                    print!("$> ");
+                    vga_buffer::reset_cmd_start();
+                }
+            },
+            Action::CursorLeft => edit_line(line, InputLine::move_left),
+            Action::CursorRight => edit_line(line, InputLine::move_right),
+            Action::LineStart => edit_line(line, InputLine::move_to_start),
+            Action::LineEnd => edit_line(line, InputLine::move_to_end),
+            Action::KillToEnd => edit_line(line, InputLine::kill_to_end),
+            Action::KillToStart => edit_line(line, InputLine::kill_to_start),
+            Action::KillWord => edit_line(line, InputLine::kill_word),
+            Action::ClearScreen => {
+                vga_buffer::reset_screen();
+                print!("$> ");
+                vga_buffer::reset_cmd_start();
+                redraw_line(line, 0);
+            },
+            Action::HistoryPrev => {
+                let recalled = HISTORY.lock().older().map(String::from);
+                if let Some(recalled) = recalled {
+                    replace_line(line, &recalled);
+                }
+            },
+            Action::HistoryNext => {
+                let recalled = HISTORY.lock().newer().map(String::from);
+                if let Some(recalled) = recalled {
+                    replace_line(line, &recalled);
                 }
+            },
+            Action::ReverseSearch => {
+                let search = ReverseSearch::new(line.as_str());
+                redraw_text(&search.prompt(&HISTORY.lock()), line.len());
+                *SEARCH.lock() = Some(search);
+            },
+            Action::Leave | Action::Ignore => {},
+        }
+    }
+}
+
+/// Whether typed characters are shown. They're still added to the line when they aren't.
+pub fn echo_input() -> bool {
+    ECHO_INPUT.load(Ordering::Relaxed)
+}
+
+/// Stops typed characters from showing up on the screen, or starts showing them again, for
+/// programs that read input that shouldn't be seen.
+pub fn set_echo_input(on: bool) {
+    ECHO_INPUT.store(on, Ordering::Relaxed);
+}
+
+/// Applies `edit` to the line being typed and redraws it.
+fn edit_line(line: &mut InputLine, edit: impl FnOnce(&mut InputLine)) {
+    let shown = line.len();
+    edit(line);
+    redraw_line(line, shown);
+}
+
+/// Erases the `shown` characters of the line on screen and draws it again, marking the
+/// cursor if it isn't at the end.
+fn redraw_line(line: &InputLine, shown: usize) {
+    if !echo_input() {
+        return;
+    }
+    for _ in 0..shown {
+        vga_buffer::backspace();
+    }
+    print!("{}", line.as_str());
+    if !line.cursor_at_end() {
+        vga_buffer::mark_cursor(line.cursor());
+    }
+}
+
+/// Handles a key while Ctrl+R is searching the history, which shows in place of the line.
+///
+/// Returns `Action::Submit` when Enter ends the search, with the line found put in `line`, so
+/// it's run like a typed one. Every other key is dealt with here.
+fn search_key(key: DecodedKey, line: &mut InputLine) -> Action {
+    let mut search = SEARCH.lock();
+    let state = match search.as_mut() {
+        Some(state) => state,
+        None => return Action::Ignore,
+    };
+    let history = HISTORY.lock();
+    let shown = state.prompt(&history).chars().count();
+    let action = SEARCH_KEYMAP.action(key);
+    match action {
+        Action::InsertChar(c) => state.push(c, &history),
+        Action::Backspace => state.pop(&history),
+        Action::ReverseSearch => state.next(&history),
+        Action::Submit | Action::Leave => {
+            let text = match action {
+                Action::Submit => String::from(state.found(&history).unwrap_or(state.saved())),
+                _ => String::from(state.saved()),
+            };
+            *search = None;
+            redraw_text("", shown);
+            line.replace(&text);
+            redraw_line(line, 0);
+            return if action == Action::Submit { Action::Submit } else { Action::Ignore };
+        },
+        _ => return Action::Ignore,
+    }
+    redraw_text(&state.prompt(&history), shown);
+    Action::Ignore
+}
+
+/// Erases `shown` characters before the writing position and prints `text` there instead.
+fn redraw_text(text: &str, shown: usize) {
+    if !echo_input() {
+        return;
+    }
+    for _ in 0..shown {
+        vga_buffer::backspace();
+    }
+    print!("{}", text);
+}
+
+/// Erases the line being typed from the screen and puts `text` there instead.
+fn replace_line(line: &mut InputLine, text: &str) {
+    let shown = line.len();
+    line.replace(text);
+    redraw_line(line, shown);
+}
+
+/// Runs a command line. `context` says what ran it, for `trace`: empty when typed at the
+/// prompt, otherwise ending in "> ".
+async fn handle_command(command: String, spawner: &Spawner, context: &str) {
+    // `alias name=command` keeps the rest of the line as is, pipes included
+    if let Some(definition) = command.trim_start().strip_prefix("alias ") && definition.contains('=') {
+        define_alias(definition);
+        return;
+    }
+    let command = match expand_aliases(&command, &ALIASES.lock()) {
+        Some(command) => command,
+        None => {
+            println!("Error: aliases nest more than {} deep, is one defined in terms of itself?", MAX_ALIAS_DEPTH);
+            return;
+        }
+    };
+
+    if TRACE.load(Ordering::Relaxed) {
+        klog::record(Level::Info, format_args!("trace: {}{}", context, command));
+    }
+
+    // `<command> &` runs in the background, the rest of the line is handled there as usual
+    if let Some(command) = command.trim_end().strip_suffix('&') {
+        run_in_background(String::from(command.trim()), spawner);
+        return;
+    }
+
+    // `repeat <n> <command>` runs the rest of the line, pipes and all, n times
+    if let Some(rest) = command.strip_prefix("repeat ") {
+        let (count, command) = rest.trim_start().split_once(' ').unwrap_or((rest.trim(), ""));
+        let count = match count.parse::<u32>() {
+            Ok(count) if (1..=MAX_REPEAT).contains(&count) => count,
+            _ => {
+                println!("Error: repeat count must be between 1 and {}", MAX_REPEAT);
+                return;
+            }
+        };
+        for run in 1..=count {
+            let context = format!("{}repeat {}/{} > ", context, run, count);
+            // boxed, since the future can't contain itself
+            Box::pin(handle_command(String::from(command), spawner, &context)).await;
+            if cancel::checkpoint().await.is_err() { break; }
+        }
+        return;
+    }
+
+    let mut screen = ScreenSink::new();
+    let result = if let Some((command, target)) = command.split_once('>') {
+        // `<command> > disk` replaces the text on the disk, `<command> >> disk` appends to it
+        let (append, target) = match target.strip_prefix('>') {
+            Some(target) => (true, target),
+            None => (false, target),
+        };
+        if target.trim() != "disk" {
+            println!("Error: can only redirect to disk, not {}", target.trim());
+            return;
+        }
+        if !append {
+            if let Err(e) = clear_disk() {
+                println!("Error: {}", e);
+                return;
+            }
+        }
+        run_command(command, &mut DiskSink::new()).await
+    } else if let Some((command, stage)) = command.split_once('|') {
+        match stage.trim() {
+            // `<command> | tee` also appends the command's output to the disk
+            "tee" => run_command(command, &mut MultiSink::new(&mut screen, &mut DiskSink::new())).await,
+            // `<command> | more` shows the output a screenful at a time
+            "more" => {
+                let mut pager = PagerSink::new();
+                let result = run_command(command, &mut pager).await;
+                *PAGED_OUTPUT.lock() = pager.into_lines();
+                show_page();
+                result
+            }
+            stage => {
+                println!("Error: unknown pipe stage {}", stage);
+                return;
             }
         }
-    } 
+    } else {
+        run_command(&command, &mut screen).await
+    };
+    if result.is_err() {
+        println!("Error: could not write all of the output");
+    }
+}
+
+/// Starts `command` as a background job, printing its id.
+fn run_in_background(command: String, spawner: &Spawner) {
+    if command.is_empty() {
+        println!("Error: empty command");
+        return;
+    }
+    if reads_keyboard(&command) {
+        println!("Error: {} needs the keyboard, it can't run in the background", command);
+        return;
+    }
+    let job_spawner = spawner.clone();
+    let line = command.clone();
+    // boxed, so the future's type doesn't contain `handle_command`'s
+    let job: Pin<Box<dyn Future<Output = ()>>> = Box::pin(async move { handle_command(line, &job_spawner, "background > ").await });
+    match jobs::spawn(spawner, command, job) {
+        Some(id) => println!("[{}]", id),
+        None => println!("Error: too many tasks are starting, try again"),
+    }
+}
+
+/// Whether `command` would take keys from the terminal: the editor, paging with `| more`, or
+/// asking for `yes` before erasing something. Every stage of the line is checked, and so is
+/// what `repeat` runs, with its aliases expanded the way they will be when it does.
+fn reads_keyboard(command: &str) -> bool {
+    if let Some(rest) = command.trim_start().strip_prefix("repeat ") {
+        let command = rest.trim_start().split_once(' ').map_or("", |(_, command)| command);
+        // an alias loop is refused when the command runs
+        return expand_aliases(command, &ALIASES.lock()).is_some_and(|command| reads_keyboard(&command));
+    }
+    command.split(['|', '>']).any(|stage| {
+        let mut parts = stage.split_ascii_whitespace();
+        match parts.next() {
+            Some("textedit" | "more") => true,
+            Some("copy" | "dclear" | "fill" | "mkfs") => !take_force_flag(&mut parts),
+            _ => false,
+        }
+    })
+}
+
+fn define_alias(definition: &str) {
+    let (name, value) = definition.split_once('=').unwrap();
+    let name = name.trim();
+    if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace() || c == '|' || c == '>') {
+        println!("Error: invalid alias name {}", name);
+        return;
+    }
+    if value.trim().is_empty() {
+        println!("Error: alias {} needs a command", name);
+        return;
+    }
+    ALIASES.lock().insert(String::from(name), String::from(value.trim()));
+}
+
+/// Replaces an alias at the start of `command` with what it stands for, over and over in case
+/// that's an alias too. Returns `None` if that doesn't stop within `MAX_ALIAS_DEPTH` steps.
+fn expand_aliases(command: &str, aliases: &BTreeMap<String, String>) -> Option<String> {
+    let mut command = String::from(command.trim_start());
+    for _ in 0..=MAX_ALIAS_DEPTH {
+        let (first, rest) = command.split_once(' ').unwrap_or((&command, ""));
+        match aliases.get(first) {
+            Some(value) if rest.is_empty() => command = value.clone(),
+            Some(value) => command = format!("{} {}", value, rest),
+            None => return Some(command),
+        }
+    }
+    None
+}
+
+/// Prints the next screenful of paged output, returning whether there's more to come.
+fn show_page() -> bool {
+    let mut lines = PAGED_OUTPUT.lock();
+    let page = WRITER.lock().text_rows() - 1;
+    for _ in 0..page {
+        match lines.pop_front() {
+            Some(line) => println!("{}", line),
+            None => break,
+        }
+    }
+    if lines.is_empty() {
+        return false;
+    }
+    print!("{}", MORE_PROMPT);
+    true
 }
 
-fn handle_command(command: String) {
+async fn run_command(command: &str, out: &mut dyn Write) -> fmt::Result {
     let mut parts = command.split_ascii_whitespace();
     let command = parts.next();
     if command.is_none() { 
         // empty command, return
-        println!("Error: empty command");
-        return;
+        return writeln!(out, "Error: empty command");
     }
     let command = command.unwrap();
+    cancel::clear();
     match command {
-        "cat" => cat(parts),
-        "color" => color(parts),
-        "dclear" => dclear(parts),
-        "dappend" => dappend(parts),
-        "textedit" => {
-            WRITER.lock().reset_screen();
-            // just hack
-            *IS_TEXT_MODE.lock() = true;
-            // dump disk contents
-            let writer =  DISK_WRITER.lock();
-            for b in &writer.current_buf[0..writer.current_buf_offset as usize] {
-                print!("{}{}", (b & 0xFF) as u8 as char, (b >> 8) as u8 as char);
-            }
-            if writer.is_in_word {
-                print!("{}", (writer.current_buf[writer.current_buf_offset as usize] & 0xff) as u8 as char); 
-            }
-        }, 
-        "echo" => echo(parts),
-        "help" => help(parts),
-        _ => println!("Error: unrecognized command {}", command),
+        "benchmark" => benchmark(parts, out).await,
+        "cat" => cat(parts, out).await,
+        "copy" => copy(parts, out).await,
+        "fill" => fill(parts, out).await,
+        "find" => find(parts, out).await,
+        "wc" => wc(parts, out).await,
+        "checksum" => checksum(parts, out).await,
+        "strings" => strings(parts, out).await,
+        "color" => color(parts, out),
+        "palette" => palette(parts, out),
+        "colortest" => colortest(parts, out),
+        "dcheck" => dcheck(parts, out),
+        "dwstate" => dwstate(parts, out),
+        "dwresync" => dwresync(parts, out),
+        "dretries" => dretries(parts, out),
+        "dclear" => dclear(parts, out),
+        "mkfs" => mkfs(parts, out),
+        "dappend" => dappend(parts, out),
+        "hexappend" => hexappend(parts, out),
+        "textedit" => textedit(parts, out).await,
+        "clock" => clock(parts, out),
+        "cursor" => cursor(parts, out),
+        "autopoweroff" => autopoweroff_command(parts, out),
+        "echo" => echo(parts, out),
+        "echo_input" => echo_input_command(parts, out),
+        "trace" => trace(parts, out),
+        "help" => help(parts, out),
+        "history" => history(parts, out),
+        "jobs" => jobs(parts, out),
+        "wait" => wait(parts, out).await,
+        "alias" => alias(parts, out),
+        "unalias" => unalias(parts, out),
+        "dmesg" => dmesg(parts, out),
+        "loglevel" => loglevel(parts, out),
+        "loadscreen" => loadscreen(parts, out),
+        "reset" => reset(parts, out),
+        "screenshot" => screenshot(parts, out),
+        "statusline" => statusline(parts, out),
+        "smart" => smart(parts, out),
+        "eject" => eject(parts, out),
+        "shutdown" => shutdown(parts, out),
+        "sysinfo" => sysinfo(parts, out),
+        "kmap" => kmap(parts, out),
+        "peek" => peek(parts, out),
+        "poke" => poke(parts, out),
+        "swap" => swap(parts, out),
+        "recv" => recv(parts, out).await,
+        "send" => send(parts, out),
+        _ => writeln!(out, "Error: unrecognized command {}", command),
+    }
+}
+
+fn echo_input_command(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let on = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(arg) => return writeln!(out, "Error: expected on or off, not {}", arg),
+        None => return writeln!(out, "Error: expected on or off"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    set_echo_input(on);
+    Ok(())
+}
+
+fn trace(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let on = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(arg) => return writeln!(out, "Error: expected on or off, not {}", arg),
+        None => return writeln!(out, "trace is {}", if TRACE.load(Ordering::Relaxed) { "on" } else { "off" }),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    TRACE.store(on, Ordering::Relaxed);
+    Ok(())
+}
+
+fn autopoweroff_command(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let minutes = match args.next() {
+        Some("off") => None,
+        Some(arg) => match arg.parse::<u32>() {
+            Ok(minutes) if minutes > 0 => Some(minutes),
+            _ => return writeln!(out, "Error: expected a number of minutes or off, not {}", arg),
+        },
+        None => return match autopoweroff::timeout() {
+            Some(minutes) => writeln!(out, "shutting down after {} minutes without input", minutes),
+            None => writeln!(out, "autopoweroff is off"),
+        },
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    autopoweroff::set_timeout(minutes);
+    Ok(())
+}
+
+fn clock(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let on = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(arg) => return writeln!(out, "Error: expected on or off, not {}", arg),
+        None => return writeln!(out, "{}", crate::rtc::read()),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    clock::set_enabled(on);
+    Ok(())
+}
+
+fn cursor(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let shape = match args.next() {
+        Some("block") => CursorShape::Block,
+        Some("underline") => CursorShape::Underline,
+        Some("off") => CursorShape::Off,
+        Some(arg) => return writeln!(out, "Error: expected block, underline or off, not {}", arg),
+        None => {
+            let shape = match WRITER.lock().cursor_shape() {
+                CursorShape::Block => "block",
+                CursorShape::Underline => "underline",
+                CursorShape::Off => "off",
+            };
+            return writeln!(out, "{}", shape);
+        }
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    WRITER.lock().set_cursor_shape(shape);
+    Ok(())
+}
+
+fn echo(args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    // reserved up front, so a heap that's run out gives an error rather than halting
+    let mut text = String::new();
+    if text.try_reserve(args.clone().map(|arg| arg.len() + 1).sum()).is_err() {
+        return writeln!(out, "Error: out of memory");
+    }
+    text.extend(args.intersperse(" "));
+    writeln!(out, "{} ", text)
+}
+
+fn jobs(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let running = jobs::running();
+    if running.is_empty() {
+        return writeln!(out, "No background jobs");
+    }
+    let mut table = Table::new();
+    table.row(["ID", "COMMAND"]);
+    for (id, command) in running {
+        table.row([format!("{}", id), command]);
+    }
+    table.print(out)
+}
+
+async fn wait(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let id = match args.next().map(str::parse::<u32>) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return writeln!(out, "Error: the job id must be a number"),
+        None => return writeln!(out, "Error: expected a job id"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    if !jobs::is_running(id) {
+        return writeln!(out, "Error: no background job {} is running", id);
+    }
+    // the job prints that it's done itself
+    while jobs::is_running(id) {
+        super::sleep_ms(jobs::WAIT_POLL_MS).await;
+        if cancel::checkpoint().await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Help for one command: `help` lists the summaries, `help <name>` prints the details.
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    /// One line for the list, ending in * if Ctrl+C can cancel the command.
+    summary: &'static str,
+    /// Argument syntax and examples, each line indented by the caller.
+    detail: &'static str,
+}
+
+static COMMANDS: &[Command] = &[
+    Command { name: "alias", usage: "alias [name=command]", summary: "defines a shortcut, or lists them all without arguments",
+        detail: "The rest of the line after = is kept as is, pipes and redirections included.\nexample: alias dump=cat | more" },
+    Command { name: "autopoweroff", usage: "autopoweroff [minutes|off]", summary: "shuts down after minutes without a key pressed",
+        detail: "Parks the disks and turns off like shutdown. Serial input counts as a key, a\ncommand still running doesn't. Without arguments, prints the setting.\nexample: autopoweroff 30" },
+    Command { name: "benchmark", usage: "benchmark [sectors]", summary: "times reading and writing scratch sectors of the disk *",
+        detail: "Reads the sectors one at a time, then several at a time, and reports the rates.\nUses 32 scratch sectors after the screenshot over and over, so the text is never touched.\nexample: benchmark 200" },
+    Command { name: "cat", usage: "cat [-v|-g]", summary: "prints the contents of the disk to screen *",
+        detail: "Control bytes are printed as they are, unless -v shows them as ^A to ^_ and ^?,\nor -g as the symbols the text mode font has for them (text mode only).\nexample: cat -v | more" },
+    Command { name: "checksum", usage: "checksum", summary: "prints the CRC-32 of the text on the disk *",
+        detail: "The same CRC-32 as zip and the crc32 tool use, to check that the text came\nthrough a copy or an edit unchanged." },
+    Command { name: "clock", usage: "clock [on|off]", summary: "shows or hides a clock in the top right corner",
+        detail: "Without arguments, prints the date and time from the real-time clock (usually UTC).\nexample: clock on" },
+    Command { name: "color", usage: "color [-a] [fg] [bg]", summary: "sets the foreground of the terminal to fg and the background to bg",
+        detail: "[fg] and [bg] can either be numbers or the names of colors (in any case)\nor #RRGGBB hex colors, which use the closest supported color. Only new output\nuses them, unless -a (or --all) repaints what's already on screen too.\nexample: color -a yellow #000080" },
+    Command { name: "colortest", usage: "colortest", summary: "clears the screen and shows every foreground on every background",
+        detail: "Each cell is labeled with its foreground and background number in hex. In text\nmode the top bit of the background makes the cell blink, so backgrounds 8 to f\nblink rather than being bright. Handy after palette." },
+    Command { name: "copy", usage: "copy [-f] [from] [to] [count]", summary: "copies count sectors from lba from to lba to *",
+        detail: "Prints its progress as it goes. The ranges may overlap. Asks for yes first,\nunless -f (or --force) is given. If canceled, it says which sectors were copied,\nthe rest of the destination is left as it was. ram:lba is one of 64 scratch\nsectors in memory instead, empty at boot, e.g. to keep sectors before a fill.\nexample: copy -f 8 ram:0 16" },
+    Command { name: "cursor", usage: "cursor [block|underline|off]", summary: "sets the shape of the blinking text mode cursor",
+        detail: "Without arguments, prints the current shape. reset goes back to underline.\nexample: cursor block" },
+    Command { name: "dcheck", usage: "dcheck [fix]", summary: "checks that the disk writer agrees with the data on disk",
+        detail: "With fix, moves the writer to the end of the data found on disk." },
+    Command { name: "dclear", usage: "dclear [-f]", summary: "clears the contents of the disk",
+        detail: "Asks for yes first, unless -f (or --force) is given." },
+    Command { name: "dappend", usage: "dappend [...]", summary: "appends any text that follows to the disk",
+        detail: "example: dappend hello world" },
+    Command { name: "dwstate", usage: "dwstate", summary: "prints where the disk writer thinks the text ends",
+        detail: "Shows the LBA, word offset and half-word flag the editor and dappend write at,\nto compare with what cat shows." },
+    Command { name: "dwresync", usage: "dwresync", summary: "finds the end of the text on disk again and moves the writer there",
+        detail: "Recovers from the writer's position drifting from the data on disk.\nSee dcheck to only compare the two." },
+    Command { name: "dretries", usage: "dretries [n]", summary: "shows or sets how often a failed disk command is retried",
+        detail: "Only aborted commands and a drive that stays busy are retried, waiting 10 ms\nbefore the first retry and twice as long before each one after. At most 8.\nexample: dretries 5" },
+    Command { name: "dmesg", usage: "dmesg [level]", summary: "prints the kernel log, only down to level if given",
+        detail: "Levels are 0 error, 1 warn, 2 info and 3 debug, by number or name.\nexample: dmesg warn" },
+    Command { name: "echo", usage: "echo [...]", summary: "prints any text that follows to the screen",
+        detail: "example: echo hello > disk" },
+    Command { name: "echo_input", usage: "echo_input [on|off]", summary: "shows or hides what's typed at the prompt",
+        detail: "While off, typed lines still run but aren't shown or kept in the history.\nexample: echo_input off" },
+    Command { name: "eject", usage: "eject [disk]", summary: "ejects the media of a CD drive or removable disk",
+        detail: "Ejects from the selected disk on the bus, or disk 0 or 1 if given. Fixed disks\nrefuse.\nexample: eject 1" },
+    Command { name: "fill", usage: "fill [-f] [lba] [count] [byte]", summary: "overwrites count sectors from lba with one byte value *",
+        detail: "The byte is decimal, or hex with 0x in front. Asks for yes first, unless -f\n(or --force) is given. Filling the editor's text leaves the disk writer behind,\nsee dwresync. ram:lba fills scratch sectors in memory instead, see copy.\nexample: fill -f 100 16 0xff" },
+    Command { name: "find", usage: "find [...]", summary: "prints the byte offset of every match of the text on the disk *",
+        detail: "Matches can span sectors.\nexample: find hello world" },
+    Command { name: "help", usage: "help [command]", summary: "prints this help message, or details about one command",
+        detail: "example: help color" },
+    Command { name: "hexappend", usage: "hexappend [hex]", summary: "appends the bytes given as pairs of hex digits to the disk",
+        detail: "Spaces between digits are ignored; 00 can't be stored, it marks the end.\nexample: hexappend 48 69 0a" },
+    Command { name: "history", usage: "history", summary: "lists earlier commands, which the up and down arrows bring back",
+        detail: "Ctrl+R searches back through them for what's typed next. Ctrl+R again finds an\nolder match, Enter runs the one shown and Esc goes back to the line as it was." },
+    Command { name: "jobs", usage: "jobs", summary: "lists the commands running in the background",
+        detail: "End a command line with & to run it in the background, the prompt comes back\nright away. Background jobs can't read the keyboard, and Ctrl+C stops whichever\ncommand checks for it first.\nexample: find hello > disk &" },
+    Command { name: "loadscreen", usage: "loadscreen", summary: "restores the screen saved by screenshot", detail: "" },
+    Command { name: "kmap", usage: "kmap [virtaddr]", summary: "shows how the page tables map a virtual address",
+        detail: "The address is hex, with or without 0x. Prints the entry and its flags at each\nlevel, then the physical address, or the level where the mapping stops.\nexample: kmap 0xb8000" },
+    Command { name: "loglevel", usage: "loglevel [level]", summary: "shows or sets the least important level that gets logged",
+        detail: "Levels are 0 error, 1 warn, 2 info and 3 debug, by number or name.\nexample: loglevel debug" },
+    Command { name: "mkfs", usage: "mkfs [-f]", summary: "formats the disk and empties its text",
+        detail: "Writes the signature that marks the disk as this kernel's and empties the text.\nA disk without it is treated as empty at boot, and gets it with the first text\nwritten. Asks for yes first, unless -f (or --force) is given." },
+    Command { name: "palette", usage: "palette [color] [#RRGGBB]", summary: "changes what one of the 16 colors looks like",
+        detail: "Without arguments, lists the colors as they look now. Everything already on\nscreen in that color changes too. palette reset restores the defaults.\nexample: palette blue #3050c0" },
+    Command { name: "peek", usage: "peek [virtaddr]", summary: "prints the 64-bit value at a virtual address",
+        detail: "The address is hex, with or without 0x, and must be mapped and 8-byte aligned.\nexample: peek 0xb8000" },
+    Command { name: "poke", usage: "poke [-f] [virtaddr] [value]", summary: "writes a 64-bit value to a virtual address",
+        detail: "Refuses to write to the kernel image or stack, the page tables or device memory,\nunless -f (or --force) is given. Unmapped or unaligned addresses are always refused.\nexample: poke -f 0xb8000 0x0e410e41" },
+    Command { name: "recv", usage: "recv [lba] [count]", summary: "writes data sent over serial to count sectors from lba *",
+        detail: "The data is framed as a 4 byte length, the data, then its 4 byte Adler-32.\nNothing is written unless the checksum matches.\nexample: recv 100 4" },
+    Command { name: "repeat", usage: "repeat [n] [command]", summary: "runs the command n times, Ctrl+C stops it between runs",
+        detail: "The rest of the line is run as typed, pipes and redirections included.\nexample: repeat 3 echo hi >> disk" },
+    Command { name: "reset", usage: "reset", summary: "puts the terminal back to how it was at boot and clears the screen",
+        detail: "Restores the default colors and palette, turns off the status line, resumes\noutput paused with Scroll Lock and shows typed characters again. Handy after\ncolortest or palette." },
+    Command { name: "screenshot", usage: "screenshot", summary: "saves the screen to a reserved area of the disk", detail: "" },
+    Command { name: "send", usage: "send [lba] [count]", summary: "sends count sectors from lba over serial",
+        detail: "The data is framed as a 4 byte length, the data, then its 4 byte Adler-32.\nexample: send 0 8" },
+    Command { name: "shutdown", usage: "shutdown", summary: "parks the disks and turns the machine off",
+        detail: "Puts each ATA disk on the bus in standby first, so its heads are parked, and\nsays how that went on the screen, even when redirected. Turning off only works\nin QEMU and Bochs, elsewhere it halts." },
+    Command { name: "smart", usage: "smart [disk]", summary: "asks the disk whether it expects to fail",
+        detail: "Checks the selected disk on the bus, or disk 0 or 1 if given.\nexample: smart 1" },
+    Command { name: "statusline", usage: "statusline [on|off]", summary: "shows or hides the status line at the bottom of the screen", detail: "" },
+    Command { name: "strings", usage: "strings [minlen]", summary: "prints the runs of printable characters on the disk *",
+        detail: "Only runs of at least minlen characters (4 if not given) are printed, one per line.\nexample: strings 8 | more" },
+    Command { name: "swap", usage: "swap [test]", summary: "shows swap counters, or fills a heap block that spills to disk and checks it",
+        detail: "The heap grows into the swappable area once it's full, and the area's pages go out\nto the disk when there are more of them in use than frames.\nexample: swap test" },
+    Command { name: "sysinfo", usage: "sysinfo", summary: "prints information about the machine", detail: "" },
+    Command { name: "textedit", usage: "textedit [lba]", summary: "opens a text editor that writes to the screen and to the disk",
+        detail: "Without lba, shows the last sector's text and adds to the end of it. With lba,\nshows the text from that sector on and types at its start, moving the rest\nalong, which is slower the more text follows. ESC goes back to the terminal.\nexample: textedit 8" },
+    Command { name: "trace", usage: "trace [on|off]", summary: "records every command run in the kernel log, see dmesg",
+        detail: "Each line is recorded as run, after aliases are expanded, and without being\nprinted. Commands run by repeat or in the background say so first.\nWithout arguments, prints whether it's on.\nexample: trace on" },
+    Command { name: "unalias", usage: "unalias [name]", summary: "removes a shortcut defined with alias", detail: "" },
+    Command { name: "wait", usage: "wait [id]", summary: "waits for the background job with that id to finish *",
+        detail: "example: wait 1" },
+    Command { name: "wc", usage: "wc", summary: "counts the bytes, words and lines of the text on the disk *",
+        detail: "Words are separated by spaces, tabs or newlines; lines are counted by their newlines." },
+];
+
+fn help(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if let Some(name) = args.next() {
+        if args.next().is_some() {
+            return writeln!(out, "Error: at most 1 argument expected");
+        }
+        return help_command(name, out);
     }
+    writeln!(out, "List of commands:")?;
+    for command in COMMANDS {
+        writeln!(out, "  {}: {}", command.usage, command.summary)?;
+    }
+    writeln!(out, "  [command] | tee: runs the command, also appending its output to the disk")?;
+    writeln!(out, "  [command] | more: shows the output of the command a screenful at a time")?;
+    writeln!(out, "  [command] > disk: replaces the text on the disk with the output of the command")?;
+    writeln!(out, "  [command] >> disk: appends the output of the command to the disk")?;
+    writeln!(out, "Commands marked with * can be canceled with Ctrl+C, help [command] tells more")?;
+    writeln!(out, "Line editing: Ctrl+A/Ctrl+E go to the start/end, Ctrl+K/Ctrl+U delete to the end/start,")?;
+    writeln!(out, "  Ctrl+W deletes a word, Ctrl+L clears the screen")
 }
 
-fn echo(args: SplitAsciiWhitespace) {
-    println!("{} ", args.into_iter().intersperse(&" ").collect::<String>());
+fn help_command(name: &str, out: &mut dyn Write) -> fmt::Result {
+    let command = match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => command,
+        None => {
+            let closest = COMMANDS.iter()
+                .map(|command| (edit_distance(name, command.name), command.name))
+                .min();
+            return match closest {
+                Some((distance, closest)) if distance <= 2 => {
+                    writeln!(out, "Error: no command named {}, did you mean {}?", name, closest)
+                }
+                _ => writeln!(out, "Error: no command named {}", name),
+            };
+        }
+    };
+    writeln!(out, "usage: {}", command.usage)?;
+    writeln!(out, "  {}", command.summary)?;
+    for line in command.detail.lines() {
+        writeln!(out, "  {}", line)?;
+    }
+    if command.name == "color" {
+        writeln!(out, "  currently, the supported colors are:")?;
+        for color in COLOR_NAME_LIST {
+            writeln!(out, "    {}", color)?;
+        }
+    }
+    Ok(())
 }
 
-fn help(_args: SplitAsciiWhitespace) {
-    println!("List of commands:");
-    println!("  cat: prints the contents of the disk to screen");
-    println!("  color [fg] [bg]: sets the foreground of the terminal to fg and the background to bg");
-    println!("      [fg] and [bg] can either be numbers or the names of colors");
-    println!("      currently, the supported colors are:");
-    for color in COLOR_NAME_LIST {
-        println!("      {}", color);
+/// How many single character insertions, deletions or substitutions turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // the distances from the first i characters of a to every prefix of b
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
     }
-    println!("  dclear - clear the contents of the disk");
-    println!("  dappend [...]: appends any text that follows to the disk");
-    println!("  textedit: opens a text editor that writes to the screen and to the disk");
-    println!("      to get back to the terminal, press ESC");
-    println!("  echo [...]: prints any text that follows to the screen");
-    println!("  help: prints this help message");
+    row[b.len()]
 }
 
-fn color(mut args: SplitAsciiWhitespace) {
+fn color(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let mut repaint = args.clone();
+    let repaint = match repaint.next() {
+        Some("-a" | "--all") => {
+            args = repaint;
+            true
+        }
+        _ => false,
+    };
     let fg = args.next();
     if fg.is_none() {
-        println!("Error: missing foreground color");
-        return;
+        return writeln!(out, "Error: missing foreground color");
     }
     let fg = fg.unwrap();
 
     let bg = args.next();
     if bg.is_none() {
-        println!("Error: missing foreground color");
-        return;
+        return writeln!(out, "Error: missing foreground color");
     }
     if args.next().is_some() {
-        println!("Error: only 2 arguments expected");
-        return;
+        return writeln!(out, "Error: only 2 arguments expected");
     }
     let bg = bg.unwrap();
 
-    let fg = {
-        if let Ok(color) = fg.parse::<usize>() {
-            // is a number
-            if color > COLOR_LIST.len() {
-                println!("Error: invalid color");
-                // explain what the valid colors are
-                return;
-            }
-            COLOR_LIST[color]
+    let fg = match parse_color(fg) {
+        Some(color) => color,
+        None => {
+            return writeln!(out, "Error: invalid color {}", fg);
         }
-        else {
-            COLOR_LIST[
-                match fg {
-                    "Black" => 0,
-                    "Blue" => 1,
-                    "Green" => 2,
-                    "Cyan" => 3,
-                    "Red" => 4,
-                    "Magenta" => 5,
-                    "Brown" => 6,
-                    "LightGray" => 7,
-                    "DarkGray" => 8,
-                    "LightBlue" => 9,
-                    "LightGreen" => 10,
-                    "LightCyan" => 11,
-                    "LightRed" => 12,
-                    "Pink" => 13,
-                    "Yellow" => 14,
-                    "White" => 15,
-                    _ => {
-                        println!("Error: invalid color");
-                        // explain what the valid colors are
-                        return;
-                    }
-                } 
-            ]
+    };
+    let bg = match parse_color(bg) {
+        Some(color) => color,
+        None => {
+            return writeln!(out, "Error: invalid color {}", bg);
         }
     };
 
-    let bg = {
-        if let Ok(color) = bg.parse::<usize>() {
-            // is a number
-            if color > COLOR_LIST.len() {
-                println!("Error: invalid color");
-                // explain what the valid colors are
-                return;
+    let new_color = ColorCode::new(fg, bg);
+    let mut writer = WRITER.lock();
+    writer.set_color(new_color);
+    if repaint {
+        writer.fill_screen(new_color);
+    }
+    Ok(())
+}
+
+async fn textedit(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let end = lock_disk_writer().end();
+    let from = match args.next().map(str::parse::<u32>) {
+        None => None,
+        Some(Ok(lba)) if (USER_DATA_START_LBA..=end.lba).contains(&lba) => Some(lba),
+        Some(_) => {
+            return writeln!(out, "Error: the text is in LBAs {} to {}", USER_DATA_START_LBA, end.lba);
+        }
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    if let Err(e) = irq::lock_driver_async().await.check_writable() {
+        return writeln!(out, "Error: can't edit, the disk won't take writes: {}", e);
+    }
+
+    if let Some(lba) = from {
+        // typing goes in at the start of the sector, the text after it moves along
+        let at = DiskEnd { lba, offset: 0, is_in_word: false }.byte_offset();
+        // the text is read before the screen is cleared, so an error leaves it as it was
+        if let Err(e) = edit_at(Some(at)) {
+            return writeln!(out, "Error: {}", e);
+        }
+        *IS_TEXT_MODE.lock() = true;
+        update_editor_status();
+        return Ok(());
+    }
+    if let Err(e) = edit_at(None) {
+        return writeln!(out, "Error: {}", e);
+    }
+    // the last sector comes from the writer's buffer, which may not be written out yet
+    let writer = lock_disk_writer();
+    let text = sector_text(&writer.current_buf, writer.current_buf_offset, writer.is_in_word);
+    drop(writer);
+
+    vga_buffer::reset_screen();
+    // just hack
+    *IS_TEXT_MODE.lock() = true;
+    for b in text {
+        print!("{}", b as char);
+    }
+    update_editor_status();
+    Ok(())
+}
+
+/// Where `colortest`'s grid starts, after the column of foreground numbers.
+const COLORTEST_LEFT: usize = 6;
+/// The width of one cell in `colortest`'s grid.
+const COLORTEST_CELL: usize = 4;
+
+fn colortest(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_ok() {
+        return writeln!(out, "Error: colortest needs text mode, the framebuffer has no colors");
+    }
+    // drawn in place, so the grid doesn't scroll and the cursor stays at the bottom
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color = writer.color();
+        writer.reset_screen();
+        writer.write_at(0, 0, "fg\\bg");
+        for bg in 0..COLOR_LIST.len() {
+            // over the background digit of the cells below
+            writer.write_at(0, COLORTEST_LEFT + bg * COLORTEST_CELL, &format!("  {:x}", bg));
+        }
+        for (fg, &foreground) in COLOR_LIST.iter().enumerate() {
+            writer.set_color(color);
+            writer.write_at(1 + fg, 0, &format!("  {:x}", fg));
+            for (bg, &background) in COLOR_LIST.iter().enumerate() {
+                writer.set_color(ColorCode::new(foreground, background));
+                writer.write_at(1 + fg, COLORTEST_LEFT + bg * COLORTEST_CELL, &format!(" {:x}{:x} ", fg, bg));
             }
-            COLOR_LIST[color]
         }
-        else {
-            COLOR_LIST[
-                match bg {
-                    "Black" => 0,
-                    "Blue" => 1,
-                    "Green" => 2,
-                    "Cyan" => 3,
-                    "Red" => 4,
-                    "Magenta" => 5,
-                    "Brown" => 6,
-                    "LightGray" => 7,
-                    "DarkGray" => 8,
-                    "LightBlue" => 9,
-                    "LightGreen" => 10,
-                    "LightCyan" => 11,
-                    "LightRed" => 12,
-                    "Pink" => 13,
-                    "Yellow" => 14,
-                    "White" => 15,
-                    _ => {
-                        println!("Error: invalid color");
-                        // explain what the valid colors are
-                        return;
-                    }
-                } 
-            ]
+        writer.set_color(color);
+    });
+    Ok(())
+}
+
+/// Parses a color given as its number, its name or a `#RRGGBB` hex triplet.
+///
+/// Names are matched case-insensitively, `grey` is accepted for `gray`, and a plain
+/// `gray` means light gray (the usual VGA "gray"). Hex colors become the closest of the 16.
+pub fn parse_color(name: &str) -> Option<Color> {
+    if let Ok(index) = name.parse::<usize>() {
+        return COLOR_LIST.get(index).copied();
+    }
+    if let Some(hex) = name.strip_prefix('#') {
+        return parse_hex_color(hex).map(nearest_color);
+    }
+    let name = name.to_ascii_lowercase().replace("grey", "gray");
+    if name == "gray" {
+        return Some(Color::LightGray);
+    }
+    COLOR_NAME_LIST.iter()
+        .position(|color_name| color_name.to_ascii_lowercase() == name)
+        .map(|index| COLOR_LIST[index])
+}
+
+fn parse_hex_color(hex: &str) -> Option<(u8, u8, u8)> {
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// The VGA color closest to `rgb` as the palette is now, by Euclidean distance in RGB space.
+fn nearest_color(rgb: (u8, u8, u8)) -> Color {
+    let distance = |other: &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(rgb.0, other.0) + d(rgb.1, other.1) + d(rgb.2, other.2)
+    };
+    let (index, _) = vga_buffer::palette().iter().enumerate()
+        .min_by_key(|(_, other)| distance(other))
+        .unwrap();
+    COLOR_LIST[index]
+}
+
+fn palette(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let color = match args.next() {
+        None => {
+            let mut table = Table::new();
+            for (name, (r, g, b)) in COLOR_NAME_LIST.iter().zip(vga_buffer::palette()) {
+                table.row([String::from(*name), format!("#{:02x}{:02x}{:02x}", r, g, b)]);
+            }
+            return table.print(out);
+        }
+        Some("reset") => {
+            if args.next().is_some() {
+                return writeln!(out, "Error: only 1 argument expected");
+            }
+            vga_buffer::reset_palette();
+            return Ok(());
         }
+        // a hex color would pick whichever is closest, which isn't what's meant here
+        Some(name) if name.starts_with('#') => return writeln!(out, "Error: give the color to change by name or number"),
+        Some(name) => match parse_color(name) {
+            Some(color) => color,
+            None => return writeln!(out, "Error: invalid color {}", name),
+        },
+    };
+    let rgb = match args.next() {
+        Some(hex) => match hex.strip_prefix('#').and_then(parse_hex_color) {
+            Some(rgb) => rgb,
+            None => return writeln!(out, "Error: expected #RRGGBB, not {}", hex),
+        },
+        None => return writeln!(out, "Error: missing #RRGGBB"),
     };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 2 arguments expected");
+    }
+    // the DAC only has 6 bits per channel
+    vga_buffer::set_palette_color(color as u8, rgb.0 >> 2, rgb.1 >> 2, rgb.2 >> 2);
+    Ok(())
+}
 
-    let new_color = ColorCode::new(fg, bg);
-    WRITER.lock().set_color(new_color);
+pub fn dclear(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let force = take_force_flag(&mut args);
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    if !force {
+        ask_confirmation("all of the text on the disk", String::from("dclear -f"));
+        return Ok(());
+    }
+
+    if let Err(e) = clear_disk() {
+        return writeln!(out, "Error: {}", e);
+    }
+    Ok(())
 }
 
-pub fn dclear(mut args: SplitAsciiWhitespace) {
+fn mkfs(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let force = take_force_flag(&mut args);
     if args.next().is_some() {
-        println!("Error: 0 arguments expected");
-        return
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    if !force {
+        ask_confirmation("the text on the disk", String::from("mkfs -f"));
+        return Ok(());
+    }
+
+    if let Err(e) = format_disk() {
+        return writeln!(out, "Error: {}", e);
     }
+    writeln!(out, "Disk formatted")
+}
+
+/// Takes a leading `-f` or `--force` off `args`, which skips `ask_confirmation`.
+fn take_force_flag(args: &mut SplitAsciiWhitespace) -> bool {
+    let mut rest = args.clone();
+    if matches!(rest.next(), Some("-f" | "--force")) {
+        *args = rest;
+        return true;
+    }
+    false
+}
 
-    let mut writer = DISK_WRITER.lock();
+/// Asks before a command destroys `what`, running `command` (with its force flag) if the next
+/// line typed is exactly `yes`.
+///
+/// The question goes straight to the screen, whatever the command's output is redirected to.
+fn ask_confirmation(what: &str, command: String) {
+    print!("This will erase {}. Type 'yes' to confirm: ", what);
+    *PENDING_CONFIRMATION.lock() = Some(command);
+}
+
+/// Zeroes every sector the disk writer has written to and moves it back to the start.
+fn clear_disk() -> Result<(), DiskError> {
+    let mut writer = lock_disk_writer();
     // erase data
     let mut blank = [0; 256];
-    for lba in 0..(writer.current_lba + 1) { // lbas are also zero-indexed, so we add one to get the last one
-        DRIVER.lock().write(&mut blank, lba, 1);
+    let mut driver = irq::lock_driver()?;
+    for lba in USER_DATA_START_LBA..=writer.current_lba {
+        driver.write(&mut blank, lba, 1)?;
     }
+    drop(driver);
     writer.current_buf = blank;
     writer.current_buf_offset = 0;
-    writer.current_lba = 0;
+    writer.is_in_word = false;
+    writer.current_lba = USER_DATA_START_LBA;
+    Ok(())
+}
+
+/// How `cat` shows the control bytes in the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlDisplay {
+    /// As they are, for the screen to act on or replace.
+    Raw,
+    /// `^A` for 0x01 and so on, `^?` for DEL.
+    Caret,
+    /// As the glyph the VGA font has for the byte.
+    Glyph,
 }
 
-fn cat(mut args: SplitAsciiWhitespace) {
+/// Writes one byte of the text the way `display` asks for. Newlines are always kept.
+fn write_text_byte(out: &mut dyn Write, byte: u8, display: ControlDisplay) -> fmt::Result {
+    match (display, byte) {
+        (_, b'\n') | (ControlDisplay::Raw, _) => write!(out, "{}", byte as char),
+        (ControlDisplay::Caret, 0x00..=0x1F | 0x7F) => write!(out, "^{}", (byte ^ 0x40) as char),
+        (ControlDisplay::Caret, _) => write!(out, "{}", byte as char),
+        (ControlDisplay::Glyph, _) => write!(out, "{}", crate::cp437::glyph(byte)),
+    }
+}
+
+async fn cat(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let display = match args.next() {
+        None => ControlDisplay::Raw,
+        Some("-v") => ControlDisplay::Caret,
+        Some("-g") => ControlDisplay::Glyph,
+        Some(flag) => return writeln!(out, "Error: unknown option {}, expected -v or -g", flag),
+    };
     if args.next().is_some() {
-        println!("Error: 0 arguments expected");
-        return;
+        return writeln!(out, "Error: at most 1 argument expected");
     }
-    let writer = DISK_WRITER.lock();
+    // don't hold the writer across the awaits below
+    let (current_lba, current_buf, current_buf_offset, is_in_word) = {
+        let writer = lock_disk_writer();
+        (writer.current_lba, writer.current_buf, writer.current_buf_offset, writer.is_in_word)
+    };
     // read full sectors
     let mut buf = [0; 256];
-    for lba in 0..writer.current_lba { // lbas zero-indexed
-        DRIVER.lock().read(&mut buf, lba, 1);
-        for b in buf {
-            print!("{}{}", (b & 0xFF) as u8 as char, (b >> 8)as u8 as char);
+    for lba in USER_DATA_START_LBA..current_lba {
+        if cancel::checkpoint().await.is_err() { return Ok(()); }
+        if let Err(e) = irq::read_async(&mut buf, lba, 1).await {
+            return writeln!(out, "\nError: {}", e);
+        }
+        for b in words_to_bytes(&buf) {
+            write_text_byte(out, b, display)?;
         }
     }
-    for b in &writer.current_buf[0..writer.current_buf_offset as usize] {
-        print!("{}{}", (b & 0xFF) as u8 as char, (b >> 8)as u8 as char);
+    for b in sector_text(&current_buf, current_buf_offset, is_in_word) {
+        write_text_byte(out, b, display)?;
     }
-    println!();
+    writeln!(out)
 }
 
-fn dappend(args: SplitAsciiWhitespace) {
+#[test_case]
+fn test_write_text_byte_modes() {
+    let text = b"a\x01\n\x7f";
+    for (display, expected) in [
+        (ControlDisplay::Raw, "a\u{1}\n\u{7f}"),
+        (ControlDisplay::Caret, "a^A\n^?"),
+        (ControlDisplay::Glyph, "a☺\n⌂"),
+    ] {
+        let mut out = crate::sink::StringSink::new();
+        for byte in text {
+            write_text_byte(&mut out, *byte, display).unwrap();
+        }
+        assert_eq!(out.as_str(), expected);
+    }
+}
 
-    for c in args.into_iter().intersperse(&" ").flat_map(|s| s.chars())  {
-        //print!("{c}");
-        let mut writer = DISK_WRITER.lock();
-        let off = writer.current_buf_offset as usize;
-        if !writer.is_in_word {
-            writer.current_buf[off] |= c as u32 as u16;
-        } else {
-            writer.current_buf[off] |= (c as u32 as u16) << 8; // set high bytes
-        }
-        if writer.is_in_word { writer.current_buf_offset += 1; }
-        writer.is_in_word = !writer.is_in_word;
-    }
-    //println!("\nFlushing Buffer!");
-    // Flush buffer 
-    let mut writer = DISK_WRITER.lock();
-    let lba = writer.current_lba;
-    x86_64::instructions::interrupts::without_interrupts(||
-        DRIVER.lock().write(&mut writer.current_buf, lba, 1));
-
-    
-    if writer.current_buf_offset == 256 {
-        // go to next sector
-        // first, output the current cached buf
-        
-        writer.current_lba += 1;
-        writer.current_buf_offset = 0;
-        writer.is_in_word = false;
-        let lba = writer.current_lba;
-        x86_64::instructions::interrupts::without_interrupts(||
-            DRIVER.lock().read(&mut writer.current_buf, lba, 1));
-    }
-    //println!("Finished flushing buffer!");
-}
\ No newline at end of file
+/// The text in the disk writer's sector: the words before `offset`, and the low byte of the
+/// word at `offset` if `is_in_word`.
+fn sector_text(buf: &[u16; 256], offset: u16, is_in_word: bool) -> Vec<u8> {
+    let len = offset as usize * 2 + is_in_word as usize;
+    let mut bytes = words_to_bytes(&buf[..(len + 1) / 2]);
+    bytes.truncate(len);
+    bytes
+}
+
+/// Why `for_each_text_sector` stopped before the end of the text.
+enum TextError {
+    Canceled,
+    Disk(DiskError),
+    /// The callback couldn't write its output.
+    Output,
+}
+
+impl TextError {
+    /// What a command does when reading the text stopped: says why, unless it was canceled.
+    fn report(self, out: &mut dyn Write) -> fmt::Result {
+        match self {
+            TextError::Canceled => Ok(()),
+            TextError::Disk(e) => writeln!(out, "Error: {}", e),
+            TextError::Output => Err(fmt::Error),
+        }
+    }
+}
+
+/// Calls `f` with the bytes of the text on the disk, a sector at a time.
+///
+/// The last call gets the disk writer's sector, which may not be written out yet, up to and
+/// including a pending half word. Ctrl+C stops it between sectors. The writer is only locked
+/// to copy its position, never across an await.
+async fn for_each_text_sector(mut f: impl FnMut(&[u8]) -> fmt::Result) -> Result<(), TextError> {
+    let (current_lba, current_buf, current_buf_offset, is_in_word) = {
+        let writer = lock_disk_writer();
+        (writer.current_lba, writer.current_buf, writer.current_buf_offset, writer.is_in_word)
+    };
+
+    let mut buf = [0; 256];
+    for lba in USER_DATA_START_LBA..=current_lba {
+        cancel::checkpoint().await.map_err(|_| TextError::Canceled)?;
+        let bytes = if lba < current_lba {
+            irq::lock_driver_async().await.read(&mut buf, lba, 1).map_err(TextError::Disk)?;
+            words_to_bytes(&buf)
+        } else {
+            sector_text(&current_buf, current_buf_offset, is_in_word)
+        };
+        f(&bytes).map_err(|_| TextError::Output)?;
+    }
+    Ok(())
+}
+
+async fn find(args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let needle = args.intersperse(&" ").collect::<String>();
+    if needle.is_empty() {
+        return writeln!(out, "Error: missing text to find");
+    }
+    let needle = needle.as_bytes();
+
+    // bytes carried over from the previous sector so matches can span sector boundaries
+    let mut window: Vec<u8> = Vec::new();
+    let mut window_start = 0;
+    let mut matches = 0;
+    let result = for_each_text_sector(|bytes| {
+        window.extend_from_slice(bytes);
+        if window.len() >= needle.len() {
+            for i in 0..=(window.len() - needle.len()) {
+                if &window[i..i + needle.len()] == needle {
+                    writeln!(out, "{}", window_start + i)?;
+                    matches += 1;
+                }
+            }
+            let keep = needle.len() - 1;
+            let drained = window.len() - keep;
+            window.drain(..drained);
+            window_start += drained;
+        }
+        Ok(())
+    }).await;
+    if let Err(e) = result {
+        return e.report(out);
+    }
+    writeln!(out, "{} matches", matches)
+}
+
+/// Running totals for `wc`, fed a byte at a time.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct WordCount {
+    bytes: usize,
+    words: usize,
+    lines: usize,
+    in_word: bool,
+}
+
+impl WordCount {
+    fn add(&mut self, byte: u8) {
+        self.bytes += 1;
+        if byte == b'\n' {
+            self.lines += 1;
+        }
+        let in_word = !byte.is_ascii_whitespace();
+        if in_word && !self.in_word {
+            self.words += 1;
+        }
+        self.in_word = in_word;
+    }
+}
+
+async fn wc(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let mut count = WordCount::default();
+    let result = for_each_text_sector(|bytes| {
+        bytes.iter().for_each(|byte| count.add(*byte));
+        Ok(())
+    }).await;
+    if let Err(e) = result {
+        return e.report(out);
+    }
+    writeln!(out, "{} bytes, {} words, {} lines", count.bytes, count.words, count.lines)
+}
+
+async fn checksum(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let mut crc = Crc32::new();
+    let mut len = 0;
+    let result = for_each_text_sector(|bytes| {
+        crc.update(bytes);
+        len += bytes.len();
+        Ok(())
+    }).await;
+    if let Err(e) = result {
+        return e.report(out);
+    }
+    writeln!(out, "{:08x} ({} bytes)", crc.finish(), len)
+}
+
+#[test_case]
+fn test_word_count() {
+    let mut count = WordCount::default();
+    for byte in b"  hello world\n\tsecond  line\n" {
+        count.add(*byte);
+    }
+    assert_eq!((count.bytes, count.words, count.lines), (28, 4, 2));
+}
+
+/// Picks out the runs of `strings`, fed a byte at a time.
+///
+/// A run is only held back until it's long enough to print, after that it's written as it
+/// comes, so a long run doesn't have to fit in memory.
+struct PrintableRuns {
+    min_len: usize,
+    pending: Vec<u8>,
+    printing: bool,
+}
+
+impl PrintableRuns {
+    fn new(min_len: usize) -> PrintableRuns {
+        PrintableRuns { min_len, pending: Vec::new(), printing: false }
+    }
+
+    fn add(&mut self, byte: u8, out: &mut dyn Write) -> fmt::Result {
+        if !(byte == b'\t' || (0x20..=0x7e).contains(&byte)) {
+            return self.finish(out);
+        }
+        if self.printing {
+            return write!(out, "{}", byte as char);
+        }
+        self.pending.push(byte);
+        if self.pending.len() >= self.min_len {
+            self.printing = true;
+            self.pending.drain(..).try_for_each(|byte| write!(out, "{}", byte as char))?;
+        }
+        Ok(())
+    }
+
+    /// Ends the current run, as if a non-printable byte came next.
+    fn finish(&mut self, out: &mut dyn Write) -> fmt::Result {
+        self.pending.clear();
+        if self.printing {
+            self.printing = false;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+async fn strings(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let min_len = match args.next().map(str::parse::<usize>) {
+        None => STRINGS_MIN_LEN,
+        Some(Ok(n)) if n > STRINGS_MAX_MIN_LEN =>
+            return writeln!(out, "Error: the minimum length can be at most {}", STRINGS_MAX_MIN_LEN),
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => return writeln!(out, "Error: invalid minimum length"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    let mut runs = PrintableRuns::new(min_len);
+    // runs carry on from one sector into the next
+    let result = for_each_text_sector(|bytes| {
+        bytes.iter().try_for_each(|byte| runs.add(*byte, out))
+    }).await;
+    if let Err(e) = result {
+        runs.finish(out)?;
+        return e.report(out);
+    }
+    runs.finish(out)
+}
+
+#[test_case]
+fn test_printable_runs() {
+    let mut out = crate::sink::StringSink::new();
+    let mut runs = PrintableRuns::new(4);
+    for byte in b"abc\x01longer run\nhi\xffjoined\ttab" {
+        runs.add(*byte, &mut out).unwrap();
+    }
+    runs.finish(&mut out).unwrap();
+    assert_eq!(out.as_str(), "longer run\njoined\ttab\n");
+}
+
+fn dappend(args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if let Err(e) = irq::lock_driver().and_then(|mut driver| driver.check_writable()) {
+        return writeln!(out, "Error: the disk won't take writes: {}", e);
+    }
+
+    let text: String = args.intersperse(" ").collect();
+    // gathered in the writer's buffer, so each sector the text touches is written once
+    if let Err(e) = lock_disk_writer().append_bytes(text.as_bytes()) {
+        return writeln!(out, "Error: {}", e);
+    }
+    Ok(())
+}
+
+fn hexappend(args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let hex = args.collect::<String>();
+    if hex.is_empty() {
+        return writeln!(out, "Error: missing hex bytes");
+    }
+    let bytes = match parse_hex(&hex) {
+        Ok(bytes) => bytes,
+        Err(HexError::OddLength) => return writeln!(out, "Error: odd number of hex digits"),
+        Err(HexError::InvalidDigit(c)) => return writeln!(out, "Error: {} is not a hex digit", c),
+    };
+    if let Some(i) = bytes.iter().position(|b| *b == 0) {
+        return writeln!(out, "Error: byte {} is 00, which would end the text on the disk", i);
+    }
+    if let Err(e) = lock_disk_writer().append_bytes(&bytes) {
+        return writeln!(out, "Error: {}", e);
+    }
+    writeln!(out, "Appended {} bytes", bytes.len())
+}
+
+/// A sector `copy` or `fill` works on: on the disk, or in the `RAM` scratch sectors when
+/// written as `ram:lba`.
+#[derive(Clone, Copy)]
+struct SectorAddress {
+    ram: bool,
+    lba: u32,
+}
+
+impl SectorAddress {
+    fn parse(s: &str) -> Option<SectorAddress> {
+        match s.strip_prefix("ram:") {
+            Some(lba) => Some(SectorAddress { ram: true, lba: lba.parse().ok()? }),
+            None => Some(SectorAddress { ram: false, lba: s.parse().ok()? }),
+        }
+    }
+
+    /// The sector `offset` sectors after this one, on the same device.
+    fn plus(self, offset: u32) -> SectorAddress {
+        SectorAddress { lba: self.lba + offset, ..self }
+    }
+
+    /// Reads `count` sectors from here, at most 256.
+    async fn read(self, buf: &mut [u16], count: u32) -> Result<(), DiskError> {
+        match self.ram {
+            true => RAM.lock().read_sectors(buf, self.lba as u64, count),
+            // 256 is sent as 0
+            false => irq::read_async(buf, self.lba, count as u8).await,
+        }
+    }
+
+    /// Writes `count` sectors from here, at most 256.
+    async fn write(self, data: &mut [u16], count: u32) -> Result<(), DiskError> {
+        match self.ram {
+            true => RAM.lock().write_sectors(data, self.lba as u64, count),
+            false => irq::lock_driver_async().await.write(data, self.lba, count as u8),
+        }
+    }
+}
+
+impl fmt::Display for SectorAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ram {
+            true => write!(f, "ram:{}", self.lba),
+            false => write!(f, "{}", self.lba),
+        }
+    }
+}
+
+/// Why a command can't write the raw sectors it was given, see `check_sector_writes`.
+enum SectorWriteError {
+    NotWritable(DiskError),
+    NoAnswer,
+    /// The disk ends before the sectors do; it has this many.
+    TooSmall(u32),
+    /// The sectors run past `RAM_SECTORS`.
+    PastRam,
+}
+
+impl fmt::Display for SectorWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectorWriteError::NotWritable(e) => write!(f, "the disk won't take writes: {}", e),
+            SectorWriteError::NoAnswer => write!(f, "the disk stopped answering"),
+            SectorWriteError::TooSmall(capacity) => write!(f, "the disk only has {} sectors", capacity),
+            SectorWriteError::PastRam => write!(f, "ram only has {} sectors", RAM_SECTORS),
+        }
+    }
+}
+
+/// Checks that the disk takes writes and has the `count` sectors from each of `addresses`,
+/// and that those in ram fit in it, before `fill`, `copy` or `benchmark` write to them.
+async fn check_sector_writes(addresses: &[SectorAddress], count: u32) -> Result<(), SectorWriteError> {
+    let fits = |address: &SectorAddress, capacity: u64| address.lba as u64 + count as u64 <= capacity;
+    if addresses.iter().any(|address| address.ram && !fits(address, RAM_SECTORS)) {
+        return Err(SectorWriteError::PastRam);
+    }
+    if addresses.iter().all(|address| address.ram) {
+        return Ok(());
+    }
+    let capacity = {
+        let mut driver = irq::lock_driver_async().await;
+        driver.check_writable().map_err(SectorWriteError::NotWritable)?;
+        match driver.identify() {
+            Identify::Ata(info) => info.sectors,
+            _ => return Err(SectorWriteError::NoAnswer),
+        }
+    };
+    if addresses.iter().any(|address| !address.ram && !fits(address, capacity as u64)) {
+        return Err(SectorWriteError::TooSmall(capacity));
+    }
+    Ok(())
+}
+
+async fn fill(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let force = take_force_flag(&mut args);
+    let lba = match args.next().map(SectorAddress::parse) {
+        Some(Some(lba)) => lba,
+        Some(None) => return writeln!(out, "Error: invalid LBA"),
+        None => return writeln!(out, "Error: missing LBA"),
+    };
+    let count = match args.next().map(str::parse::<u32>) {
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => return writeln!(out, "Error: invalid sector count"),
+        None => return writeln!(out, "Error: missing sector count"),
+    };
+    let byte = match args.next().map(parse_byte) {
+        Some(Some(byte)) => byte,
+        Some(None) => return writeln!(out, "Error: the byte must be 0 to 255, or 0x00 to 0xff"),
+        None => return writeln!(out, "Error: missing byte"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 3 arguments expected");
+    }
+
+    if let Err(e) = check_sector_writes(&[lba], count).await {
+        return writeln!(out, "Error: {}", e);
+    }
+    if !force {
+        ask_confirmation(&format!("sectors {} to {}", lba, lba.plus(count - 1)), format!("fill -f {} {} {}", lba, count, byte));
+        return Ok(());
+    }
+
+    let mut buf = vec![u16::from_le_bytes([byte, byte]); 256 * FILL_CHUNK as usize];
+    let mut done = 0;
+    while done < count {
+        if cancel::checkpoint().await.is_err() {
+            return writeln!(out, "Filled {} sectors before being canceled", done);
+        }
+        let chunk = (count - done).min(FILL_CHUNK as u32);
+        if let Err(e) = lba.plus(done).write(&mut buf[..256 * chunk as usize], chunk).await {
+            return writeln!(out, "Error: {} at LBA {}", e, lba.plus(done));
+        }
+        done += chunk;
+    }
+    writeln!(out, "Filled {} sectors from LBA {} with {:#04x}", count, lba, byte)
+}
+
+async fn copy(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let force = take_force_flag(&mut args);
+    let mut lbas = [SectorAddress { ram: false, lba: 0 }; 2];
+    for (lba, name) in lbas.iter_mut().zip(["source", "destination"]) {
+        *lba = match args.next().map(SectorAddress::parse) {
+            Some(Some(lba)) => lba,
+            Some(None) => return writeln!(out, "Error: invalid {} LBA", name),
+            None => return writeln!(out, "Error: missing {} LBA", name),
+        };
+    }
+    let [from, to] = lbas;
+    let count: u32 = match args.next().map(str::parse::<u32>) {
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => return writeln!(out, "Error: invalid sector count"),
+        None => return writeln!(out, "Error: missing sector count"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 3 arguments expected");
+    }
+
+    if let Err(e) = check_sector_writes(&[from, to], count).await {
+        return writeln!(out, "Error: {}", e);
+    }
+    if !force {
+        ask_confirmation(&format!("sectors {} to {}", to, to.plus(count - 1)), format!("copy -f {} {} {}", from, to, count));
+        return Ok(());
+    }
+
+    // like memmove, copying onto a later part of the source has to start from the end, or the
+    // source would be overwritten before it's read
+    let backwards = to.ram == from.ram && to.lba > from.lba && to.lba < from.lba + count;
+    let mut buf = vec![0; 256 * COPY_CHUNK as usize];
+    let mut done = 0;
+    while done < count {
+        if cancel::checkpoint().await.is_err() {
+            let copied = if backwards { count - done..count } else { 0..done };
+            if copied.is_empty() {
+                return writeln!(out, "Canceled before any sectors were copied");
+            }
+            return writeln!(out, "Copied {} of {} sectors before being canceled, to LBA {} to {}",
+                done, count, to.plus(copied.start), to.plus(copied.end - 1));
+        }
+        let chunk = (count - done).min(COPY_CHUNK as u32);
+        let offset = if backwards { count - done - chunk } else { done };
+        let words = &mut buf[..256 * chunk as usize];
+        if let Err(e) = from.plus(offset).read(words, chunk).await {
+            return writeln!(out, "Error: {} at LBA {}", e, from.plus(offset));
+        }
+        if let Err(e) = to.plus(offset).write(words, chunk).await {
+            return writeln!(out, "Error: {} at LBA {}", e, to.plus(offset));
+        }
+        let before = done;
+        done += chunk;
+        if done < count && done / COPY_PROGRESS_SECTORS != before / COPY_PROGRESS_SECTORS {
+            writeln!(out, "  {} of {} sectors ({}%)", done, count, done as u64 * 100 / count as u64)?;
+        }
+    }
+    writeln!(out, "Copied {} sectors from LBA {} to LBA {}", count, from, to)
+}
+
+/// Parses a byte given in decimal, or in hex after 0x.
+fn parse_byte(s: &str) -> Option<u8> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[test_case]
+fn test_sector_address() {
+    let address = SectorAddress::parse("ram:12").unwrap();
+    assert!(address.ram && address.lba == 12);
+    assert_eq!(format!("{}", address.plus(3)), "ram:15");
+    assert!(!SectorAddress::parse("8").unwrap().ram);
+    assert!(SectorAddress::parse("ram:").is_none());
+    assert!(SectorAddress::parse("disk:8").is_none());
+}
+
+#[test_case]
+fn test_take_force_flag() {
+    let mut args = "-f 1 2".split_ascii_whitespace();
+    assert!(take_force_flag(&mut args));
+    assert_eq!(args.next(), Some("1"));
+    let mut args = "1 -f".split_ascii_whitespace();
+    assert!(!take_force_flag(&mut args));
+    assert_eq!(args.next(), Some("1"));
+}
+
+#[test_case]
+fn test_reads_keyboard() {
+    assert!(reads_keyboard("textedit"));
+    assert!(reads_keyboard("cat | more"));
+    assert!(reads_keyboard("fill 100 16 0"));
+    assert!(reads_keyboard("copy 8 100 16"));
+    assert!(!reads_keyboard("fill -f 100 16 0"));
+    assert!(!reads_keyboard("cat | tee"));
+    assert!(!reads_keyboard("wc"));
+    // past the first stage, and inside repeat
+    assert!(reads_keyboard("cat | foo | more"));
+    assert!(reads_keyboard("repeat 2 dclear"));
+    assert!(reads_keyboard("repeat 2 repeat 3 cat | more"));
+    assert!(!reads_keyboard("repeat 2 dclear -f"));
+    assert!(!reads_keyboard("echo more >> disk"));
+}
+
+#[test_case]
+fn test_sector_text_half_word() {
+    let mut buf = [0; 256];
+    buf[..2].copy_from_slice(&bytes_to_words(b"abc"));
+    assert_eq!(sector_text(&buf, 1, true), b"abc");
+    assert_eq!(sector_text(&buf, 1, false), b"ab");
+    assert!(sector_text(&buf, 0, false).is_empty());
+}
+
+#[test_case]
+fn test_parse_byte() {
+    assert_eq!(parse_byte("255"), Some(255));
+    assert_eq!(parse_byte("0xff"), Some(255));
+    assert_eq!(parse_byte("0X0a"), Some(10));
+    assert_eq!(parse_byte("256"), None);
+    assert_eq!(parse_byte("ff"), None);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum HexError {
+    OddLength,
+    InvalidDigit(char),
+}
+
+/// Parses pairs of hex digits into bytes, ignoring whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, HexError> {
+    let digits = hex.chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(HexError::InvalidDigit(c)))
+        .collect::<Result<Vec<u8>, HexError>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(HexError::OddLength);
+    }
+    Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+async fn benchmark(mut args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let sectors = match args.next() {
+        None => BENCHMARK_SECTORS,
+        Some(arg) => match arg.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                return writeln!(out, "Error: invalid sector count");
+            }
+        },
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    if let Err(e) = check_sector_writes(&[SectorAddress { ram: false, lba: BENCHMARK_START_LBA }], BENCHMARK_SCRATCH_SECTORS).await {
+        return writeln!(out, "Error: {}", e);
+    }
+
+    writeln!(out, "Benchmarking {} sectors in LBAs {} to {}", sectors, BENCHMARK_START_LBA,
+        BENCHMARK_START_LBA + BENCHMARK_SCRATCH_SECTORS - 1)?;
+    for chunk in [1, BENCHMARK_CHUNK] {
+        let (read_ticks, write_ticks) = match benchmark_pass(sectors, chunk, out).await {
+            Ok(ticks) => ticks,
+            Err(BenchmarkError::Output) => return Err(fmt::Error),
+            Err(_) => return Ok(()),
+        };
+        writeln!(out, "{} sector(s) per command:", chunk)?;
+        print_rate("read", sectors, read_ticks, out)?;
+        print_rate("write", sectors, write_ticks, out)?;
+    }
+    Ok(())
+}
+
+enum BenchmarkError {
+    Canceled,
+    // already reported
+    Disk,
+    Output,
+}
+
+/// Reads `sectors` sectors `chunk` at a time from the scratch sectors, wrapping around at their
+/// end, and writes each chunk straight back, returning the ticks spent reading and writing.
+async fn benchmark_pass(sectors: u32, chunk: u8, out: &mut dyn Write) -> Result<(u128, u128), BenchmarkError> {
+    let mut buf = vec![0; 256 * chunk as usize];
+    let mut read_ticks = 0;
+    let mut write_ticks = 0;
+    let mut done = 0;
+    while done < sectors {
+        cancel::checkpoint().await.map_err(|_| BenchmarkError::Canceled)?;
+        let offset = done % BENCHMARK_SCRATCH_SECTORS;
+        let count = (sectors - done).min(chunk as u32).min(BENCHMARK_SCRATCH_SECTORS - offset) as u8;
+        let lba = BENCHMARK_START_LBA + offset;
+        let words = &mut buf[..256 * count as usize];
+
+        // locked before the clock starts, so waiting for another task's transfer isn't timed
+        let mut driver = irq::lock_driver_async().await;
+        let start = read_timer();
+        let read = driver.read(words, lba, count);
+        read_ticks += ticks_since(start);
+        // the scratch sectors hold nothing, but writing back what was read keeps them stable
+        let start = read_timer();
+        let written = read.and_then(|_| driver.write(words, lba, count));
+        write_ticks += ticks_since(start);
+        drop(driver);
+        if let Err(e) = written {
+            writeln!(out, "Error: {} at LBA {}", e, lba).map_err(|_| BenchmarkError::Output)?;
+            return Err(BenchmarkError::Disk);
+        }
+
+        done += count as u32;
+    }
+    Ok((read_ticks, write_ticks))
+}
+
+fn print_rate(label: &str, sectors: u32, ticks: u128, out: &mut dyn Write) -> fmt::Result {
+    let ms = time::ticks_to_ms(ticks).max(1);
+    let bytes = sectors as u128 * 512;
+    // hundredths of a MB/s, to print two decimals without floats
+    let centi_mb_per_sec = bytes * 1000 * 100 / ms / 1_000_000;
+    writeln!(out, "  {}: {} ms, {}.{:02} MB/s", label, ms, centi_mb_per_sec / 100, centi_mb_per_sec % 100)
+}
+
+#[test_case]
+fn test_parse_color_ignores_case() {
+    assert_eq!(parse_color("BLACK"), Some(Color::Black));
+    assert_eq!(parse_color("black"), Some(Color::Black));
+    assert_eq!(parse_color("lightblue"), Some(Color::LightBlue));
+}
+
+#[test_case]
+fn test_parse_color_grey_alias() {
+    assert_eq!(parse_color("Grey"), Some(Color::LightGray));
+    assert_eq!(parse_color("LightGrey"), Some(Color::LightGray));
+    assert_eq!(parse_color("darkgrey"), Some(Color::DarkGray));
+}
+
+#[test_case]
+fn test_parse_color_numbers() {
+    assert_eq!(parse_color("0"), Some(Color::Black));
+    assert_eq!(parse_color("15"), Some(Color::White));
+    assert_eq!(parse_color("16"), None);
+}
+
+#[test_case]
+fn test_parse_color_hex() {
+    assert_eq!(parse_color("#000000"), Some(Color::Black));
+    assert_eq!(parse_color("#00ff00"), Some(Color::Green));
+    assert_eq!(parse_color("#FF5555"), Some(Color::LightRed));
+    assert_eq!(parse_color("#fffff0"), Some(Color::White));
+    assert_eq!(parse_color("#00ff0"), None);
+    assert_eq!(parse_color("#gg0000"), None);
+}
+
+#[test_case]
+fn test_parse_hex() {
+    assert_eq!(parse_hex("48 65 6c6C 6f"), Ok(vec![0x48, 0x65, 0x6c, 0x6c, 0x6f]));
+    assert_eq!(parse_hex("abc"), Err(HexError::OddLength));
+    assert_eq!(parse_hex("4g"), Err(HexError::InvalidDigit('g')));
+}
+
+#[test_case]
+fn test_expand_aliases() {
+    let mut aliases = BTreeMap::new();
+    aliases.insert(String::from("hi"), String::from("echo hello"));
+    aliases.insert(String::from("greet"), String::from("hi there"));
+    assert_eq!(expand_aliases("greet you", &aliases).as_deref(), Some("echo hello there you"));
+    assert_eq!(expand_aliases("hi", &aliases).as_deref(), Some("echo hello"));
+    assert_eq!(expand_aliases("cat", &aliases).as_deref(), Some("cat"));
+}
+
+#[test_case]
+fn test_expand_aliases_loop() {
+    let mut aliases = BTreeMap::new();
+    aliases.insert(String::from("a"), String::from("b x"));
+    aliases.insert(String::from("b"), String::from("a y"));
+    assert_eq!(expand_aliases("a", &aliases), None);
+}
+
+#[test_case]
+fn test_echo_output() {
+    let mut out = crate::sink::StringSink::new();
+    echo("hello   world".split_ascii_whitespace(), &mut out).unwrap();
+    assert_eq!(out.as_str(), "hello world \n");
+}
+
+#[test_case]
+fn test_color_argument_errors() {
+    let mut out = crate::sink::StringSink::new();
+    color("".split_ascii_whitespace(), &mut out).unwrap();
+    color("red blue green".split_ascii_whitespace(), &mut out).unwrap();
+    color("red mauve".split_ascii_whitespace(), &mut out).unwrap();
+    assert_eq!(out.as_str(), "Error: missing foreground color\n\
+                              Error: only 2 arguments expected\n\
+                              Error: invalid color mauve\n");
+}
+
+#[test_case]
+fn test_help_lists_commands() {
+    let mut out = crate::sink::StringSink::new();
+    help("".split_ascii_whitespace(), &mut out).unwrap();
+    for command in ["cat", "echo", "help", "| tee", "| more", "> disk"] {
+        assert!(out.as_str().contains(command));
+    }
+}
+
+#[test_case]
+fn test_help_for_one_command() {
+    let mut out = crate::sink::StringSink::new();
+    help("color".split_ascii_whitespace(), &mut out).unwrap();
+    assert!(out.as_str().starts_with("usage: color [fg] [bg]"));
+    assert!(out.as_str().contains("#RRGGBB"));
+    assert!(!out.as_str().contains("dappend"));
+
+    let mut out = crate::sink::StringSink::new();
+    help("colr".split_ascii_whitespace(), &mut out).unwrap();
+    assert!(out.as_str().contains("did you mean color?"));
+}
+
+#[test_case]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("color", "color"), 0);
+    assert_eq!(edit_distance("colr", "color"), 1);
+    assert_eq!(edit_distance("cta", "cat"), 2);
+    assert_eq!(edit_distance("", "echo"), 4);
+}
+
+fn screenshot(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let mut buf = vec![0; SCREENSHOT_SECTORS as usize * 256];
+    buf[0] = BUFFER_WIDTH as u16;
+    buf[1] = BUFFER_HEIGHT as u16;
+    let cells = WRITER.lock().snapshot();
+    buf[2..2 + cells.len()].copy_from_slice(&cells);
+    match irq::lock_driver().and_then(|mut driver| driver.write(&mut buf, SCREENSHOT_LBA, SCREENSHOT_SECTORS)) {
+        Ok(()) => writeln!(out, "Saved screen to LBA {}", SCREENSHOT_LBA),
+        Err(e) => writeln!(out, "Error: {}", e),
+    }
+}
+
+fn reset(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    set_echo_input(true);
+    vga_buffer::reset_terminal();
+    // the output isn't paused anymore, so Scroll Lock's LED goes off
+    sync_leds();
+    Ok(())
+}
+
+fn loadscreen(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let mut buf = vec![0; SCREENSHOT_SECTORS as usize * 256];
+    if let Err(e) = irq::lock_driver().and_then(|mut driver| driver.read(&mut buf, SCREENSHOT_LBA, SCREENSHOT_SECTORS)) {
+        return writeln!(out, "Error: {}", e);
+    }
+    if buf[0] as usize != BUFFER_WIDTH || buf[1] as usize != BUFFER_HEIGHT {
+        return writeln!(out, "Error: no screenshot saved (found a {}x{} screen)", buf[0], buf[1]);
+    }
+    WRITER.lock().restore(&buf[2..2 + BUFFER_WIDTH * BUFFER_HEIGHT]);
+    Ok(())
+}
+
+fn alias(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    // definitions are handled by handle_command, before the line is split up
+    if let Some(arg) = args.next() {
+        return writeln!(out, "Error: expected name=command, not {}", arg);
+    }
+    let aliases: Vec<(String, String)> = ALIASES.lock().iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    for (name, value) in aliases {
+        writeln!(out, "{}={}", name, value)?;
+    }
+    Ok(())
+}
+
+fn unalias(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let name = match args.next() {
+        Some(name) => name,
+        None => return writeln!(out, "Error: missing alias name"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    if ALIASES.lock().remove(name).is_none() {
+        return writeln!(out, "Error: no alias named {}", name);
+    }
+    Ok(())
+}
+
+fn history(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    let lines: Vec<String> = HISTORY.lock().iter().map(String::from).collect();
+    for (i, line) in lines.iter().enumerate() {
+        writeln!(out, "{:>3}  {}", i + 1, line)?;
+    }
+    Ok(())
+}
+
+fn dmesg(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let level = match args.next() {
+        None => Level::Debug,
+        Some(arg) => match Level::parse(arg) {
+            Some(level) => level,
+            None => return writeln!(out, "Error: invalid log level {}", arg),
+        },
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    // the log is locked while this runs, so collect first and write afterwards
+    let mut lines = Vec::new();
+    klog::for_each(level, |entry| {
+        let ms = time::ticks_to_ms(entry.ticks);
+        lines.push(format!("[{:>5}.{:03}] {}: {}", ms / 1000, ms % 1000, entry.level.name(), entry.message()));
+    });
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn loglevel(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let level = match args.next() {
+        None => return writeln!(out, "log level is {} ({})", klog::threshold() as u8, klog::threshold().name()),
+        Some(arg) => match Level::parse(arg) {
+            Some(level) => level,
+            None => return writeln!(out, "Error: invalid log level {}", arg),
+        },
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    klog::set_threshold(level);
+    writeln!(out, "log level set to {} ({})", level as u8, level.name())
+}
+
+fn dretries(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let retries = match args.next().map(str::parse::<u8>) {
+        None => None,
+        Some(Ok(retries)) if retries <= MAX_RETRIES_LIMIT => Some(retries),
+        Some(_) => return writeln!(out, "Error: retries must be 0 to {}", MAX_RETRIES_LIMIT),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    let mut driver = match irq::lock_driver() {
+        Ok(driver) => driver,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    match retries {
+        Some(retries) => {
+            driver.set_max_retries(retries);
+            writeln!(out, "disk commands retried up to {} times", retries)
+        }
+        None => writeln!(out, "disk commands are retried up to {} times", driver.max_retries()),
+    }
+}
+
+fn statusline(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let enabled = match args.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(arg) => return writeln!(out, "Error: expected on or off, not {}", arg),
+        None => return writeln!(out, "Error: expected on or off"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_ok() {
+        return writeln!(out, "Error: statusline needs text mode, the framebuffer has no status line");
+    }
+    let mut writer = WRITER.lock();
+    writer.set_status_enabled(enabled);
+    writer.set_status_line("mode: command");
+    Ok(())
+}
+
+fn smart(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let disk = match args.next().map(str::parse::<u8>) {
+        None => None,
+        Some(Ok(disk @ 0..=1)) => Some(disk),
+        Some(_) => return writeln!(out, "Error: disk must be 0 or 1"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    let (disk, status) = {
+        let mut driver = match irq::lock_driver() {
+            Ok(driver) => driver,
+            Err(e) => return writeln!(out, "Error: {}", e),
+        };
+        let selected = driver.disk();
+        let disk = disk.unwrap_or(selected);
+        driver.change_disk(disk);
+        let status = driver.smart_status();
+        driver.change_disk(selected);
+        (disk, status)
+    };
+    match status {
+        SmartStatus::Ok => writeln!(out, "disk {}: OK", disk),
+        SmartStatus::Failing => writeln!(out, "disk {}: FAILING, the drive predicts it will fail soon", disk),
+        SmartStatus::Unsupported => writeln!(out, "disk {}: SMART isn't supported or is turned off", disk),
+        SmartStatus::Error(e) => writeln!(out, "Error: {}", e),
+    }
+}
+
+fn eject(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let disk = match args.next().map(str::parse::<u8>) {
+        None => None,
+        Some(Ok(disk @ 0..=1)) => Some(disk),
+        Some(_) => return writeln!(out, "Error: disk must be 0 or 1"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    let (disk, result) = {
+        let mut driver = match irq::lock_driver() {
+            Ok(driver) => driver,
+            Err(e) => return writeln!(out, "Error: {}", e),
+        };
+        let selected = driver.disk();
+        let disk = disk.unwrap_or(selected);
+        driver.change_disk(disk);
+        let result = driver.eject();
+        driver.change_disk(selected);
+        (disk, result)
+    };
+    match result {
+        Ok(()) => writeln!(out, "disk {}: ejected", disk),
+        Err(DiskError::Error(err)) if err.aborted_command() => writeln!(out, "disk {}: the drive refused, it has no removable media", disk),
+        Err(e) => writeln!(out, "Error: {}", e),
+    }
+}
+
+fn shutdown(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    // written while the disks still spin, `out` may be the disk
+    writeln!(out, "Parking the disks and powering off")?;
+    // the disk writer writes its sector out on every change, so there's nothing to flush,
+    // only the drives to quiesce
+    let results = match irq::lock_driver() {
+        Ok(mut driver) => park_disks(&mut driver),
+        Err(e) => {
+            // a task is in the middle of a transfer, which going off ends anyway
+            println!("disks not parked: {}", e);
+            Vec::new()
+        }
+    };
+    // on the screen, writing to the disk now would spin it back up
+    for (disk, result) in results {
+        match result {
+            Ok(()) => println!("disk {}: parked", disk),
+            Err(DiskError::Error(err)) if err.aborted_command() => println!("disk {}: standby isn't supported", disk),
+            Err(e) => println!("disk {}: {}", disk, e),
+        }
+    }
+    crate::power_off()
+}
+
+/// Parses a virtual address in hex, with or without 0x.
+fn parse_virt_addr(arg: Option<&str>) -> Result<VirtAddr, String> {
+    let addr = match arg {
+        Some(hex) => hex.strip_prefix("0x").unwrap_or(hex),
+        None => return Err(String::from("missing virtual address")),
+    };
+    match u64::from_str_radix(addr, 16).map(VirtAddr::try_new) {
+        Ok(Ok(addr)) => Ok(addr),
+        Ok(Err(_)) => Err(format!("{} isn't canonical, bits 48 to 63 must copy bit 47", addr)),
+        Err(_) => Err(format!("{} isn't a hex address", addr)),
+    }
+}
+
+/// Whether all 8 bytes from `addr` are mapped, so reading them won't page fault.
+fn u64_mapped(addr: VirtAddr) -> bool {
+    [0, 7].iter().all(|offset| {
+        VirtAddr::try_new(addr.as_u64().wrapping_add(*offset))
+            .ok()
+            .and_then(memory::translate)
+            .map_or(false, |translation| translation.phys.is_some())
+    })
+}
+
+fn peek(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let addr = match parse_virt_addr(args.next()) {
+        Ok(addr) => addr,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    if !addr.is_aligned(8u64) {
+        return writeln!(out, "Error: {:#x} is not 8-byte aligned", addr.as_u64());
+    }
+    if !u64_mapped(addr) {
+        return writeln!(out, "Error: {:#x} is not mapped", addr.as_u64());
+    }
+    let value = unsafe { core::ptr::read_volatile(addr.as_ptr::<u64>()) };
+    writeln!(out, "{:#x}: {:#018x}", addr.as_u64(), value)
+}
+
+fn poke(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let force = take_force_flag(&mut args);
+    let addr = match parse_virt_addr(args.next()) {
+        Ok(addr) => addr,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    let value = match args.next() {
+        Some(value) => value,
+        None => return writeln!(out, "Error: missing value"),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 2 arguments expected");
+    }
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    let value = match value {
+        Ok(value) => value,
+        Err(_) => return writeln!(out, "Error: the value must be a number, in hex with 0x"),
+    };
+    // even with -f: a volatile access has to be aligned, and the write would only page fault
+    if !addr.is_aligned(8u64) {
+        return writeln!(out, "Error: {:#x} is not 8-byte aligned", addr.as_u64());
+    }
+    if !u64_mapped(addr) {
+        return writeln!(out, "Error: {:#x} is not mapped", addr.as_u64());
+    }
+    let end = addr.as_u64().wrapping_add(7);
+    if !force && (memory::is_dangerous_addr(addr.as_u64()) || memory::is_dangerous_addr(end)) {
+        return writeln!(out, "Error: {:#x} is kernel code, a page table or a device, use -f to write anyway", addr.as_u64());
+    }
+    unsafe { core::ptr::write_volatile(addr.as_mut_ptr::<u64>(), value) };
+    Ok(())
+}
+
+fn kmap(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let arg = args.next();
+    if args.next().is_some() {
+        return writeln!(out, "Error: only 1 argument expected");
+    }
+    let addr = match parse_virt_addr(arg) {
+        Ok(addr) => addr,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    let translation = match memory::translate(addr) {
+        Some(translation) => translation,
+        None => return writeln!(out, "Error: the page tables can't be read yet"),
+    };
+    for entry in &translation.entries {
+        let flag = |flag: PageTableFlags, name: &'static str| entry.flags.contains(flag).then_some(name);
+        let names = [
+            flag(PageTableFlags::PRESENT, "present"),
+            flag(PageTableFlags::WRITABLE, "writable"),
+            flag(PageTableFlags::USER_ACCESSIBLE, "user"),
+            flag(PageTableFlags::HUGE_PAGE, "huge"),
+            flag(PageTableFlags::NO_EXECUTE, "no-execute"),
+        ];
+        let names: String = names.into_iter().flatten().intersperse(" ").collect();
+        writeln!(out, "level {} entry {:3}: {}", entry.level, entry.index, if names.is_empty() { "-" } else { &names })?;
+    }
+    match translation.phys {
+        Some(phys) => writeln!(out, "{:#x} -> {:#x}", addr.as_u64(), phys.as_u64()),
+        None => {
+            let level = translation.entries.last().map_or(4, |entry| entry.level);
+            writeln!(out, "{:#x} is not mapped (stops at level {})", addr.as_u64(), level)
+        }
+    }
+}
+
+fn sysinfo(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    writeln!(out, "CPU")?;
+    writeln!(out, "  vendor:   {}", cpu::vendor())?;
+    if let Some(brand) = cpu::brand() {
+        writeln!(out, "  model:    {}", brand)?;
+    }
+    writeln!(out, "  features: {}", cpu::features().into_iter().intersperse(" ").collect::<String>())?;
+
+    writeln!(out, "Memory")?;
+    if let Some(usable) = memory::usable_memory() {
+        writeln!(out, "  usable:   {} KiB", usable / 1024)?;
+    }
+    let heap_used = allocator::heap_used();
+    writeln!(out, "  heap:     {} KiB used, {} KiB free", heap_used / 1024, (allocator::HEAP_SIZE - heap_used) / 1024)?;
+    if memory::physical_memory_offset().is_some() {
+        writeln!(out, "  A20 line: {}", if cpu::a20_enabled() { "enabled" } else { "disabled" })?;
+    }
+
+    // a transfer under way is all that's said about the disks then, it can't be cut into
+    let disks = irq::lock_driver().map(|mut driver| {
+        let selected = driver.disk();
+        let mut disks = Vec::new();
+        for disk in 0..2 {
+            driver.change_disk(disk);
+            match driver.identify() {
+                Identify::NoDrive => {}
+                identify => disks.push((disk, identify)),
+            }
+        }
+        driver.change_disk(selected);
+        disks
+    });
+    match disks {
+        Ok(disks) if disks.is_empty() => {}
+        Ok(disks) => {
+            writeln!(out, "Disks")?;
+            for (disk, identify) in disks {
+                match identify {
+                    Identify::Ata(info) => writeln!(out, "  disk {}:   {} ({} KiB)", disk, info.model, info.sectors as u64 * info.sector_words as u64 * 2 / 1024)?,
+                    Identify::Atapi => writeln!(out, "  disk {}:   ATAPI device", disk)?,
+                    Identify::Error(err) => writeln!(out, "  disk {}:   {}", disk, DiskError::Error(err))?,
+                    Identify::NoDrive => {}
+                }
+            }
+            let (by_irq, by_polling) = irq::stats();
+            writeln!(out, "  reads:    {} sectors after an IRQ, {} by polling", by_irq, by_polling)?;
+        }
+        Err(e) => {
+            writeln!(out, "Disks")?;
+            writeln!(out, "  {}", e)?;
+        }
+    }
+
+    writeln!(out, "Time")?;
+    let uptime = time::ticks_to_ms(read_timer()) / 1000;
+    writeln!(out, "  uptime:   {}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60)?;
+    writeln!(out, "  PIT:      {} Hz", time::TICKS_PER_SECOND)?;
+    writeln!(out, "  TSC:      {} MHz", time::tsc_frequency() / 1_000_000)
+}
+
+fn swap(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let test = match args.next() {
+        None => false,
+        Some("test") => true,
+        Some(arg) => return writeln!(out, "Error: unexpected argument {}", arg),
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+    if !swap::is_initialized() {
+        return writeln!(out, "Error: the swap area isn't set up");
+    }
+
+    if test {
+        // as big as the heap, so it can only fit by spilling into the swappable area, and with
+        // more of its pages there than there are frames, so filling it forces pages out and
+        // reading it brings them back in
+        let words = allocator::HEAP_SIZE / 8;
+        let mut block: Vec<u64> = Vec::new();
+        if block.try_reserve_exact(words).is_err() {
+            return writeln!(out, "Error: no room for {} KiB, even with the swappable area", words * 8 / 1024);
+        }
+        let pattern = |i: usize| (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        block.extend((0..words).map(pattern));
+        let bad = block.iter().enumerate().filter(|(i, word)| **word != pattern(*i)).count();
+        drop(block);
+        if bad != 0 {
+            writeln!(out, "Error: {} of {} words came back wrong", bad, words)?;
+        } else {
+            writeln!(out, "{} KiB allocated, written and read back", words * 8 / 1024)?;
+        }
+    }
+    let stats = swap::stats();
+    writeln!(out, "area:      {} pages at {:#x}, {} frames, disk LBA {}", swap::SWAP_AREA_PAGES,
+        swap::SWAP_AREA_START, swap::SWAP_FRAMES, swap::SWAP_START_LBA)?;
+    writeln!(out, "faults:    {}", stats.faults)?;
+    writeln!(out, "swap ins:  {}", stats.swap_ins)?;
+    writeln!(out, "swap outs: {}", stats.swap_outs)
+}
+
+fn print_writer_state(out: &mut dyn Write) -> fmt::Result {
+    let end = lock_disk_writer().end();
+    writeln!(out, "current_lba:        {}", end.lba)?;
+    writeln!(out, "current_buf_offset: {}", end.offset)?;
+    writeln!(out, "is_in_word:         {}", end.is_in_word)?;
+    writeln!(out, "text length:        {} bytes", end.byte_offset())
+}
+
+fn dwstate(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    print_writer_state(out)
+}
+
+fn dwresync(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    if args.next().is_some() {
+        return writeln!(out, "Error: 0 arguments expected");
+    }
+    // the same scan as at boot; read errors are logged and stop the scan there
+    unsafe { lock_disk_writer().init() };
+    print_writer_state(out)
+}
+
+fn dcheck(mut args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let fix = match args.next() {
+        None => false,
+        Some("fix") => true,
+        Some(arg) => {
+            return writeln!(out, "Error: unexpected argument {}", arg);
+        }
+    };
+    if args.next().is_some() {
+        return writeln!(out, "Error: at most 1 argument expected");
+    }
+
+    // the same check as at boot, an unsigned disk's text is empty whatever its sectors hold
+    let (found, buf) = match signed_text_end() {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            writeln!(out, "the disk has no signature, its text is empty until written (mkfs formats it)")?;
+            (DiskEnd::at_byte(0), [0; 256])
+        }
+        Err((e, lba)) => {
+            return writeln!(out, "Error: {} reading LBA {}", e, lba);
+        }
+    };
+    let cached = lock_disk_writer().end();
+    let show = |end: DiskEnd| (end.lba, end.offset as u32 * 2 + end.is_in_word as u32);
+    writeln!(out, "data ends at LBA {}, byte {}", show(found).0, show(found).1)?;
+    writeln!(out, "writer is at LBA {}, byte {}", show(cached).0, show(cached).1)?;
+
+    // nothing should follow the terminator, otherwise cat and the editor lose that data
+    let mut stray = None;
+    let first_clear_word = found.offset as usize + 1;
+    if let Some(p) = buf[first_clear_word.min(256)..].iter().position(|w| *w != 0) {
+        stray = Some((found.lba, first_clear_word + p));
+    }
+    let mut sector = [0; 256];
+    let last_lba = found.lba.max(cached.lba) + DCHECK_EXTRA_SECTORS;
+    for lba in (found.lba + 1)..=last_lba {
+        if stray.is_some() { break; }
+        if let Err(e) = irq::lock_driver().and_then(|mut driver| driver.read(&mut sector, lba, 1)) {
+            writeln!(out, "Error: {} reading LBA {}", e, lba)?;
+            break;
+        }
+        if let Some(p) = sector.iter().position(|w| *w != 0) {
+            stray = Some((lba, p));
+        }
+    }
+    if let Some((lba, word)) = stray {
+        writeln!(out, "found data after the end, at LBA {}, byte {}", lba, word * 2)?;
+    }
+
+    if found == cached {
+        writeln!(out, "writer is in sync")?;
+    } else if fix {
+        lock_disk_writer().resync(found, buf);
+        writeln!(out, "writer moved to the end of the data")?;
+    } else {
+        writeln!(out, "writer is out of sync, run `dcheck fix` to move it to the end of the data")?;
+    }
+    Ok(())
+}
+
+/// Parses the `<lba> <count>` arguments of `recv` and `send`.
+fn parse_sector_range(mut args: SplitAsciiWhitespace) -> Result<(u32, u8), &'static str> {
+    let lba = args.next().ok_or("missing LBA")?.parse::<u32>().map_err(|_| "invalid LBA")?;
+    let count = match args.next().ok_or("missing sector count")?.parse::<u8>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err("sector count must be between 1 and 255"),
+    };
+    if args.next().is_some() {
+        return Err("only 2 arguments expected");
+    }
+    Ok((lba, count))
+}
+
+/// The Adler-32 checksum used to frame serial transfers, the same as zlib's `adler32`.
+fn adler32(bytes: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    for byte in bytes {
+        a = (a + *byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+enum TransferError {
+    Canceled,
+    TimedOut { received: usize, expected: usize },
+    TooLong(usize),
+    BadChecksum,
+}
+
+async fn recv(args: SplitAsciiWhitespace<'_>, out: &mut dyn Write) -> fmt::Result {
+    let (lba, count) = match parse_sector_range(args) {
+        Ok(range) => range,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    writeln!(out, "Waiting for up to {} bytes on serial", count as usize * 512)?;
+    serial_input::set_raw(true);
+    let data = receive_frame(count as usize * 512).await;
+    serial_input::set_raw(false);
+    let data = match data {
+        Ok(data) => data,
+        Err(TransferError::Canceled) => return Ok(()),
+        Err(TransferError::TimedOut { received, expected }) => {
+            return writeln!(out, "Error: timed out after {} of {} bytes", received, expected);
+        }
+        Err(TransferError::TooLong(len)) => {
+            return writeln!(out, "Error: {} bytes don't fit in {} sectors", len, count);
+        }
+        Err(TransferError::BadChecksum) => return writeln!(out, "Error: checksum mismatch, nothing written"),
+    };
+
+    // zero padded up to whole sectors
+    let mut buf = bytes_to_words(&data);
+    buf.resize(count as usize * 256, 0);
+    if let Err(e) = irq::lock_driver_async().await.write(&mut buf, lba, count) {
+        return writeln!(out, "Error: {}", e);
+    }
+    writeln!(out, "Received {} bytes into LBA {}", data.len(), lba)
+}
+
+/// Reads a length, that many bytes and their checksum from the serial line.
+async fn receive_frame(max_len: usize) -> Result<Vec<u8>, TransferError> {
+    let mut header = [0; 4];
+    for i in 0..4 {
+        header[i] = serial_input::receive_byte(SERIAL_TIMEOUT_MS).await
+            .ok_or(TransferError::TimedOut { received: 0, expected: max_len })?;
+    }
+    let len = u32::from_le_bytes(header) as usize;
+    if len > max_len {
+        return Err(TransferError::TooLong(len));
+    }
+
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        if data.len() % 512 == 0 {
+            cancel::checkpoint().await.map_err(|_| TransferError::Canceled)?;
+        }
+        let byte = serial_input::receive_byte(SERIAL_TIMEOUT_MS).await
+            .ok_or(TransferError::TimedOut { received: data.len(), expected: len })?;
+        data.push(byte);
+    }
+
+    let mut trailer = [0; 4];
+    for i in 0..4 {
+        trailer[i] = serial_input::receive_byte(SERIAL_TIMEOUT_MS).await
+            .ok_or(TransferError::TimedOut { received: len, expected: len })?;
+    }
+    if u32::from_le_bytes(trailer) != adler32(&data) {
+        return Err(TransferError::BadChecksum);
+    }
+    Ok(data)
+}
+
+fn send(args: SplitAsciiWhitespace, out: &mut dyn Write) -> fmt::Result {
+    let (lba, count) = match parse_sector_range(args) {
+        Ok(range) => range,
+        Err(e) => return writeln!(out, "Error: {}", e),
+    };
+    let mut buf = vec![0; count as usize * 256];
+    if let Err(e) = irq::lock_driver().and_then(|mut driver| driver.read(&mut buf, lba, count)) {
+        return writeln!(out, "Error: {}", e);
+    }
+    let data = words_to_bytes(&buf);
+
+    let frame = (data.len() as u32).to_le_bytes().into_iter()
+        .chain(data.iter().copied())
+        .chain(adler32(&data).to_le_bytes());
+    for byte in frame {
+        serial::send_raw(byte);
+    }
+    writeln!(out, "Sent {} bytes from LBA {}", data.len(), lba)
+}
+
+#[test_case]
+fn test_adler32() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
@@ -0,0 +1,54 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::string::String;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{rtc, vga_buffer::{BUFFER_WIDTH, WRITER}};
+
+/// How often the clock task looks at the RTC. Well under a second, so no second is skipped
+/// even though the sleeps and the RTC's updates don't line up.
+const POLL_MS: u128 = 250;
+const CLOCK_WIDTH: usize = "HH:MM:SS".len();
+const CLOCK_COL: usize = BUFFER_WIDTH - CLOCK_WIDTH;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Shows or hides the clock. Hiding it blanks the corner right away.
+pub fn set_enabled(on: bool) {
+    let was_on = ENABLED.swap(on, Ordering::Relaxed);
+    if was_on && !on {
+        draw(&" ".repeat(CLOCK_WIDTH));
+    }
+}
+
+/// Keeps HH:MM:SS from the real-time clock in the top right corner of the screen while it's
+/// enabled, redrawing it whenever the second changes.
+///
+/// Scrolling carries the clock up and off the screen with the text, it's back by the next
+/// redraw. It sleeps in between, so it costs nothing while the system is idle.
+pub async fn clock() {
+    let mut shown = None;
+    loop {
+        if enabled() && crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_err() {
+            let now = rtc::read();
+            if shown != Some(now) {
+                let mut text = String::with_capacity(CLOCK_WIDTH);
+                let _ = write!(text, "{:02}:{:02}:{:02}", now.hour, now.minute, now.second);
+                draw(&text);
+                shown = Some(now);
+            }
+        } else {
+            shown = None;
+        }
+        super::sleep_ms(POLL_MS).await;
+    }
+}
+
+fn draw(text: &str) {
+    // held only for the few cells, and with interrupts off like every other print
+    without_interrupts(|| WRITER.lock().write_at(0, CLOCK_COL, text));
+}
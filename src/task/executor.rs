@@ -3,17 +3,38 @@ use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 
+/// How many tasks can be spawned through a `Spawner` before the executor takes them in.
+const MAX_NEW_TASKS: usize = 16;
+
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
     task_queue: Arc<ArrayQueue<TaskId>>,
+    new_tasks: Arc<ArrayQueue<Task>>,
     waker_cache: BTreeMap<TaskId, Waker>,
 }
 
+/// Spawns tasks onto an executor that's already running, get one from `Executor::spawner`.
+///
+/// Tasks can't reach the executor itself while it polls them, so this hands new tasks over
+/// through a queue the executor empties before each round of polling.
+#[derive(Clone)]
+pub struct Spawner {
+    new_tasks: Arc<ArrayQueue<Task>>,
+}
+
+impl Spawner {
+    /// Queues `task` to be started, giving it back if too many are already waiting.
+    pub fn spawn(&self, task: Task) -> Result<(), Task> {
+        self.new_tasks.push(task).map_err(|e| e.0)
+    }
+}
+
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
+            new_tasks: Arc::new(ArrayQueue::new(MAX_NEW_TASKS)),
             waker_cache: BTreeMap::new(),
         }
     }
@@ -26,6 +47,10 @@ impl Executor {
         self.task_queue.push(task_id).expect("queue full");
     }
 
+    pub fn spawner(&self) -> Spawner {
+        Spawner { new_tasks: self.new_tasks.clone() }
+    }
+
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready_tasks();
@@ -34,11 +59,16 @@ impl Executor {
     }
 
     fn run_ready_tasks(&mut self) {
+        while let Ok(task) = self.new_tasks.pop() {
+            self.spawn(task);
+        }
+
         // destructure `self` to avoid borrow checker errors
         let Self {
             tasks,
             task_queue,
             waker_cache,
+            ..
         } = self;
 
         while let Ok(task_id) = task_queue.pop() {
@@ -61,11 +91,18 @@ impl Executor {
         }
     }
 
+    /// Halts the CPU until the next interrupt if no task is ready, instead of spinning.
+    ///
+    /// The queues are checked with interrupts off: otherwise an interrupt could wake a task
+    /// between the check and the `hlt`, and that task would wait for the next interrupt (up to
+    /// a timer tick, or forever without one). `enable_and_hlt` is `sti; hlt`, and `sti` only
+    /// takes effect after the following instruction, so no interrupt can slip in between the
+    /// two either; a pending one ends the `hlt` right away.
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
         interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.task_queue.is_empty() && self.new_tasks.is_empty() {
             enable_and_hlt();
         } else {
             interrupts::enable();
@@ -100,3 +137,22 @@ impl Wake for TaskWaker {
         self.wake_task();
     }
 }
+
+#[test_case]
+fn test_spawner_starts_tasks_while_running() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        let spawned = spawner.spawn(Task::new(async { RAN.store(true, Ordering::Relaxed) }));
+        assert!(spawned.is_ok());
+    }));
+    executor.run_ready_tasks();
+    assert!(!RAN.load(Ordering::Relaxed));
+    // picked up on the next round
+    executor.run_ready_tasks();
+    assert!(RAN.load(Ordering::Relaxed));
+    assert!(executor.tasks.is_empty());
+}
@@ -0,0 +1,46 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{future::Future, sync::atomic::{AtomicU32, Ordering}};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::{executor::Spawner, Task};
+use crate::println;
+
+/// How often `wait` checks whether the job it waits for is done.
+pub const WAIT_POLL_MS: u128 = 100;
+
+lazy_static! {
+    // the command line of each background job that's still running, by id
+    static ref JOBS: Mutex<BTreeMap<u32, String>> = Mutex::new(BTreeMap::new());
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Starts `job` as a task of its own, listed as `command` until it finishes, and returns its id.
+///
+/// Returns `None` if the executor already has too many tasks waiting to start.
+pub fn spawn(spawner: &Spawner, command: String, job: impl Future<Output = ()> + 'static) -> Option<u32> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    JOBS.lock().insert(id, command);
+    let task = Task::new(async move {
+        job.await;
+        let command = JOBS.lock().remove(&id);
+        if let Some(command) = command {
+            println!("[{}] done: {}", id, command);
+        }
+    });
+    if spawner.spawn(task).is_err() {
+        JOBS.lock().remove(&id);
+        return None;
+    }
+    Some(id)
+}
+
+/// The id and command line of every background job that hasn't finished, oldest first.
+pub fn running() -> Vec<(u32, String)> {
+    JOBS.lock().iter().map(|(id, command)| (*id, command.clone())).collect()
+}
+
+pub fn is_running(id: u32) -> bool {
+    JOBS.lock().contains_key(&id)
+}
@@ -1,9 +1,14 @@
-use crate::{print, println, vga_buffer::{WRITER, BUFFER_WIDTH}, disk::pio};
+use crate::{klog, klog::Level, print, println, ps2, vga_buffer::{self, WRITER, BUFFER_WIDTH}, disk::{irq, pio, BlockDevice}};
+use alloc::{format, vec::Vec};
+use core::fmt;
+use super::cancel;
+use super::keymap::{Action, EDITOR_KEYMAP};
 use conquer_once::spin::OnceCell;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     task::{Context, Poll},
 };
 use crossbeam_queue::ArrayQueue;
@@ -11,36 +16,189 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+// scancodes the interrupt handler couldn't queue since the last report
+static DROPPED_SCANCODES: AtomicU64 = AtomicU64::new(0);
+/// How often `report_dropped_scancodes` looks for dropped scancodes.
+pub const DROP_REPORT_MS: u128 = 1000;
 static WAKER: AtomicWaker = AtomicWaker::new();
+// tracked here rather than in the decoder so Ctrl+C works while a command blocks the CLI task
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
+// the same goes for Scroll Lock, which pauses the output of a running command
+static SCROLL_LOCK_HELD: AtomicBool = AtomicBool::new(false);
+// only touched by the keyboard interrupt handler
+static REPEAT_FILTER: Mutex<RepeatFilter> = Mutex::new(RepeatFilter::new());
 
 /// Called by the keyboard interrupt handler
 ///
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
+    super::autopoweroff::note_input();
+    match scancode {
+        0x1D => CTRL_HELD.store(true, Ordering::Relaxed),
+        0x9D => CTRL_HELD.store(false, Ordering::Relaxed),
+        // Ctrl+C cancels the running command instead of being typed
+        0x2E if CTRL_HELD.load(Ordering::Relaxed) => {
+            cancel::request();
+            return;
+        }
+        // held down it repeats, only the first press toggles
+        0x46 => {
+            if !SCROLL_LOCK_HELD.swap(true, Ordering::Relaxed) {
+                vga_buffer::set_output_paused(!vga_buffer::output_paused());
+                // nothing to do about a keyboard that doesn't answer, and no logging in here
+                let _ = update_leds();
+            }
+        }
+        0xC6 => SCROLL_LOCK_HELD.store(false, Ordering::Relaxed),
+        // the keyboard answering a command, not a key
+        ps2::RESPONSE_ACK | ps2::RESPONSE_RESEND => {
+            ps2::record_response(scancode);
+            return;
+        }
+        _ => {}
+    }
+    match REPEAT_FILTER.lock().add(scancode) {
+        Filtered::Drop => {}
+        Filtered::Pass(scancode) => queue_scancode(scancode),
+        Filtered::PassExtended(scancode) => {
+            queue_scancode(0xE0);
+            queue_scancode(scancode);
+        }
+    }
+}
+
+fn queue_scancode(scancode: u8) {
+    // counted rather than printed: printing here could wait forever on a WRITER lock held by
+    // the code we interrupted, and would print once per lost byte
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
+            DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Logs how many scancodes were dropped, at most once every `DROP_REPORT_MS`.
+///
+/// Runs for as long as the kernel does, sleeping in between.
+pub async fn report_dropped_scancodes() {
+    loop {
+        super::sleep_ms(DROP_REPORT_MS).await;
+        let dropped = DROPPED_SCANCODES.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            klog!(Level::Warn, "dropped {} scancodes, the keyboard queue was full or not set up yet", dropped);
+        }
+    }
+}
+
+/// What `RepeatFilter::add` decided to do with a scancode.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Filtered {
+    Drop,
+    Pass(u8),
+    /// Pass the scancode on after an 0xE0 prefix.
+    PassExtended(u8),
+}
+
+/// Keeps auto-repeat of held modifier keys away from the decoder.
+///
+/// Holding Shift, Ctrl or Alt makes the keyboard send the key's make code over and over, which
+/// floods the scancode queue and, mixed with other keys, shows up as stuck modifiers. Only the
+/// first make code of a modifier is passed on until its break code arrives. The 0xE0 prefix is
+/// held back until the byte after it, so a dropped extended key doesn't leave the decoder
+/// expecting one. The fake shifts (0xE0 0x2A and friends) some keyboards send around the
+/// navigation keys are dropped too, since they only confuse the decoder.
+pub struct RepeatFilter {
+    // one bit per modifier, see `modifier_bit`
+    held: u8,
+    extended: bool,
+}
+
+impl RepeatFilter {
+    pub const fn new() -> RepeatFilter {
+        RepeatFilter { held: 0, extended: false }
+    }
+
+    pub fn add(&mut self, scancode: u8) -> Filtered {
+        if scancode == 0xE0 {
+            self.extended = true;
+            return Filtered::Drop;
+        }
+        let extended = core::mem::replace(&mut self.extended, false);
+        let pass = if extended { Filtered::PassExtended(scancode) } else { Filtered::Pass(scancode) };
+
+        if extended && matches!(scancode & 0x7F, 0x2A | 0x36) {
+            return Filtered::Drop;
+        }
+        let bit = match Self::modifier_bit(scancode & 0x7F, extended) {
+            Some(bit) => bit,
+            None => return pass,
+        };
+        if scancode & 0x80 != 0 {
+            self.held &= !bit;
+        } else if self.held & bit != 0 {
+            // auto-repeat of a modifier that's already down
+            return Filtered::Drop;
+        } else {
+            self.held |= bit;
+        }
+        pass
+    }
+
+    fn modifier_bit(code: u8, extended: bool) -> Option<u8> {
+        match (code, extended) {
+            (0x2A, false) => Some(1 << 0), // left shift
+            (0x36, false) => Some(1 << 1), // right shift
+            (0x1D, false) => Some(1 << 2), // left ctrl
+            (0x1D, true) => Some(1 << 3),  // right ctrl
+            (0x38, false) => Some(1 << 4), // left alt
+            (0x38, true) => Some(1 << 5),  // right alt
+            _ => None,
+        }
     }
 }
 
+/// The scancodes queued by the keyboard interrupt handler, as a stream.
+///
+/// There can only be one at a time, since there's one queue and one waker to wake its reader;
+/// a task that wants the keyboard while another has it gets `None` from `try_new`. Dropping the
+/// stream gives the keyboard back, and the queue outlives it, so scancodes typed in between
+/// wait for the next stream rather than being lost.
 pub struct ScancodeStream {
     _private: (),
 }
 
+// whether a `ScancodeStream` exists
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
 impl ScancodeStream {
+    /// Takes the keyboard, panicking if another stream already has it.
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
+        ScancodeStream::try_new().expect("only one ScancodeStream can exist at a time")
+    }
+
+    /// Takes the keyboard, or returns `None` if another stream already has it.
+    pub fn try_new() -> Option<Self> {
+        if STREAM_TAKEN.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        // made by the first stream and kept from then on
+        let _ = SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(100));
+        Some(ScancodeStream { _private: () })
+    }
+}
+
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        // a waker registered by this stream's task shouldn't be woken for the next one
+        WAKER.take();
+        STREAM_TAKEN.store(false, Ordering::Release);
     }
 }
 
@@ -68,148 +226,867 @@ impl Stream for ScancodeStream {
     }
 }
 
+/// Turns scancodes into keys, the one decoder every keyboard consumer goes through.
+///
+/// Set 1 sends the navigation keys as 0xE0 followed by a code that is shared with the keypad, so
+/// the prefix has to stay attached to its code; `RepeatFilter` makes sure it does. The arrows,
+/// Home/End, PageUp/PageDown, Insert and Delete all come out as `DecodedKey::RawKey`, the same
+/// keys `AnsiDecoder` produces for a serial terminal. The layout would type Delete as a DEL
+/// character, which is turned back into its raw key here. Ctrl+letter comes out as the matching
+/// control character (Ctrl+A is 0x01), like it does from a terminal.
+///
+/// The keypad types digits while Num Lock is on and works as those navigation keys while it's
+/// off. Num Lock is shared by every decoder, so it stays put when the editor is opened or left.
+pub struct KeyDecoder {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+}
+
+// on at boot, like the BIOS leaves it
+static NUM_LOCK: AtomicBool = AtomicBool::new(true);
+
+pub fn num_lock() -> bool {
+    NUM_LOCK.load(Ordering::Relaxed)
+}
+
+/// Sets Num Lock and its LED.
+pub fn set_num_lock(on: bool) {
+    NUM_LOCK.store(on, Ordering::Relaxed);
+    sync_leds();
+}
+
+/// Sets the LEDs again after a lock changed without its key, like `reset` resuming output.
+pub fn sync_leds() {
+    if let Err(e) = update_leds() {
+        klog!(Level::Debug, "couldn't set the keyboard LEDs: {}", e);
+    }
+}
+
+/// Lights the LEDs of the locks that are on: Num Lock, and Scroll Lock while output is paused.
+fn update_leds() -> Result<(), ps2::Ps2Error> {
+    let mut leds = 0;
+    if num_lock() {
+        leds |= ps2::LED_NUM_LOCK;
+    }
+    if vga_buffer::output_paused() {
+        leds |= ps2::LED_SCROLL_LOCK;
+    }
+    ps2::set_leds(leds)
+}
+
+/// What a keypad digit or its period types, or `None` for any other key.
+fn keypad_key(code: KeyCode, num_lock: bool) -> Option<DecodedKey> {
+    let (digit, navigation) = match code {
+        KeyCode::Numpad0 => ('0', KeyCode::Insert),
+        KeyCode::Numpad1 => ('1', KeyCode::End),
+        KeyCode::Numpad2 => ('2', KeyCode::ArrowDown),
+        KeyCode::Numpad3 => ('3', KeyCode::PageDown),
+        KeyCode::Numpad4 => ('4', KeyCode::ArrowLeft),
+        // nothing to navigate to, the keymaps ignore it
+        KeyCode::Numpad5 => ('5', KeyCode::Numpad5),
+        KeyCode::Numpad6 => ('6', KeyCode::ArrowRight),
+        KeyCode::Numpad7 => ('7', KeyCode::Home),
+        KeyCode::Numpad8 => ('8', KeyCode::ArrowUp),
+        KeyCode::Numpad9 => ('9', KeyCode::PageUp),
+        KeyCode::NumpadPeriod => ('.', KeyCode::Delete),
+        _ => return None,
+    };
+    Some(if num_lock { DecodedKey::Unicode(digit) } else { DecodedKey::RawKey(navigation) })
+}
+
+impl KeyDecoder {
+    pub fn new() -> KeyDecoder {
+        KeyDecoder { keyboard: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::MapLettersToUnicode) }
+    }
+
+    /// Feeds one scancode, returning a key once a full press has been seen.
+    pub fn add_scancode(&mut self, scancode: u8) -> Option<DecodedKey> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        // handled here rather than by the layout, which would keep its own Num Lock per decoder
+        if event.code == KeyCode::NumpadLock {
+            if event.state == KeyState::Down {
+                set_num_lock(!num_lock());
+            }
+            return None;
+        }
+        // already handled by `add_scancode`
+        if event.code == KeyCode::ScrollLock {
+            return None;
+        }
+        if let Some(key) = keypad_key(event.code, num_lock()) {
+            return (event.state == KeyState::Down).then_some(key);
+        }
+        match self.keyboard.process_keyevent(event)? {
+            DecodedKey::Unicode('\x7F') => Some(DecodedKey::RawKey(KeyCode::Delete)),
+            key => Some(key),
+        }
+    }
+}
+
 pub async fn print_keypresses() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut keyboard = KeyDecoder::new();
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => {
-                        if character as u32 == 8 {
-                            let mut writer = WRITER.lock();
-                            let cur_pos = writer.current_pos();
-                            let start = writer.cmd_start();
-                            let pos = |pos: (usize, usize)| pos.0 * BUFFER_WIDTH + pos.1;
-                            if pos(cur_pos) > pos(start) { writer.backspace(); }
-                        } else {
-                            print!("{}", character);
-                        }
-                    },
-                    DecodedKey::RawKey(key) => print!("{:?} ", key),
-                }
+        if let Some(key) = keyboard.add_scancode(scancode) {
+            match key {
+                DecodedKey::Unicode(character) => {
+                    if character as u32 == 8 {
+                        let mut writer = WRITER.lock();
+                        let cur_pos = writer.current_pos();
+                        let start = writer.cmd_start();
+                        let pos = |pos: (usize, usize)| pos.0 * BUFFER_WIDTH + pos.1;
+                        if pos(cur_pos) > pos(start) { writer.backspace(); }
+                    } else {
+                        print!("{}", character);
+                    }
+                },
+                DecodedKey::RawKey(key) => print!("{:?} ", key),
             }
         }
     }
 }
 
+/// First sector of the typed text. The sectors before it are kept for metadata about the disk,
+/// which nothing that reads or writes the text touches.
+pub const USER_DATA_START_LBA: u32 = 8;
+/// The text stops before this sector. The sectors from here on hold the swap space, the
+/// screenshot and the benchmark's scratch sectors, so the writer never grows into them.
+pub const TEXT_END_LBA: u32 = crate::swap::SWAP_START_LBA;
+/// The most text that fits before `TEXT_END_LBA`, leaving room for the zero that ends it.
+pub const MAX_TEXT_BYTES: u32 = (TEXT_END_LBA - USER_DATA_START_LBA) * 512 - 1;
+/// The metadata sector that starts with `DISK_SIGNATURE` on a disk formatted by `format_disk`.
+pub const SIGNATURE_LBA: u32 = 0;
+/// Marks a disk as holding text written by this kernel, anything else is treated as empty.
+pub const DISK_SIGNATURE: &[u8; 8] = b"MNOSFS1\0";
+
+/// The signature sector as written to disk: `DISK_SIGNATURE`, then zeroes.
+fn signature_sector() -> [u16; 256] {
+    let signature = crate::disk::bytes_to_words(DISK_SIGNATURE);
+    let mut sector = [0; 256];
+    sector[..signature.len()].copy_from_slice(&signature);
+    sector
+}
+
+/// Whether the disk starts with `DISK_SIGNATURE`.
+pub fn has_signature() -> Result<bool, pio::DiskError> {
+    let mut sector = [0; 256];
+    irq::lock_driver()?.read(&mut sector, SIGNATURE_LBA, 1)?;
+    let expected = signature_sector();
+    Ok(sector[..DISK_SIGNATURE.len() / 2] == expected[..DISK_SIGNATURE.len() / 2])
+}
+
+/// Writes the signature and empties the text, leaving the writer at its start.
+///
+/// Only the first sector of text is zeroed, which is enough to end it; what was after it stays
+/// on the disk but is never read.
+pub fn format_disk() -> Result<(), pio::DiskError> {
+    let mut writer = lock_disk_writer();
+    let mut blank = [0; 256];
+    irq::lock_driver()?.write(&mut blank, USER_DATA_START_LBA, 1)?;
+    irq::lock_driver()?.write(&mut signature_sector(), SIGNATURE_LBA, 1)?;
+    writer.resync(DiskEnd::at_byte(0), blank);
+    writer.needs_signature = false;
+    Ok(())
+}
+
+/// Where the text on the disk ends, as `DiskWriter::init` sees it, with the sector it ends in.
+/// `None` if the disk has no `DISK_SIGNATURE`, so its text is empty whatever the sectors hold.
+/// On a read error, returns the error and the LBA that couldn't be read.
+pub fn signed_text_end() -> Result<Option<(DiskEnd, [u16; 256])>, (pio::DiskError, u32)> {
+    match has_signature() {
+        Ok(true) => find_disk_end().map(Some),
+        Ok(false) => Ok(None),
+        Err(e) => Err((e, SIGNATURE_LBA)),
+    }
+}
+
+#[derive(Clone)]
 pub struct DiskWriter {
+    /// The sector being written to, counted from the start of the disk like every LBA.
     pub current_lba: u32,
     pub current_buf: [u16; 256],
     pub current_buf_offset: u16,
     pub is_in_word: bool,
+    /// Set while the disk is known to lack `DISK_SIGNATURE`; the first write signs it.
+    needs_signature: bool,
 }
 impl DiskWriter {
+    /// Finds the end of the text on disk and moves the writer there.
+    ///
+    /// A disk without `DISK_SIGNATURE` (blank, or written by something else) is treated as
+    /// empty rather than scanned, since where its first zero byte happens to be means nothing.
+    /// The signature is written along with the first text, so that text is found next boot.
+    /// A disk whose sectors aren't 512 bytes, which the text's layout assumes, is treated as
+    /// empty too; writing to it then fails rather than storing the wrong amount.
     pub unsafe fn init(&mut self) {
-        // kinda hacky, assume we never write a 0 into the disk ourselves
-        let mut lba = 0;
+        let sector_words = match irq::lock_driver() {
+            Ok(mut driver) => {
+                // learns the sector size, if there's a disk at all
+                driver.identify();
+                driver.sector_words()
+            }
+            Err(e) => {
+                // only at run time, by `dwresync`; the writer stays where it was
+                klog!(Level::Error, "{} looking for the end of the text", e);
+                return;
+            }
+        };
+        self.needs_signature = false;
+        if sector_words != pio::DEFAULT_SECTOR_WORDS {
+            klog!(Level::Error, "the disk has {} byte sectors, the text needs 512 byte ones", sector_words * 2);
+            self.resync(DiskEnd::at_byte(0), [0; 256]);
+            return;
+        }
+        let (end, buf) = match signed_text_end() {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                klog!(Level::Warn, "the disk has no signature, treating it as empty (mkfs formats it)");
+                self.needs_signature = true;
+                (DiskEnd::at_byte(0), [0; 256])
+            }
+            Err((e, lba)) => {
+                // can't see any further, treat the unreadable sector as the end of the data
+                klog!(Level::Error, "{} reading LBA {}", e, lba);
+                (DiskEnd { lba: lba.max(USER_DATA_START_LBA), offset: 0, is_in_word: false }, [0; 256])
+            }
+        };
+        klog!(Level::Info, "disk text ends at LBA {}, byte {} ({} bytes)", end.lba, end.offset as u32 * 2 + end.is_in_word as u32, end.byte_offset());
+        self.resync(end, buf);
+    }
+
+    /// Where the writer thinks the data ends.
+    pub fn end(&self) -> DiskEnd {
+        DiskEnd { lba: self.current_lba, offset: self.current_buf_offset, is_in_word: self.is_in_word }
+    }
+
+    /// Moves the writer to `end`, with `buf` holding the sector at `end.lba`.
+    pub fn resync(&mut self, end: DiskEnd, buf: [u16; 256]) {
+        self.current_lba = end.lba;
+        self.current_buf_offset = end.offset;
+        self.is_in_word = end.is_in_word;
+        self.current_buf = buf;
+    }
+
+    /// Appends bytes after the end of the data, writing out each sector as it fills up and the
+    /// last, partial sector once at the end. Zero bytes are skipped since they mark the end.
+    ///
+    /// Nothing is written if the text would grow past `MAX_TEXT_BYTES`. If a write fails, the
+    /// writer goes back to where the text ended before, so it never points past what's on disk.
+    pub fn append_bytes(&mut self, bytes: &[u8]) -> Result<(), EditError> {
+        let added = bytes.iter().filter(|b| **b != 0).count();
+        let len = u32::try_from(added).ok().and_then(|added| self.end().byte_offset().checked_add(added));
+        if len.map_or(true, |len| len > MAX_TEXT_BYTES) {
+            return Err(EditError::Full { max: MAX_TEXT_BYTES });
+        }
+        let before = self.clone();
+        if let Err(e) = self.write_bytes(bytes) {
+            *self = before;
+            // the old last sector may already be on disk with some of the new bytes in it, put
+            // it back so the text ends where the writer says again
+            if let Err(e) = self.flush() {
+                klog!(Level::Error, "{} restoring LBA {} after a failed append", e, self.current_lba);
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Does the writing for `append_bytes`, leaving the writer wherever it got to on an error.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), pio::DiskError> {
+        for &byte in bytes.iter().filter(|b| **b != 0) {
+            let off = self.current_buf_offset as usize;
+            if !self.is_in_word {
+                self.current_buf[off] |= byte as u16;
+            } else {
+                self.current_buf[off] |= (byte as u16) << 8; // set high bytes
+                self.current_buf_offset += 1;
+            }
+            self.is_in_word = !self.is_in_word;
+
+            if self.current_buf_offset == 256 {
+                // go to next sector
+                self.flush()?;
+                self.current_lba += 1;
+                self.current_buf_offset = 0;
+                self.is_in_word = false;
+                // nothing after the end of the text counts, whatever the disk holds there
+                self.current_buf = [0; 256];
+            }
+        }
+        self.flush()
+    }
+
+    /// Writes the cached sector out to disk.
+    pub fn flush(&mut self) -> Result<(), pio::DiskError> {
+        let lba = self.current_lba;
+        x86_64::instructions::interrupts::without_interrupts(||
+            irq::lock_driver()?.write(&mut self.current_buf, lba, 1))?;
+        self.sign()
+    }
+
+    /// Writes `DISK_SIGNATURE` if the disk doesn't have it yet. Called after the text is
+    /// written, so a failed write never leaves a signed disk with stale sectors for its text.
+    fn sign(&mut self) -> Result<(), pio::DiskError> {
+        if self.needs_signature {
+            x86_64::instructions::interrupts::without_interrupts(||
+                irq::lock_driver()?.write(&mut signature_sector(), SIGNATURE_LBA, 1))?;
+            self.needs_signature = false;
+        }
+        Ok(())
+    }
+
+    /// Inserts `bytes` at byte `offset` of the text, moving everything after it forward.
+    /// Zero bytes are skipped, like in `append_bytes`.
+    ///
+    /// Every sector from the one holding `offset` to the end of the text is rewritten, so
+    /// inserting near the start of a long text is slow.
+    pub fn insert_at(&mut self, offset: u32, bytes: &[u8]) -> Result<(), EditError> {
+        let bytes: Vec<u8> = bytes.iter().copied().filter(|byte| *byte != 0).collect();
+        self.splice(offset, 0, &bytes)
+    }
+
+    /// Removes `len` bytes of the text from byte `offset` on, moving everything after them back.
+    ///
+    /// Rewrites the same sectors as `insert_at` does.
+    pub fn delete_range(&mut self, offset: u32, len: u32) -> Result<(), EditError> {
+        self.splice(offset, len, &[])
+    }
+
+    /// Replaces `remove` bytes of the text from byte `offset` on with `insert`, rewriting the
+    /// sectors from `offset`'s to the end of the text and moving the writer to the new end.
+    fn splice(&mut self, offset: u32, remove: u32, insert: &[u8]) -> Result<(), EditError> {
+        let len = self.end().byte_offset();
+        if offset.checked_add(remove).map_or(true, |end| end > len) {
+            return Err(EditError::PastEnd { len });
+        }
+        if (len - remove) as usize + insert.len() > MAX_TEXT_BYTES as usize {
+            return Err(EditError::Full { max: MAX_TEXT_BYTES });
+        }
+        let first_lba = DiskEnd::at_byte(offset).lba;
+        // text bytes before `first_lba`
+        let base = (first_lba - USER_DATA_START_LBA) * 512;
+        let sectors = DiskEnd::at_byte(len).lba - first_lba + 1;
+        let mut words = alloc::vec![0; sectors as usize * 256];
+        x86_64::instructions::interrupts::without_interrupts(||
+            irq::lock_driver()?.read_sectors(&mut words, first_lba as u64, sectors))?;
+
+        let spliced = splice_sectors(crate::disk::words_to_bytes(&words), (len - base) as usize,
+            (offset - base) as usize, remove as usize, insert);
+        let mut words = crate::disk::bytes_to_words(&spliced);
+        let sectors = (spliced.len() / 512) as u32;
+        x86_64::instructions::interrupts::without_interrupts(||
+            irq::lock_driver()?.write_sectors(&mut words, first_lba as u64, sectors))?;
+        self.sign()?;
+
+        let end = DiskEnd::at_byte(len - remove + insert.len() as u32);
         let mut buf = [0; 256];
-        while {
-            pio::DRIVER.lock().read(&mut buf, lba, 1);
-            let last_written_pos = buf.iter().position(|v| *v == 0);
-            if let Some(p) = last_written_pos {
-                self.current_buf_offset = p as u16;
-                if self.current_buf_offset != 0 && (buf[self.current_buf_offset as usize - 1] >> 8) == 0 {
-                    self.current_buf_offset -= 1;
-                    self.is_in_word = true;
-                }
-                false
-            } else { true }
+        let at = (end.lba - first_lba) as usize * 256;
+        buf.copy_from_slice(&words[at..at + 256]);
+        self.resync(end, buf);
+        Ok(())
+    }
+}
+
+/// Why the disk writer couldn't change the text.
+#[derive(Debug, Clone, Copy)]
+pub enum EditError {
+    /// The bytes asked for aren't all in the text, which is `len` bytes long.
+    PastEnd { len: u32 },
+    /// The text would be longer than `max` bytes and reach into the sectors after it.
+    Full { max: u32 },
+    Disk(pio::DiskError),
+}
+
+impl From<pio::DiskError> for EditError {
+    fn from(e: pio::DiskError) -> EditError {
+        EditError::Disk(e)
+    }
+}
 
-        } {
-            lba += 1;
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::PastEnd { len } => write!(f, "past the end of the text, which is {} bytes", len),
+            EditError::Full { max } => write!(f, "the text can't be longer than {} bytes", max),
+            EditError::Disk(e) => write!(f, "{}", e),
         }
-        self.current_lba = lba;
-        self.current_buf = buf;
+    }
+}
+
+/// Replaces `remove` bytes at `at` in the first `len` bytes of `sectors` with `insert`.
+///
+/// Returns whole sectors again, zero-filled after the new text and long enough to cover the old
+/// text too, so no stale byte is left behind the terminator.
+fn splice_sectors(sectors: Vec<u8>, len: usize, at: usize, remove: usize, insert: &[u8]) -> Vec<u8> {
+    let mut text = sectors;
+    text.truncate(len);
+    text.splice(at..at + remove, insert.iter().copied());
+    let sectors = len.max(text.len()) / 512 + 1;
+    text.resize(sectors * 512, 0);
+    text
+}
+
+/// A position in the zero-terminated data on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskEnd {
+    pub lba: u32,
+    /// Word offset into the sector.
+    pub offset: u16,
+    /// Whether the low byte of the word at `offset` is already used.
+    pub is_in_word: bool,
+}
+
+impl DiskEnd {
+    /// Where byte `offset` of the text is on the disk.
+    pub fn at_byte(offset: u32) -> DiskEnd {
+        DiskEnd {
+            lba: USER_DATA_START_LBA + offset / 512,
+            offset: (offset % 512 / 2) as u16,
+            is_in_word: offset % 2 == 1,
+        }
+    }
+
+    /// How many bytes of text come before this position.
+    pub fn byte_offset(&self) -> u32 {
+        (self.lba - USER_DATA_START_LBA) * 512 + self.offset as u32 * 2 + self.is_in_word as u32
+    }
+}
+
+/// Scans from `USER_DATA_START_LBA` for the first zero byte, which marks the end of the data,
+/// and returns it along with the contents of the sector it's in. On a read error, returns the
+/// error and the LBA that couldn't be read.
+pub fn find_disk_end() -> Result<(DiskEnd, [u16; 256]), (pio::DiskError, u32)> {
+    let mut driver = irq::lock_driver().map_err(|e| (e, USER_DATA_START_LBA))?;
+    find_text_end(&mut *driver)
+}
+
+/// `find_disk_end` on any block device.
+fn find_text_end(disk: &mut impl BlockDevice) -> Result<(DiskEnd, [u16; 256]), (pio::DiskError, u32)> {
+    // kinda hacky, assume we never write a 0 into the disk ourselves
+    let mut lba = USER_DATA_START_LBA;
+    let mut buf = [0; 256];
+    loop {
+        disk.read_sectors(&mut buf, lba as u64, 1).map_err(|e| (e, lba))?;
+        if let Some(p) = buf.iter().position(|v| *v == 0) {
+            let mut end = DiskEnd { lba, offset: p as u16, is_in_word: false };
+            if end.offset != 0 && (buf[end.offset as usize - 1] >> 8) == 0 {
+                end.offset -= 1;
+                end.is_in_word = true;
+            }
+            return Ok((end, buf));
+        }
+        lba += 1;
     }
 }
 
 lazy_static! {
+    /// Where typed text goes on the disk. Lock it with `lock_disk_writer`, which checks the
+    /// lock order.
     pub static ref DISK_WRITER: Mutex<DiskWriter> = Mutex::new(DiskWriter { 
-        current_lba: 0, 
+        current_lba: USER_DATA_START_LBA, 
         current_buf: [0; 256], 
         current_buf_offset: 0, 
         is_in_word: false,
+        needs_signature: false,
     });
 }
 
+/// Locks `DISK_WRITER`, checking the lock order in debug (and test) builds.
+///
+/// The order is `DISK_WRITER` before `pio::DRIVER`: the writer's methods lock the driver
+/// themselves, so taking the writer while already holding the driver would deadlock as soon as
+/// one of them runs. Never lock the writer with the driver held.
+pub fn lock_disk_writer() -> MutexGuard<'static, DiskWriter> {
+    debug_assert!(pio::DRIVER.try_lock().is_some(),
+        "lock order violated: DISK_WRITER locked while DRIVER is held");
+    DISK_WRITER.lock()
+}
+
 pub async fn text_editor() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut keyboard = KeyDecoder::new();
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                text_edit_process_key(key);
-            }
+        if let Some(key) = keyboard.add_scancode(scancode) {
+            text_edit_process_key(key);
         }
     }
 }
 
 pub fn text_edit_process_key(key: DecodedKey) {
-    match key {
-        DecodedKey::Unicode(character) => {
-            if character as u32 == 8 {
-                // this isn't great, but it kinda works so we'll roll with it
-                WRITER.lock().backspace();
-                let mut writer = DISK_WRITER.lock();
-                // first, try to move back
-                if writer.current_buf_offset == 0 && !writer.is_in_word {
-                    if writer.current_lba != 0 {
-                        writer.current_lba -= 1;
-                        writer.current_buf_offset = 255;
-                        let lba = writer.current_lba;
-                        x86_64::instructions::interrupts::without_interrupts(||
-                            pio::DRIVER.lock().read(&mut writer.current_buf, lba, 1));
-                    }
-                }
-                else if !writer.is_in_word { writer.current_buf_offset -= 1; }
-                writer.is_in_word = !writer.is_in_word;
-                
-                let off = writer.current_buf_offset as usize;
-                if !writer.is_in_word {
-                    writer.current_buf[off] = 0;
-                } else {
-                    writer.current_buf[off] &= 0xFF; // clear high bytes
+    edit_key(key);
+    update_editor_status();
+}
+
+/// Where the editor puts what's typed when it was opened in the middle of the text, see
+/// `edit_at`. `None` while it types at the end, through the disk writer.
+static EDIT_CURSOR: Mutex<Option<EditCursor>> = Mutex::new(None);
+/// How much of the text after the cursor the editor shows, so the cursor stays on screen.
+const EDIT_BYTES_AFTER_CURSOR: u32 = 8 * BUFFER_WIDTH as u32;
+
+#[derive(Debug, Clone, Copy)]
+struct EditCursor {
+    /// The byte offset of the first byte on screen.
+    view_start: u32,
+    /// The byte offset typed characters go in at.
+    at: u32,
+}
+
+/// Moves where the editor types to byte `offset` of the text, showing the text from there on,
+/// or back to the end of the text for `None`.
+///
+/// Away from the end, each key rewrites the text from the cursor on with `DiskWriter::splice`
+/// and redraws the screen, so typing gets slower the more text follows. Backspace stops at
+/// `offset`, since the text before it isn't shown. The screen is left alone if the text
+/// can't be read.
+pub fn edit_at(offset: Option<u32>) -> Result<(), pio::DiskError> {
+    let cursor = offset.map(|at| EditCursor { view_start: at, at });
+    if let Some(cursor) = cursor {
+        draw_editor(cursor)?;
+    }
+    *EDIT_CURSOR.lock() = cursor;
+    Ok(())
+}
+
+/// Clears the screen and shows the text from `cursor.view_start`, marking where the cursor is.
+fn draw_editor(cursor: EditCursor) -> Result<(), pio::DiskError> {
+    let len = lock_disk_writer().end().byte_offset();
+    // a background job may have shortened the text
+    let at = cursor.at.min(len);
+    let view_start = cursor.view_start.min(at);
+    let shown_end = len.min(at + EDIT_BYTES_AFTER_CURSOR);
+    let first_lba = DiskEnd::at_byte(view_start).lba;
+    let sectors = DiskEnd::at_byte(shown_end).lba - first_lba + 1;
+    let mut words = alloc::vec![0; sectors as usize * 256];
+    x86_64::instructions::interrupts::without_interrupts(||
+        irq::lock_driver()?.read_sectors(&mut words, first_lba as u64, sectors))?;
+    let bytes = crate::disk::words_to_bytes(&words);
+    let base = (first_lba - USER_DATA_START_LBA) * 512;
+    let text = |from: u32, to: u32| -> alloc::string::String {
+        bytes[(from - base) as usize..(to - base) as usize].iter().map(|byte| *byte as char).collect()
+    };
+
+    vga_buffer::reset_screen();
+    print!("{}", text(view_start, at));
+    // the character under the cursor is marked, a blank one at a line's or the text's end
+    let after = match bytes.get((at - base) as usize) {
+        Some(&byte) if at < shown_end && byte != b'\n' => {
+            vga_buffer::print_inverted(format_args!("{}", byte as char));
+            at + 1
+        }
+        _ => {
+            vga_buffer::print_inverted(format_args!(" "));
+            at
+        }
+    };
+    print!("{}", text(after, shown_end));
+    Ok(())
+}
+
+/// `edit_key` while the editor is away from the end of the text.
+fn edit_key_at(mut cursor: EditCursor, key: DecodedKey) {
+    let result = match EDITOR_KEYMAP.action(key) {
+        Action::Backspace if cursor.at > cursor.view_start => {
+            cursor.at -= 1;
+            lock_disk_writer().delete_range(cursor.at, 1)
+        }
+        Action::InsertChar(character) => match u8::try_from(character) {
+            Ok(byte) => {
+                cursor.at += 1;
+                lock_disk_writer().insert_at(cursor.at - 1, &[byte])
+            }
+            Err(_) => return,
+        },
+        _ => return,
+    };
+    match result {
+        Ok(()) => {}
+        // the sectors after the text are kept for other things
+        Err(EditError::Full { .. }) => {
+            crate::speaker::beep();
+            return;
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    }
+    *EDIT_CURSOR.lock() = Some(cursor);
+    if let Err(e) = draw_editor(cursor) {
+        println!("Error: {}", e);
+    }
+}
+
+/// Shows where the editor is writing on the status line.
+pub fn update_editor_status() {
+    let cursor = *EDIT_CURSOR.lock();
+    let end = match cursor {
+        Some(cursor) => DiskEnd::at_byte(cursor.at),
+        None => lock_disk_writer().end(),
+    };
+    let status = format!("LBA {} offset {} | mode: edit | ESC: back to the terminal",
+        end.lba, end.offset as u32 * 2 + end.is_in_word as u32);
+    vga_buffer::set_status_line(&status);
+}
+
+fn edit_key(key: DecodedKey) {
+    let cursor = *EDIT_CURSOR.lock();
+    if let Some(cursor) = cursor {
+        return edit_key_at(cursor, key);
+    }
+    match EDITOR_KEYMAP.action(key) {
+        Action::Backspace => {
+            // this isn't great, but it kinda works so we'll roll with it
+            vga_buffer::backspace();
+            let mut writer = lock_disk_writer();
+            // first, try to move back
+            if writer.current_buf_offset == 0 && !writer.is_in_word {
+                // at the start of the text, there's nothing to erase
+                if writer.current_lba <= USER_DATA_START_LBA {
+                    return;
                 }
-                
-                // Flush buffer
+                writer.current_lba -= 1;
+                writer.current_buf_offset = 255;
                 let lba = writer.current_lba;
-                x86_64::instructions::interrupts::without_interrupts(||
-                    pio::DRIVER.lock().write(&mut writer.current_buf, lba, 1));
-            } else {
-                print!("{}", character);
-                let mut writer = DISK_WRITER.lock();
-                let off = writer.current_buf_offset as usize;
-                if !writer.is_in_word {
-                    writer.current_buf[off] |= character as u32 as u16;
-                } else {
-                    writer.current_buf[off] |= (character as u32 as u16) << 8; // set high bytes
+                let result = x86_64::instructions::interrupts::without_interrupts(||
+                    irq::lock_driver()?.read(&mut writer.current_buf, lba, 1));
+                if let Err(e) = result {
+                    // back at the start of the sector after, where the text ends
+                    writer.current_lba += 1;
+                    writer.current_buf_offset = 0;
+                    writer.current_buf = [0; 256];
+                    println!("Error: {}", e);
+                    return;
                 }
-                if writer.is_in_word { writer.current_buf_offset += 1; }
-                writer.is_in_word = !writer.is_in_word;
+            }
+            else if !writer.is_in_word { writer.current_buf_offset -= 1; }
+            writer.is_in_word = !writer.is_in_word;
+            
+            let off = writer.current_buf_offset as usize;
+            if !writer.is_in_word {
+                writer.current_buf[off] = 0;
+            } else {
+                writer.current_buf[off] &= 0xFF; // clear high bytes
+            }
+            
+            // Flush buffer
+            if let Err(e) = writer.flush() { println!("Error: {}", e); }
+        },
+        Action::InsertChar(character) => {
+            if lock_disk_writer().end().byte_offset() >= MAX_TEXT_BYTES {
+                // the sectors after the text are kept for other things
+                crate::speaker::beep();
+                return;
+            }
+            print!("{}", character);
+            let mut writer = lock_disk_writer();
+            let off = writer.current_buf_offset as usize;
+            if !writer.is_in_word {
+                writer.current_buf[off] |= character as u32 as u16;
+            } else {
+                writer.current_buf[off] |= (character as u32 as u16) << 8; // set high bytes
+            }
+            if writer.is_in_word { writer.current_buf_offset += 1; }
+            writer.is_in_word = !writer.is_in_word;
 
-                // Flush buffer (not much of a buffer I know)
-                let lba = writer.current_lba;
-                x86_64::instructions::interrupts::without_interrupts(||
-                    pio::DRIVER.lock().write(&mut writer.current_buf, lba, 1));
+            // Flush buffer (not much of a buffer I know)
+            if let Err(e) = writer.flush() { println!("Error: {}", e); }
 
+            
+            if writer.current_buf_offset == 256 {
+                // go to next sector
+                // first, output the current cached buf
                 
-                if writer.current_buf_offset == 256 {
-                    // go to next sector
-                    // first, output the current cached buf
-                    
-                    writer.current_lba += 1;
-                    writer.current_buf_offset = 0;
-                    writer.is_in_word = false;
-                    let lba = writer.current_lba;
-                    x86_64::instructions::interrupts::without_interrupts(||
-                        pio::DRIVER.lock().read(&mut writer.current_buf, lba, 1));
-                }
-                //println!("Leaving buffer step");
+                writer.current_lba += 1;
+                writer.current_buf_offset = 0;
+                writer.is_in_word = false;
+                // the zero that ends the text goes here now, replacing whatever the disk held
+                writer.current_buf = [0; 256];
+                if let Err(e) = writer.flush() { println!("Error: {}", e); }
             }
+            //println!("Leaving buffer step");
         },
-        DecodedKey::RawKey(_key) => {},
+        // anything else, Ctrl shortcuts included, would only end up on the disk
+        _ => {},
     }
 }
+
+#[test_case]
+fn test_decoder_extended_arrow() {
+    let mut decoder = KeyDecoder::new();
+    assert_eq!(decoder.add_scancode(0xE0), None);
+    assert_eq!(decoder.add_scancode(0x48), Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    // the break code doesn't produce a second key
+    assert_eq!(decoder.add_scancode(0xE0), None);
+    assert_eq!(decoder.add_scancode(0xC8), None);
+}
+
+#[test_case]
+fn test_decoder_navigation_keys() {
+    let mut decoder = KeyDecoder::new();
+    let keys = [
+        (0x47, KeyCode::Home), (0x4F, KeyCode::End), (0x49, KeyCode::PageUp),
+        (0x51, KeyCode::PageDown), (0x52, KeyCode::Insert), (0x53, KeyCode::Delete),
+        (0x4B, KeyCode::ArrowLeft), (0x4D, KeyCode::ArrowRight), (0x50, KeyCode::ArrowDown),
+    ];
+    for (code, key) in keys {
+        assert_eq!(decoder.add_scancode(0xE0), None);
+        assert_eq!(decoder.add_scancode(code), Some(DecodedKey::RawKey(key)));
+        assert_eq!(decoder.add_scancode(0xE0), None);
+        assert_eq!(decoder.add_scancode(code | 0x80), None);
+    }
+}
+
+#[test_case]
+fn test_scancode_stream_can_be_recreated() {
+    // the CLI task isn't running in tests, so the keyboard is free
+    let stream = ScancodeStream::new();
+    assert!(ScancodeStream::try_new().is_none());
+    drop(stream);
+    let again = ScancodeStream::try_new();
+    assert!(again.is_some());
+}
+
+#[test_case]
+fn test_keyboard_responses_are_not_queued() {
+    let stream = ScancodeStream::new();
+    let queue = SCANCODE_QUEUE.try_get().unwrap();
+    while queue.pop().is_ok() {}
+    add_scancode(ps2::RESPONSE_ACK);
+    add_scancode(ps2::RESPONSE_RESEND);
+    crate::assert_err!(queue.pop());
+    // so no key can come out of them either
+    add_scancode(0x1E);
+    assert_eq!(crate::assert_ok!(queue.pop()), 0x1E);
+    drop(stream);
+}
+
+#[test_case]
+fn test_signature_sector_layout() {
+    let sector = signature_sector();
+    // low byte first, like the text
+    assert_eq!(sector[0], u16::from_le_bytes([b'M', b'N']));
+    assert_eq!(sector[3], u16::from_le_bytes([b'1', 0]));
+    assert!(sector[4..].iter().all(|w| *w == 0));
+}
+
+#[test_case]
+fn test_splice_sectors_insert() {
+    let mut text = alloc::vec![b'a'; 510];
+    text.resize(512, 0);
+    // at the start
+    let spliced = splice_sectors(text.clone(), 510, 0, 0, b"xy");
+    assert_eq!(spliced.len(), 1024);
+    assert_eq!(&spliced[..3], b"xya");
+    assert_eq!(spliced[512], 0);
+    // in the middle, pushing the end over into the next sector
+    let spliced = splice_sectors(text.clone(), 510, 255, 0, b"xyz");
+    assert_eq!(&spliced[254..259], b"axyza");
+    assert_eq!(&spliced[512..514], [b'a', 0]);
+    // at the end, which fills the sector exactly so the next one holds the zero
+    let spliced = splice_sectors(text, 510, 510, 0, b"xy");
+    assert_eq!(&spliced[508..512], b"aaxy");
+    assert!(spliced[512..].iter().all(|byte| *byte == 0));
+}
+
+#[test_case]
+fn test_splice_sectors_delete() {
+    let mut text: alloc::vec::Vec<u8> = (0..600).map(|i| b'a' + (i % 26) as u8).collect();
+    text.resize(1024, 0);
+    // from the start
+    let spliced = splice_sectors(text.clone(), 600, 0, 2, &[]);
+    assert_eq!(spliced.len(), 1024);
+    assert_eq!(&spliced[..2], b"cd");
+    assert_eq!(spliced[598], 0);
+    // across the sector boundary, pulling the end back into the first sector
+    let spliced = splice_sectors(text.clone(), 600, 500, 100, &[]);
+    assert_eq!(spliced[499], text[499]);
+    assert!(spliced[500..].iter().all(|byte| *byte == 0));
+    // to the end
+    let spliced = splice_sectors(text.clone(), 600, 550, 50, &[]);
+    assert_eq!(&spliced[..550], &text[..550]);
+    assert!(spliced[550..].iter().all(|byte| *byte == 0));
+}
+
+#[test_case]
+fn test_text_ends_before_reserved_sectors() {
+    assert_eq!(DiskEnd::at_byte(MAX_TEXT_BYTES).lba, TEXT_END_LBA - 1);
+    // the zero after a full text is still in the text's sectors
+    assert_eq!(DiskEnd::at_byte(MAX_TEXT_BYTES).offset, 255);
+    assert!(DiskEnd::at_byte(MAX_TEXT_BYTES).is_in_word);
+}
+
+#[test_case]
+fn test_disk_end_byte_offsets() {
+    assert_eq!(DiskEnd::at_byte(0), DiskEnd { lba: USER_DATA_START_LBA, offset: 0, is_in_word: false });
+    assert_eq!(DiskEnd::at_byte(513), DiskEnd { lba: USER_DATA_START_LBA + 1, offset: 0, is_in_word: true });
+    for offset in [0, 1, 2, 511, 512, 1027, 40_000] {
+        assert_eq!(DiskEnd::at_byte(offset).byte_offset(), offset);
+    }
+}
+
+#[test_case]
+fn test_text_round_trips_after_metadata() {
+    use crate::disk::ram::RamDisk;
+    let mut disk = RamDisk::new(TEXT_END_LBA as u64);
+    // metadata sectors full of non-zero bytes, which the scan must not stop at or count
+    let mut metadata = alloc::vec![0xFFFF; USER_DATA_START_LBA as usize * 256];
+    disk.write_sectors(&mut metadata, 0, USER_DATA_START_LBA).unwrap();
+    let text: Vec<u8> = (0..1027).map(|i| b'a' + (i % 26) as u8).collect();
+    let mut sectors = text.clone();
+    sectors.resize(3 * 512, 0);
+    disk.write_sectors(&mut crate::disk::bytes_to_words(&sectors), USER_DATA_START_LBA as u64, 3).unwrap();
+
+    let (end, buf) = find_text_end(&mut disk).unwrap();
+    assert_eq!(end, DiskEnd::at_byte(text.len() as u32));
+    assert_eq!(end.byte_offset(), text.len() as u32);
+    let mut words = alloc::vec![0; 3 * 256];
+    disk.read_sectors(&mut words, USER_DATA_START_LBA as u64, 3).unwrap();
+    assert_eq!(&crate::disk::words_to_bytes(&words)[..text.len()], &text[..]);
+    assert_eq!(&buf[..], &words[2 * 256..]);
+}
+
+#[test_case]
+fn test_decoder_num_lock_keypad() {
+    let mut decoder = KeyDecoder::new();
+    let was_on = num_lock();
+    set_num_lock(true);
+    assert_eq!(decoder.add_scancode(0x47), Some(DecodedKey::Unicode('7')));
+    assert_eq!(decoder.add_scancode(0xC7), None);
+    // Num Lock press and release
+    assert_eq!(decoder.add_scancode(0x45), None);
+    assert_eq!(decoder.add_scancode(0xC5), None);
+    assert!(!num_lock());
+    assert_eq!(decoder.add_scancode(0x47), Some(DecodedKey::RawKey(KeyCode::Home)));
+    assert_eq!(decoder.add_scancode(0xC7), None);
+    // a new decoder sees the same state
+    assert_eq!(KeyDecoder::new().add_scancode(0x53), Some(DecodedKey::RawKey(KeyCode::Delete)));
+    set_num_lock(was_on);
+}
+
+#[test_case]
+fn test_repeat_filter_drops_held_shift() {
+    let mut filter = RepeatFilter::new();
+    assert_eq!(filter.add(0x2A), Filtered::Pass(0x2A));
+    assert_eq!(filter.add(0x2A), Filtered::Drop);
+    assert_eq!(filter.add(0x1E), Filtered::Pass(0x1E));
+    assert_eq!(filter.add(0x1E), Filtered::Pass(0x1E));
+    assert_eq!(filter.add(0xAA), Filtered::Pass(0xAA));
+    assert_eq!(filter.add(0x2A), Filtered::Pass(0x2A));
+}
+
+#[test_case]
+fn test_repeat_filter_extended_keys() {
+    let mut filter = RepeatFilter::new();
+    assert_eq!(filter.add(0xE0), Filtered::Drop);
+    assert_eq!(filter.add(0x1D), Filtered::PassExtended(0x1D));
+    assert_eq!(filter.add(0xE0), Filtered::Drop);
+    assert_eq!(filter.add(0x1D), Filtered::Drop);
+    // left ctrl is tracked separately from right ctrl
+    assert_eq!(filter.add(0x1D), Filtered::Pass(0x1D));
+    // fake shift around an arrow key
+    assert_eq!(filter.add(0xE0), Filtered::Drop);
+    assert_eq!(filter.add(0x2A), Filtered::Drop);
+    assert_eq!(filter.add(0xE0), Filtered::Drop);
+    assert_eq!(filter.add(0x48), Filtered::PassExtended(0x48));
+}
@@ -0,0 +1,108 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// What a key does when editing text, independent of which key it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    InsertChar(char),
+    Backspace,
+    /// Removes the character under the cursor.
+    Delete,
+    /// Runs the line.
+    Submit,
+    CursorLeft,
+    CursorRight,
+    LineStart,
+    LineEnd,
+    KillToEnd,
+    KillToStart,
+    KillWord,
+    /// Clears the screen, keeping what's being typed.
+    ClearScreen,
+    HistoryPrev,
+    HistoryNext,
+    /// Searches back through the history for what's typed next.
+    ReverseSearch,
+    /// Goes back to where this mode was entered from.
+    Leave,
+    Ignore,
+}
+
+/// Which `Action` each key triggers in one input mode.
+///
+/// Keys without a binding insert themselves if they're printable, everything else is ignored.
+pub struct KeyMap {
+    bindings: &'static [(DecodedKey, Action)],
+}
+
+impl KeyMap {
+    pub const fn new(bindings: &'static [(DecodedKey, Action)]) -> KeyMap {
+        KeyMap { bindings }
+    }
+
+    pub fn action(&self, key: DecodedKey) -> Action {
+        if let Some((_, action)) = self.bindings.iter().find(|(bound, _)| *bound == key) {
+            return *action;
+        }
+        match key {
+            DecodedKey::Unicode(c) if !c.is_control() => Action::InsertChar(c),
+            _ => Action::Ignore,
+        }
+    }
+}
+
+/// The command line at the prompt. Ctrl+letter arrives as its control character.
+pub static CLI_KEYMAP: KeyMap = KeyMap::new(&[
+    (DecodedKey::Unicode('\n'), Action::Submit),
+    (DecodedKey::Unicode('\t'), Action::InsertChar('\t')),
+    (DecodedKey::Unicode('\x08'), Action::Backspace),
+    (DecodedKey::RawKey(KeyCode::Delete), Action::Delete),
+    (DecodedKey::RawKey(KeyCode::ArrowLeft), Action::CursorLeft),
+    (DecodedKey::RawKey(KeyCode::ArrowRight), Action::CursorRight),
+    (DecodedKey::RawKey(KeyCode::Home), Action::LineStart),
+    (DecodedKey::Unicode('\x01'), Action::LineStart),
+    (DecodedKey::RawKey(KeyCode::End), Action::LineEnd),
+    (DecodedKey::Unicode('\x05'), Action::LineEnd),
+    (DecodedKey::Unicode('\x0B'), Action::KillToEnd),
+    (DecodedKey::Unicode('\x15'), Action::KillToStart),
+    (DecodedKey::Unicode('\x17'), Action::KillWord),
+    (DecodedKey::Unicode('\x0C'), Action::ClearScreen),
+    (DecodedKey::RawKey(KeyCode::ArrowUp), Action::HistoryPrev),
+    (DecodedKey::RawKey(KeyCode::ArrowDown), Action::HistoryNext),
+    (DecodedKey::Unicode('\x12'), Action::ReverseSearch),
+]);
+
+/// Typing a query after Ctrl+R: Ctrl+R again finds an older match, Enter runs the match and
+/// Esc goes back to the line as it was.
+pub static SEARCH_KEYMAP: KeyMap = KeyMap::new(&[
+    (DecodedKey::Unicode('\n'), Action::Submit),
+    (DecodedKey::Unicode('\x08'), Action::Backspace),
+    (DecodedKey::Unicode('\x12'), Action::ReverseSearch),
+    (DecodedKey::Unicode('\x1B'), Action::Leave),
+]);
+
+/// The text editor, which writes straight to the disk and can only type and erase.
+pub static EDITOR_KEYMAP: KeyMap = KeyMap::new(&[
+    (DecodedKey::Unicode('\n'), Action::InsertChar('\n')),
+    (DecodedKey::Unicode('\t'), Action::InsertChar('\t')),
+    (DecodedKey::Unicode('\x08'), Action::Backspace),
+    (DecodedKey::Unicode('\x1B'), Action::Leave),
+]);
+
+#[test_case]
+fn test_cli_bindings() {
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::Unicode('a')), Action::InsertChar('a'));
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::Unicode('\n')), Action::Submit);
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::Unicode('\x17')), Action::KillWord);
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::RawKey(KeyCode::ArrowUp)), Action::HistoryPrev);
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::Unicode('\x12')), Action::ReverseSearch);
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::Unicode('\x1B')), Action::Ignore);
+    assert_eq!(CLI_KEYMAP.action(DecodedKey::RawKey(KeyCode::F1)), Action::Ignore);
+}
+
+#[test_case]
+fn test_editor_bindings() {
+    assert_eq!(EDITOR_KEYMAP.action(DecodedKey::Unicode('\n')), Action::InsertChar('\n'));
+    assert_eq!(EDITOR_KEYMAP.action(DecodedKey::Unicode('\x1B')), Action::Leave);
+    assert_eq!(EDITOR_KEYMAP.action(DecodedKey::Unicode('\x01')), Action::Ignore);
+    assert_eq!(EDITOR_KEYMAP.action(DecodedKey::RawKey(KeyCode::ArrowUp)), Action::Ignore);
+}
@@ -0,0 +1,385 @@
+use alloc::{format, string::String};
+use spin::Mutex;
+
+use crate::collections::RingBuffer;
+
+/// How many earlier command lines the up and down arrows can bring back.
+pub const HISTORY_SIZE: usize = 32;
+
+/// Command lines that have been run, oldest first.
+pub static HISTORY: Mutex<History> = Mutex::new(History::new());
+
+/// The command line being typed at the prompt.
+///
+/// Kept separately from the screen, so what gets run doesn't depend on scanning the VGA buffer.
+/// Editing happens at the cursor, a byte offset into the line that is always on a character
+/// boundary.
+pub struct InputLine {
+    text: String,
+    cursor: usize,
+    max_len: usize,
+    refused: usize,
+}
+
+impl InputLine {
+    /// An empty line that takes up to `max_len` characters, however many bytes they are.
+    pub fn new(max_len: usize) -> InputLine {
+        InputLine { text: String::new(), cursor: 0, max_len, refused: 0 }
+    }
+
+    /// Inserts a character at the cursor, returning false (and leaving the line alone) if it's
+    /// full.
+    pub fn push(&mut self, c: char) -> bool {
+        if self.is_full() {
+            self.refused += 1;
+            return false;
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        true
+    }
+
+    /// Removes the character before the cursor, returning false if there wasn't one.
+    pub fn backspace(&mut self) -> bool {
+        match self.text[..self.cursor].chars().next_back() {
+            Some(c) => {
+                self.cursor -= c.len_utf8();
+                self.text.remove(self.cursor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(c) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(c) = self.text[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Ctrl+A
+    pub fn move_to_start(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Ctrl+E
+    pub fn move_to_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Ctrl+K, removes everything after the cursor.
+    pub fn kill_to_end(&mut self) {
+        self.text.truncate(self.cursor);
+    }
+
+    /// Ctrl+U, removes everything before the cursor.
+    pub fn kill_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    /// Ctrl+W, removes the word before the cursor along with any spaces after it.
+    pub fn kill_word(&mut self) {
+        let before = self.text[..self.cursor].trim_end_matches(|c: char| c.is_ascii_whitespace());
+        let start = before.rfind(|c: char| c.is_ascii_whitespace()).map_or(0, |i| i + 1);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Hands out the finished line and starts a new, empty one.
+    pub fn take(&mut self) -> String {
+        self.refused = 0;
+        self.cursor = 0;
+        core::mem::take(&mut self.text)
+    }
+
+    /// How many characters have been turned away since the line was started.
+    pub fn refused(&self) -> usize {
+        self.refused
+    }
+
+    /// Swaps the line for `text`, cut down to the maximum length.
+    pub fn replace(&mut self, text: &str) {
+        self.text.clear();
+        self.cursor = 0;
+        for c in text.chars() {
+            if !self.push(c) {
+                break;
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn cursor_at_end(&self) -> bool {
+        self.cursor == self.text.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.text.chars().count() >= self.max_len
+    }
+}
+
+/// The lines run at the prompt, and where the arrow keys are while going back through them.
+pub struct History {
+    lines: RingBuffer<String, HISTORY_SIZE>,
+    // how many lines back from the newest the arrows have gone, None when not browsing
+    position: Option<usize>,
+}
+
+impl History {
+    pub const fn new() -> History {
+        History { lines: RingBuffer::new(), position: None }
+    }
+
+    /// Remembers a line that was run and stops browsing. Blank lines and repeats aren't kept.
+    pub fn push(&mut self, line: &str) {
+        self.position = None;
+        if line.trim().is_empty() || self.lines.newest().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.lines.push(String::from(line));
+    }
+
+    /// Goes one line further back, returning it, or `None` at the oldest line.
+    pub fn older(&mut self) -> Option<&str> {
+        let position = self.position.map_or(0, |p| p + 1);
+        if position >= self.lines.len() {
+            return None;
+        }
+        self.position = Some(position);
+        self.lines.get(self.lines.len() - 1 - position).map(String::as_str)
+    }
+
+    /// Goes one line forward, returning it; past the newest line that's an empty line.
+    ///
+    /// Returns `None` when not browsing.
+    pub fn newer(&mut self) -> Option<&str> {
+        match self.position? {
+            0 => {
+                self.position = None;
+                Some("")
+            }
+            p => {
+                self.position = Some(p - 1);
+                self.lines.get(self.lines.len() - p).map(String::as_str)
+            }
+        }
+    }
+
+    /// Iterates from the oldest line to the newest.
+    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// Looks for the newest line containing `query` that's at least `from` lines back from the
+    /// newest, returning how many lines back it is.
+    pub fn find(&self, query: &str, from: usize) -> Option<usize> {
+        (from..self.lines.len()).find(|back| self.back(*back).map_or(false, |line| line.contains(query)))
+    }
+
+    /// The line `back` lines back from the newest.
+    pub fn back(&self, back: usize) -> Option<&str> {
+        let index = self.lines.len().checked_sub(back + 1)?;
+        self.lines.get(index).map(String::as_str)
+    }
+}
+
+/// Ctrl+R's search back through the history, narrowed down as the query is typed.
+pub struct ReverseSearch {
+    query: String,
+    // lines back from the newest of the last line found, None until something is
+    found: Option<usize>,
+    // whether the query as it is now matched nothing, `found` is then from before
+    failed: bool,
+    saved: String,
+}
+
+impl ReverseSearch {
+    /// Starts a search with an empty query, keeping `saved`, the line being typed, to go back
+    /// to.
+    pub fn new(saved: &str) -> ReverseSearch {
+        ReverseSearch { query: String::new(), found: None, failed: false, saved: String::from(saved) }
+    }
+
+    /// Adds to the query. The line found so far stays if it still matches.
+    pub fn push(&mut self, c: char, history: &History) {
+        self.query.push(c);
+        self.search(self.found.unwrap_or(0), history);
+    }
+
+    /// Takes the last character off the query and searches again from the newest line.
+    pub fn pop(&mut self, history: &History) {
+        self.query.pop();
+        self.found = None;
+        self.failed = false;
+        if !self.query.is_empty() {
+            self.search(0, history);
+        }
+    }
+
+    /// Goes on to the next older match, for Ctrl+R pressed again.
+    pub fn next(&mut self, history: &History) {
+        self.search(self.found.map_or(0, |found| found + 1), history);
+    }
+
+    fn search(&mut self, from: usize, history: &History) {
+        match history.find(&self.query, from) {
+            Some(found) => {
+                self.found = Some(found);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+
+    /// The line found, if any.
+    pub fn found<'a>(&self, history: &'a History) -> Option<&'a str> {
+        history.back(self.found?)
+    }
+
+    /// The line that was being typed when the search started.
+    pub fn saved(&self) -> &str {
+        &self.saved
+    }
+
+    /// What's shown in place of the line while searching.
+    pub fn prompt(&self, history: &History) -> String {
+        format!("({}reverse-i-search)`{}': {}", if self.failed { "failed " } else { "" },
+            self.query, self.found(history).unwrap_or(""))
+    }
+}
+
+#[test_case]
+fn test_push_past_limit_keeps_line() {
+    let mut line = InputLine::new(4);
+    for c in "abcdefgh".chars() {
+        line.push(c);
+    }
+    assert_eq!(line.as_str(), "abcd");
+    assert!(line.is_full());
+    assert!(!line.push('x'));
+    assert_eq!(line.refused(), 5);
+    assert!(line.backspace());
+    assert!(line.push('z'));
+    assert_eq!(line.take(), "abcz");
+    assert_eq!(line.len(), 0);
+    assert_eq!(line.refused(), 0);
+}
+
+#[test_case]
+fn test_limit_counts_characters() {
+    let mut line = InputLine::new(3);
+    for c in "äöüß".chars() {
+        line.push(c);
+    }
+    assert_eq!(line.as_str(), "äöü");
+    assert_eq!(line.refused(), 1);
+}
+
+#[cfg(test)]
+fn line_with_cursor(text: &str, cursor: usize) -> InputLine {
+    let mut line = InputLine::new(64);
+    line.replace(text);
+    line.cursor = cursor;
+    line
+}
+
+#[test_case]
+fn test_move_to_start_and_end() {
+    let mut line = line_with_cursor("echo hi", 7);
+    line.move_to_start();
+    assert_eq!(line.cursor(), 0);
+    line.push('x');
+    assert_eq!(line.as_str(), "xecho hi");
+    line.move_to_end();
+    assert!(line.cursor_at_end());
+    line.move_left();
+    assert!(line.backspace());
+    assert_eq!(line.as_str(), "xecho i");
+}
+
+#[test_case]
+fn test_kill_to_end() {
+    let mut line = line_with_cursor("echo hello world", 10);
+    line.kill_to_end();
+    assert_eq!(line.as_str(), "echo hello");
+    assert!(line.cursor_at_end());
+}
+
+#[test_case]
+fn test_kill_to_start() {
+    let mut line = line_with_cursor("echo hello world", 11);
+    line.kill_to_start();
+    assert_eq!(line.as_str(), "world");
+    assert_eq!(line.cursor(), 0);
+}
+
+#[test_case]
+fn test_kill_word() {
+    let mut line = line_with_cursor("echo hello  world", 12);
+    line.kill_word();
+    assert_eq!(line.as_str(), "echo world");
+    assert_eq!(line.cursor(), 5);
+    line.kill_word();
+    assert_eq!(line.as_str(), "world");
+    line.kill_word();
+    assert_eq!(line.as_str(), "world");
+}
+
+#[test_case]
+fn test_history_browsing() {
+    let mut history = History::new();
+    history.push("first");
+    history.push("second");
+    history.push("second");
+    history.push("  ");
+    assert_eq!(history.iter().count(), 2);
+    assert_eq!(history.newer(), None);
+    assert_eq!(history.older(), Some("second"));
+    assert_eq!(history.older(), Some("first"));
+    assert_eq!(history.older(), None);
+    assert_eq!(history.newer(), Some("second"));
+    assert_eq!(history.newer(), Some(""));
+    assert_eq!(history.newer(), None);
+}
+
+#[test_case]
+fn test_reverse_search() {
+    let mut history = History::new();
+    for line in ["echo one", "cat", "echo two", "dmesg"] {
+        history.push(line);
+    }
+    let mut search = ReverseSearch::new("ec");
+    assert_eq!(search.prompt(&history), "(reverse-i-search)`': ");
+    search.push('e', &history);
+    assert_eq!(search.found(&history), Some("dmesg"));
+    search.push('c', &history);
+    assert_eq!(search.found(&history), Some("echo two"));
+    search.next(&history);
+    assert_eq!(search.found(&history), Some("echo one"));
+    // nothing older, the last match stays
+    search.next(&history);
+    assert_eq!(search.prompt(&history), "(failed reverse-i-search)`ec': echo one");
+    search.push('x', &history);
+    assert_eq!(search.found(&history), Some("echo one"));
+    search.pop(&history);
+    assert_eq!(search.prompt(&history), "(reverse-i-search)`ec': echo two");
+    assert_eq!(search.saved(), "ec");
+}
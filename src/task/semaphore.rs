@@ -0,0 +1,124 @@
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Limits how many tasks can be in some section at once, making the others wait without
+/// spinning.
+///
+/// Unlike a spinlock, a permit can be held across an await: a task that can't get one is
+/// parked until a permit is given back. With one permit it's an async mutex, e.g. for a
+/// sequence of disk commands that mustn't be interleaved with another task's.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    // everyone that found no permit left, woken when one is given back
+    waiters: Vec<Waker>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore { state: Mutex::new(State { permits, waiters: Vec::new() }) }
+    }
+
+    /// Waits for a permit, which is given back when the returned `Permit` is dropped.
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire { semaphore: self }
+    }
+
+    /// Takes a permit if one is free, without waiting.
+    ///
+    /// Doesn't wait for the semaphore's own lock either, so it's safe in an interrupt handler:
+    /// if the code that was interrupted holds that lock, there's no permit to be had.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.try_lock()?;
+        if state.permits == 0 {
+            return None;
+        }
+        state.permits -= 1;
+        Some(Permit { semaphore: self })
+    }
+
+    pub fn available(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    fn release(&self) {
+        let waiters = {
+            let mut state = self.state.lock();
+            state.permits += 1;
+            core::mem::take(&mut state.waiters)
+        };
+        // all of them, since a woken task that was dropped meanwhile would never pass it on;
+        // whoever isn't first just waits again
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Permit<'a>> {
+        let mut state = self.semaphore.state.lock();
+        if state.permits > 0 {
+            state.permits -= 1;
+            return Poll::Ready(Permit { semaphore: self.semaphore });
+        }
+        if !state.waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// A permit from a `Semaphore`, given back when dropped.
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+#[test_case]
+fn test_one_permit_excludes() {
+    use super::{simple_executor::SimpleExecutor, yield_now, Task};
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    static SEMAPHORE: Semaphore = Semaphore::new(1);
+    static INSIDE: AtomicBool = AtomicBool::new(false);
+    static DONE: AtomicU32 = AtomicU32::new(0);
+
+    let mut executor = SimpleExecutor::new();
+    for _ in 0..2 {
+        executor.spawn(Task::new(async {
+            for _ in 0..3 {
+                let _permit = SEMAPHORE.acquire().await;
+                assert!(!INSIDE.swap(true, Ordering::Relaxed));
+                // gives the other task every chance to get in too
+                yield_now().await;
+                yield_now().await;
+                INSIDE.store(false, Ordering::Relaxed);
+            }
+            DONE.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+    executor.run();
+    assert_eq!(DONE.load(Ordering::Relaxed), 2);
+    assert_eq!(SEMAPHORE.available(), 1);
+    assert!(SEMAPHORE.try_acquire().is_some());
+}
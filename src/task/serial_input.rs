@@ -0,0 +1,247 @@
+use crate::{println, time::{read_timer, ticks_since, ticks_to_ms}};
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+static SERIAL_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Called by the serial interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    super::autopoweroff::note_input();
+    // Ctrl+C cancels the running command instead of being typed
+    if byte == 0x03 && !RAW_MODE.load(Ordering::Relaxed) {
+        super::cancel::request();
+        return;
+    }
+    if let Ok(queue) = SERIAL_QUEUE.try_get() {
+        if let Err(_) = queue.push(byte) {
+            println!("WARNING: serial queue full; dropping serial input");
+        } else {
+            WAKER.wake();
+        }
+    }
+    // no consumer yet, nobody is listening on the serial line
+}
+
+/// Passes every byte through untouched, Ctrl+C included, while binary data is being received.
+pub fn set_raw(raw: bool) {
+    RAW_MODE.store(raw, Ordering::Relaxed);
+}
+
+/// Waits for the next byte from the serial line, giving up after `timeout_ms` without one.
+///
+/// For commands that read the line themselves; the CLI isn't polling `SerialStream` while a
+/// command runs, so the bytes come straight off the queue.
+pub async fn receive_byte(timeout_ms: u128) -> Option<u8> {
+    let queue = SERIAL_QUEUE.try_get().ok()?;
+    let start = read_timer();
+    loop {
+        if let Ok(byte) = queue.pop() {
+            return Some(byte);
+        }
+        if ticks_to_ms(ticks_since(start)) >= timeout_ms {
+            return None;
+        }
+        super::yield_now().await;
+    }
+}
+
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        SERIAL_QUEUE
+            .try_init_once(|| ArrayQueue::new(100))
+            .expect("SerialStream::new should only be called once");
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SERIAL_QUEUE
+            .try_get()
+            .expect("serial queue not initialized");
+
+        // fast path
+        if let Ok(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(&cx.waker());
+        match queue.pop() {
+            Ok(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    Ground,
+    Escape,
+    // ESC [ ...
+    Csi,
+    // ESC O ...
+    Ss3,
+}
+
+/// Turns the bytes a serial terminal sends into the same `DecodedKey`s the keyboard produces.
+///
+/// Terminals send arrow and navigation keys as ANSI escape sequences (`ESC [ A` for up and so on),
+/// and those can be split across interrupts, so the decoder keeps the partial sequence in its state
+/// until the final byte arrives. Since a bare ESC can't be told apart from the start of a sequence,
+/// pressing ESC twice sends a single ESC key.
+pub struct AnsiDecoder {
+    state: AnsiState,
+    param: u8,
+    in_first_param: bool,
+    // the last byte was a CR, so an LF right after it is the same line ending
+    after_cr: bool,
+}
+
+impl AnsiDecoder {
+    pub const fn new() -> Self {
+        AnsiDecoder { state: AnsiState::Ground, param: 0, in_first_param: true, after_cr: false }
+    }
+
+    /// Feeds one byte from the serial line, returning a key once a full one has been seen.
+    pub fn add_byte(&mut self, byte: u8) -> Option<DecodedKey> {
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match self.state {
+            AnsiState::Ground => match byte {
+                0x1B => {
+                    self.state = AnsiState::Escape;
+                    None
+                }
+                // terminals send CR (or CRLF, or LF) for enter and DEL for backspace
+                b'\n' if after_cr => None,
+                b'\r' => {
+                    self.after_cr = true;
+                    Some(DecodedKey::Unicode('\n'))
+                }
+                b'\n' => Some(DecodedKey::Unicode('\n')),
+                0x7F | 0x08 => Some(DecodedKey::Unicode(0x08 as char)),
+                0x00..=0x7E => Some(DecodedKey::Unicode(byte as char)),
+                _ => None,
+            },
+            AnsiState::Escape => match byte {
+                b'[' => {
+                    self.state = AnsiState::Csi;
+                    self.param = 0;
+                    self.in_first_param = true;
+                    None
+                }
+                b'O' => {
+                    self.state = AnsiState::Ss3;
+                    None
+                }
+                _ => {
+                    // ESC ESC, or ESC followed by something we don't know: treat it as a bare ESC
+                    self.state = AnsiState::Ground;
+                    Some(DecodedKey::Unicode(0x1B as char))
+                }
+            },
+            AnsiState::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.in_first_param {
+                        self.param = self.param.saturating_mul(10).saturating_add(byte - b'0');
+                    }
+                    None
+                }
+                // modifier parameters (ESC [ 1 ; 5 A) are ignored
+                b';' => {
+                    self.in_first_param = false;
+                    None
+                }
+                // intermediate bytes, keep waiting for the final byte
+                0x20..=0x2F => None,
+                _ => {
+                    self.state = AnsiState::Ground;
+                    let code = match byte {
+                        b'~' => match self.param {
+                            1 | 7 => KeyCode::Home,
+                            2 => KeyCode::Insert,
+                            3 => KeyCode::Delete,
+                            4 | 8 => KeyCode::End,
+                            5 => KeyCode::PageUp,
+                            6 => KeyCode::PageDown,
+                            _ => return None,
+                        },
+                        _ => Self::final_byte_key(byte)?,
+                    };
+                    Some(DecodedKey::RawKey(code))
+                }
+            },
+            AnsiState::Ss3 => {
+                self.state = AnsiState::Ground;
+                Self::final_byte_key(byte).map(DecodedKey::RawKey)
+            }
+        }
+    }
+
+    fn final_byte_key(byte: u8) -> Option<KeyCode> {
+        match byte {
+            b'A' => Some(KeyCode::ArrowUp),
+            b'B' => Some(KeyCode::ArrowDown),
+            b'C' => Some(KeyCode::ArrowRight),
+            b'D' => Some(KeyCode::ArrowLeft),
+            b'H' => Some(KeyCode::Home),
+            b'F' => Some(KeyCode::End),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+fn decode(decoder: &mut AnsiDecoder, bytes: &[u8]) -> alloc::vec::Vec<DecodedKey> {
+    bytes.iter().filter_map(|byte| decoder.add_byte(*byte)).collect()
+}
+
+#[test_case]
+fn test_crlf_is_one_enter() {
+    let mut decoder = AnsiDecoder::new();
+    let enter = DecodedKey::Unicode('\n');
+    assert_eq!(decode(&mut decoder, b"a\r\nb"), [DecodedKey::Unicode('a'), enter, DecodedKey::Unicode('b')]);
+    // split across reads too
+    assert_eq!(decode(&mut decoder, b"\r"), [enter]);
+    assert_eq!(decode(&mut decoder, b"\n"), []);
+    // CR alone and LF alone are each a line
+    assert_eq!(decode(&mut decoder, b"\r\r\n\n"), [enter, enter, enter]);
+}
+
+#[test_case]
+fn test_escape_sequences_split_across_reads() {
+    let mut decoder = AnsiDecoder::new();
+    assert_eq!(decode(&mut decoder, b"\x1b"), []);
+    assert_eq!(decode(&mut decoder, b"["), []);
+    assert_eq!(decode(&mut decoder, b"A"), [DecodedKey::RawKey(KeyCode::ArrowUp)]);
+    // with a parameter, split inside it
+    assert_eq!(decode(&mut decoder, b"\x1b[3"), []);
+    assert_eq!(decode(&mut decoder, b"~x"), [DecodedKey::RawKey(KeyCode::Delete), DecodedKey::Unicode('x')]);
+    // with a modifier, which is dropped
+    assert_eq!(decode(&mut decoder, b"\x1b[1;"), []);
+    assert_eq!(decode(&mut decoder, b"5C"), [DecodedKey::RawKey(KeyCode::ArrowRight)]);
+    assert_eq!(decode(&mut decoder, b"\x1bO"), []);
+    assert_eq!(decode(&mut decoder, b"H"), [DecodedKey::RawKey(KeyCode::Home)]);
+    // ESC ESC is the ESC key
+    assert_eq!(decode(&mut decoder, b"\x1b"), []);
+    assert_eq!(decode(&mut decoder, b"\x1b"), [DecodedKey::Unicode('\x1b')]);
+}
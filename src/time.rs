@@ -1,13 +1,89 @@
-use spin;
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64;
+use x86_64::instructions::port::Port;
 
-pub static TIMER: spin::Mutex<u128> = 
-    spin::Mutex::new(0);
+/// Timer ticks since boot, only ever changed by `tick`.
+///
+/// An atomic rather than a lock, so reading it never needs interrupts off and the timer
+/// interrupt can't find it held. It's 64 bits because x86_64 has no plain 128 bit atomics
+/// (only `cmpxchg16b` loops); at `TICKS_PER_SECOND` that still takes over 500 million years to
+/// run out, and it saturates rather than wrapping if it ever does. Everything else keeps using
+/// `u128` ticks, so the width stays a detail of this module.
+static TIMER: AtomicU64 = AtomicU64::new(0);
+
+/// How often the timer interrupt fires once `init` has programmed the PIT: once per millisecond.
+pub const TICKS_PER_SECOND: u32 = 1000;
+/// The rate the PIT's channels count down at, divided down to get the timer and speaker tones.
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Programs PIT channel 0 to fire at `TICKS_PER_SECOND` instead of the BIOS default of ~18.2 Hz.
+pub fn init() {
+    let divisor = PIT_BASE_FREQUENCY / TICKS_PER_SECOND;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+    unsafe {
+        // channel 0, lobyte/hibyte access, rate generator
+        command.write(0x34);
+        channel0.write((divisor & 0xFF) as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
 
 pub fn read_timer() -> u128 {
-    let mut time = 0;
-    x86_64::instructions::interrupts::without_interrupts(||
-        time = *TIMER.lock()
-    );
-    return time;
-}
\ No newline at end of file
+    TIMER.load(Ordering::Relaxed) as u128
+}
+
+/// Counts one tick and returns the new time. Only for the timer interrupt handler.
+pub(crate) fn tick() -> u128 {
+    // the handler is the only writer, so a load and a store can't lose a tick
+    let now = TIMER.load(Ordering::Relaxed).saturating_add(1);
+    TIMER.store(now, Ordering::Relaxed);
+    now as u128
+}
+
+/// Ticks elapsed since `start`, a value from `read_timer`.
+///
+/// Use this rather than subtracting by hand: a `start` that's somehow ahead of the timer gives 0
+/// instead of an overflow.
+pub fn ticks_since(start: u128) -> u128 {
+    read_timer().saturating_sub(start)
+}
+
+pub fn ticks_to_ms(ticks: u128) -> u128 {
+    ticks.saturating_mul(1000) / TICKS_PER_SECOND as u128
+}
+
+/// Busy-waits for about `ms` milliseconds, for code that can't sleep.
+///
+/// Counts writes to port 0x80, the POST code port nothing listens on, which take about a
+/// microsecond each on the ISA bus. Unlike the timer that works with interrupts off, but it's
+/// only roughly right, so it's for backoffs rather than timing.
+pub fn io_wait_ms(ms: u128) {
+    let mut post: Port<u8> = Port::new(0x80);
+    for _ in 0..ms.saturating_mul(1000) {
+        unsafe { post.write(0) };
+    }
+}
+
+/// Measures the TSC frequency in Hz against the PIT.
+///
+/// Spins for 50 ms, so interrupts have to be enabled.
+pub fn tsc_frequency() -> u64 {
+    use core::arch::x86_64::_rdtsc;
+
+    const SAMPLE_TICKS: u128 = TICKS_PER_SECOND as u128 / 20;
+    // line up with a tick boundary first
+    let start_tick = read_timer();
+    while read_timer() == start_tick { core::hint::spin_loop(); }
+    let start_tick = read_timer();
+    let start_tsc = unsafe { _rdtsc() };
+    while ticks_since(start_tick) < SAMPLE_TICKS { core::hint::spin_loop(); }
+    let elapsed_tsc = unsafe { _rdtsc() } - start_tsc;
+    elapsed_tsc * TICKS_PER_SECOND as u64 / SAMPLE_TICKS as u64
+}
+
+#[test_case]
+fn test_ticks_since_future_start() {
+    assert_eq!(ticks_since(read_timer() + 1000), 0);
+    assert_eq!(ticks_to_ms(u128::MAX), u128::MAX / TICKS_PER_SECOND as u128);
+}
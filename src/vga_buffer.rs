@@ -1,5 +1,6 @@
 use core::fmt::{self, Write};
-use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::{string::String, vec::Vec};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
@@ -17,9 +18,11 @@ lazy_static! {
         }
         Writer {
             column_position: 0,
-            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            color_code: DEFAULT_COLOR,
             buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-            cmd_start: (0, 0) // should set in init();
+            cmd_start: (0, 0), // should set in init();
+            status_line: false,
+            cursor_shape: DEFAULT_CURSOR_SHAPE,
         }
     });
 }
@@ -55,6 +58,97 @@ pub static COLOR_NAME_LIST: [&'static str; 16] =
     ["Black", "Blue", "Green", "Cyan", "Red", "Magenta", "Brown", "LightGray", "DarkGray",
      "LightBlue", "LightGreen", "LightCyan", "LightRed", "Pink", "Yellow", "White"];
 
+/// The RGB values the default VGA palette shows each of `COLOR_LIST` as.
+pub const COLOR_RGB: [(u8, u8, u8); 16] =
+    [(0, 0, 0), (0, 0, 170), (0, 170, 0), (0, 170, 170), (170, 0, 0), (170, 0, 170), (170, 85, 0), (170, 170, 170),
+     (85, 85, 85), (85, 85, 255), (85, 255, 85), (85, 255, 255), (255, 85, 85), (255, 85, 255), (255, 255, 85), (255, 255, 255)];
+
+/// The DAC register each of the 16 text mode colors is shown with. The attribute controller's
+/// default palette sends brown and the bright colors to registers other than their own index.
+const TEXT_MODE_DAC_INDEX: [u8; 16] =
+    [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F];
+const DAC_WRITE_INDEX_PORT: u16 = 0x3C8;
+const DAC_DATA_PORT: u16 = 0x3C9;
+
+// what each color currently looks like, 8 bits per channel
+static PALETTE: Mutex<[(u8, u8, u8); 16]> = Mutex::new(COLOR_RGB);
+
+/// Changes what color `index` (0 to 15, as in `COLOR_LIST`) looks like on screen, given as
+/// 6-bit DAC values. Other indexes are ignored.
+///
+/// Everything already drawn in that color changes with it, since only the palette changes.
+pub fn set_palette_color(index: u8, r: u8, g: u8, b: u8) {
+    use x86_64::instructions::port::Port;
+
+    if index >= 16 {
+        return;
+    }
+    // mode 13h goes straight to the DAC, text mode through the attribute controller
+    let register = if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_ok() {
+        index
+    } else {
+        TEXT_MODE_DAC_INDEX[index as usize]
+    };
+    let mut palette = PALETTE.lock();
+    let mut write_index: Port<u8> = Port::new(DAC_WRITE_INDEX_PORT);
+    let mut data: Port<u8> = Port::new(DAC_DATA_PORT);
+    unsafe {
+        write_index.write(register);
+        for channel in [r, g, b] {
+            data.write(channel & 0x3F);
+        }
+    }
+    let widen = |channel: u8| (channel & 0x3F) << 2 | (channel & 0x3F) >> 4;
+    palette[index as usize] = (widen(r), widen(g), widen(b));
+}
+
+/// What the 16 colors look like right now, 8 bits per channel.
+pub fn palette() -> [(u8, u8, u8); 16] {
+    *PALETTE.lock()
+}
+
+/// Puts every color back to its default from `COLOR_RGB`.
+pub fn reset_palette() {
+    for (index, (r, g, b)) in COLOR_RGB.iter().enumerate() {
+        set_palette_color(index as u8, r >> 2, g >> 2, b >> 2);
+    }
+}
+
+// the CRT controller's registers are reached by writing an index, then the data
+const CRTC_INDEX_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CURSOR_START_REGISTER: u8 = 0x0A;
+const CURSOR_END_REGISTER: u8 = 0x0B;
+// in the cursor start register, hides the cursor
+const CURSOR_DISABLE: u8 = 1 << 5;
+// the scanline bits of both cursor registers, the others are left as they are
+const CURSOR_SCANLINE_MASK: u8 = 0x1F;
+
+/// How the hardware text mode cursor is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Off,
+}
+
+impl CursorShape {
+    /// The first and last of the 16 scanlines in a cell the cursor covers, `None` if hidden.
+    fn scanlines(self) -> Option<(u8, u8)> {
+        match self {
+            CursorShape::Block => Some((0, 15)),
+            CursorShape::Underline => Some((14, 15)),
+            CursorShape::Off => None,
+        }
+    }
+}
+
+/// The cursor shape the BIOS starts out with, and `Writer::reset` goes back to.
+pub const DEFAULT_CURSOR_SHAPE: CursorShape = CursorShape::Underline;
+
+/// The colors text starts out in, and goes back to after `reset_terminal`.
+pub const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::Yellow, Color::Black);
+
 /// A combination of a foreground and a background color.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
@@ -62,9 +156,19 @@ pub struct ColorCode(u8);
 
 impl ColorCode {
     /// Create a new `ColorCode` with the given foreground and background colors.
-    pub fn new(foreground: Color, background: Color) -> ColorCode {
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    /// The same background with a different foreground.
+    pub fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode(self.0 & 0xF0 | (foreground as u8))
+    }
+
+    /// The foreground and background swapped.
+    pub fn inverted(self) -> ColorCode {
+        ColorCode(self.0 << 4 | self.0 >> 4)
+    }
 }
 
 /// A screen character in the VGA text buffer, consisting of an ASCII character and a `ColorCode`.
@@ -95,59 +199,72 @@ pub struct Writer {
     color_code: ColorCode,
     buffer: &'static mut Buffer,
     // stuff for cmd, should extract
-    cmd_start: (usize, usize) // row, col
+    cmd_start: (usize, usize), // row, col
+    // when set, the last row is a status line that scrolling leaves alone
+    status_line: bool,
+    // what was last written to the cursor registers
+    cursor_shape: CursorShape,
 }
 
 impl Writer {
     /// Writes an ASCII byte to the buffer.
     ///
-    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character.
+    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character, backspace (`\x08`)
+    /// erases the last character and form feed (`\x0C`) clears the screen.
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+            0x08 => self.backspace(),
+            0x0C => self.reset_screen(),
+            byte => self.write_glyph(byte),
+        }
+    }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+    /// Puts the font's character for `byte` in the next cell, even for a control byte.
+    fn write_glyph(&mut self, byte: u8) {
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
         }
+
+        let row = self.bottom_row();
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_character: byte,
+            color_code,
+        });
+        self.column_position += 1;
     }
 
     /// Writes the given ASCII string to the buffer.
     ///
-    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character. Does **not**
-    /// support strings with non-ASCII characters, since they can't be printed in the VGA text
-    /// mode.
+    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character. Other non-ASCII
+    /// characters can't be printed in the VGA text mode, except the glyphs the font draws for
+    /// control bytes (see `cp437::glyph`).
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+        for c in s.chars() {
+            match c {
+                // printable ASCII byte, newline, backspace or form feed
+                '\x20'..='\x7e' | '\n' | '\x08' | '\x0C' => self.write_byte(c as u8),
+                c => match crate::cp437::control_byte(c) {
+                    Some(byte) => self.write_glyph(byte),
+                    // not part of printable ASCII range
+                    None => self.write_byte(0xfe),
+                },
             }
         }
     }
 
-    /// Shifts all lines one line up and clears the last row.
+    /// Shifts all lines one line up and clears the last row, keeping the status line.
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
+        for row in 1..=self.bottom_row() {
             for col in 0..BUFFER_WIDTH {
                 let character = self.buffer.chars[row][col].read();
                 self.buffer.chars[row - 1][col].write(character);
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+        self.clear_row(self.bottom_row());
         self.column_position = 0;
         if self.cmd_start.0 > 0 { self.cmd_start.0 -= 1; } // Decrease cmd start
         //else { panic!("Command goes off the screen, implement actual screenbuffer to fix!"); }
@@ -164,31 +281,88 @@ impl Writer {
         }
     }
 
+    /// Gives every cell above the status line `color`, keeping the characters in them.
+    pub fn fill_screen(&mut self, color: ColorCode) {
+        for row in 0..=self.bottom_row() {
+            for col in 0..BUFFER_WIDTH {
+                let mut character = self.buffer.chars[row][col].read();
+                character.color_code = color;
+                self.buffer.chars[row][col].write(character);
+            }
+        }
+    }
+
     pub fn reset_screen(&mut self) {
-        for row in 0..BUFFER_HEIGHT {
+        for row in 0..=self.bottom_row() {
             self.clear_row(row);
         }
         self.column_position = 0;
     }
 
+    /// Puts the colors back to their default, gives the status line back to the text and
+    /// clears the screen.
+    pub fn reset(&mut self) {
+        self.color_code = DEFAULT_COLOR;
+        self.set_status_enabled(false);
+        self.reset_screen();
+        self.cmd_start = (0, 0);
+        self.set_cursor_shape(DEFAULT_CURSOR_SHAPE);
+    }
+
+    /// Changes the scanlines the hardware cursor covers through the CRT controller, or hides
+    /// it. Has no effect on the framebuffer, which has no hardware cursor.
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        use x86_64::instructions::port::Port;
+
+        let mut index: Port<u8> = Port::new(CRTC_INDEX_PORT);
+        let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+        let mut update = |register: u8, value: u8| unsafe {
+            index.write(register);
+            let old = data.read();
+            data.write(old & !(CURSOR_DISABLE | CURSOR_SCANLINE_MASK) | value);
+        };
+        match shape.scanlines() {
+            Some((start, end)) => {
+                update(CURSOR_START_REGISTER, start);
+                update(CURSOR_END_REGISTER, end);
+            }
+            None => update(CURSOR_START_REGISTER, CURSOR_DISABLE),
+        }
+        self.cursor_shape = shape;
+    }
+    pub fn cursor_shape(&self) -> CursorShape {
+        self.cursor_shape
+    }
+
     pub fn backspace(&mut self) {
         // Assuming the last row
-        let row = BUFFER_HEIGHT - 1;
+        let row = self.bottom_row();
         if self.column_position > 0 { 
             self.column_position -= 1;
         } 
         else {
+            // nothing to go back to, e.g. right after the screen was cleared
+            let above = row - 1;
+            if (0..BUFFER_WIDTH).all(|col| matches!(self.buffer.chars[above][col].read().ascii_character, 0 | b' ')) {
+                return;
+            }
             // Send everything down a row
-            for row in (1..BUFFER_HEIGHT).rev() {
+            for row in (1..=row).rev() {
                 for col in 0..BUFFER_WIDTH {
                     let character = self.buffer.chars[row - 1][col].read();
                     self.buffer.chars[row][col].write(character);
                 }
             }
             self.clear_row(0);
-            // Seek back to newline
+            // the command moved down with everything else
+            if self.cmd_start.0 < row { self.cmd_start.0 += 1; }
+            // Seek back to the last character, stopping at column 0 rather than going past it.
+            // Cleared cells hold spaces, so those count as empty along with never-written ones
             self.column_position = BUFFER_WIDTH - 1;
-            while self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position].read().ascii_character == 0 { self.column_position -= 1; }
+            while self.column_position > 0
+                && matches!(self.buffer.chars[row][self.column_position].read().ascii_character, 0 | b' ') {
+                self.column_position -= 1;
+            }
         }
         self.buffer.chars[row][self.column_position].write(ScreenChar {
             ascii_character: 0,
@@ -198,11 +372,11 @@ impl Writer {
     }
 
     pub fn last_char(&self) -> char {
-        self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position - 1].read().ascii_character as char
+        self.buffer.chars[self.bottom_row()][self.column_position - 1].read().ascii_character as char
     }
     pub fn scan_until_or_all(&self, c: char) -> String {
         let mut s = String::new();
-        let mut row = BUFFER_HEIGHT - 1;
+        let mut row = self.bottom_row();
         let mut col = self.column_position - 1;
         while self.buffer.chars[row][col].read().ascii_character as char != c {//&& self.buffer.chars[row][col].read().ascii_character != 0 {
             if self.buffer.chars[row][col].read().ascii_character != 0 {
@@ -218,13 +392,13 @@ impl Writer {
         s
     }
     pub fn reset_cmd_start(&mut self) {
-        self.cmd_start = (BUFFER_HEIGHT - 1, self.column_position);
+        self.cmd_start = (self.bottom_row(), self.column_position);
         //let start = self.cmd_start;
         //self.write_fmt(format_args!("{:?}", start)).unwrap();
     }
     pub fn scan_cmd(&self) -> String {
         let mut s = String::new();
-        let mut row = BUFFER_HEIGHT - 1;
+        let mut row = self.bottom_row();
         let mut col = self.column_position;
         let start = self.cmd_start;
         while row > self.cmd_start.0 || ( row == self.cmd_start.0 && col >= self.cmd_start.1) {//&& self.buffer.chars[row][col].read().ascii_character != 0 {
@@ -241,14 +415,132 @@ impl Writer {
         s
     }
 
+    /// Copies every cell on screen, row by row, with the character in the low byte
+    /// and the color in the high byte.
+    pub fn snapshot(&self) -> Vec<u16> {
+        let mut cells = Vec::with_capacity(BUFFER_WIDTH * BUFFER_HEIGHT);
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let c = self.buffer.chars[row][col].read();
+                cells.push(c.ascii_character as u16 | (c.color_code.0 as u16) << 8);
+            }
+        }
+        cells
+    }
+
+    /// Puts back cells taken by `snapshot`, then starts a fresh line below them.
+    pub fn restore(&mut self, cells: &[u16]) {
+        for (i, cell) in cells.iter().take(BUFFER_WIDTH * BUFFER_HEIGHT).enumerate() {
+            self.buffer.chars[i / BUFFER_WIDTH][i % BUFFER_WIDTH].write(ScreenChar {
+                ascii_character: (cell & 0xFF) as u8,
+                color_code: ColorCode((cell >> 8) as u8),
+            });
+        }
+        self.new_line();
+    }
+
     pub fn set_color(&mut self, color: ColorCode) {
         self.color_code = color;
     }
+    pub fn color(&self) -> ColorCode {
+        self.color_code
+    }
     pub fn cmd_start(&self) -> (usize, usize) {
         self.cmd_start
     }
+    /// Shows the line editor's cursor by swapping the colors of the character `offset` cells
+    /// after the start of the command.
+    pub fn mark_cursor(&mut self, offset: usize) {
+        let cell = self.cmd_start.0 * BUFFER_WIDTH + self.cmd_start.1 + offset;
+        let (row, col) = (cell / BUFFER_WIDTH, cell % BUFFER_WIDTH);
+        if row > self.bottom_row() {
+            return;
+        }
+        let mut character = self.buffer.chars[row][col].read();
+        character.color_code = character.color_code.inverted();
+        self.buffer.chars[row][col].write(character);
+    }
     pub fn current_pos(&self) -> (usize, usize) {
-        (BUFFER_HEIGHT - 1, self.column_position)
+        (self.bottom_row(), self.column_position)
+    }
+
+    /// Reads back a cell of the buffer, so tests can check what was drawn.
+    #[cfg(test)]
+    fn read_cell(&self, row: usize, col: usize) -> ScreenChar {
+        self.buffer.chars[row][col].read()
+    }
+
+    /// The row text is written to: the last one, or the one above the status line.
+    fn bottom_row(&self) -> usize {
+        if self.status_line { BUFFER_HEIGHT - 2 } else { BUFFER_HEIGHT - 1 }
+    }
+
+    /// How many rows scroll, which is all of them unless the status line is on.
+    pub fn text_rows(&self) -> usize {
+        self.bottom_row() + 1
+    }
+
+    /// Reserves the last row for `set_status_line`, or gives it back to the text.
+    ///
+    /// The text moves up or down a row so nothing is covered.
+    pub fn set_status_enabled(&mut self, enabled: bool) {
+        if enabled == self.status_line {
+            return;
+        }
+        if enabled {
+            for row in 1..BUFFER_HEIGHT {
+                for col in 0..BUFFER_WIDTH {
+                    let character = self.buffer.chars[row][col].read();
+                    self.buffer.chars[row - 1][col].write(character);
+                }
+            }
+            self.clear_row(BUFFER_HEIGHT - 1);
+            if self.cmd_start.0 > 0 { self.cmd_start.0 -= 1; }
+        } else {
+            for row in (1..BUFFER_HEIGHT).rev() {
+                for col in 0..BUFFER_WIDTH {
+                    let character = self.buffer.chars[row - 1][col].read();
+                    self.buffer.chars[row][col].write(character);
+                }
+            }
+            self.clear_row(0);
+            self.cmd_start.0 += 1;
+        }
+        self.status_line = enabled;
+    }
+
+    /// Writes `text` to the status line in its own colors, if the status line is on.
+    pub fn set_status_line(&mut self, text: &str) {
+        if !self.status_line {
+            return;
+        }
+        let color_code = ColorCode::new(Color::Black, Color::LightGray);
+        let mut bytes = text.bytes();
+        for col in 0..BUFFER_WIDTH {
+            let byte = match bytes.next() {
+                Some(byte @ 0x20..=0x7e) => byte,
+                Some(_) => 0xfe,
+                None => b' ',
+            };
+            self.buffer.chars[BUFFER_HEIGHT - 1][col].write(ScreenChar { ascii_character: byte, color_code });
+        }
+    }
+
+    /// Draws `text` at a fixed place on the screen, cut off at the end of the row.
+    ///
+    /// Doesn't move the position the next character is written at, so it can be used while
+    /// something else is being typed. Whatever was in those cells is lost.
+    pub fn write_at(&mut self, row: usize, col: usize, text: &str) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        for (col, byte) in (col..BUFFER_WIDTH).zip(text.bytes()) {
+            let byte = match byte {
+                0x20..=0x7e => byte,
+                _ => 0xfe,
+            };
+            self.buffer.chars[row][col].write(ScreenChar { ascii_character: byte, color_code: self.color_code });
+        }
     }
 }
 
@@ -274,19 +566,232 @@ macro_rules! println {
 }
 
 
+/// How many bytes of output `INTERRUPT_OUTPUT` keeps until it's flushed, anything past that
+/// is dropped.
+const INTERRUPT_OUTPUT_SIZE: usize = 1024;
+/// How many bytes of output are held while it's paused, anything past that is dropped.
+pub const PAUSED_OUTPUT_SIZE: usize = 4096;
+
+// prints that found the writer locked end up here, see `print_to_screen`
+static INTERRUPT_OUTPUT: Mutex<HeldOutput<INTERRUPT_OUTPUT_SIZE>> = Mutex::new(HeldOutput::new());
+// prints made while output is paused, see `set_output_paused`
+static PAUSED_OUTPUT: Mutex<HeldOutput<PAUSED_OUTPUT_SIZE>> = Mutex::new(HeldOutput::new());
+static OUTPUT_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Output waiting to be written to the screen. A fixed buffer rather than a `String`, since
+/// it's filled by interrupt handlers, which mustn't allocate.
+struct HeldOutput<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+    // bytes that didn't fit, counted so the flush can say so
+    dropped: usize,
+}
+
+impl<const N: usize> HeldOutput<N> {
+    const fn new() -> Self {
+        HeldOutput { bytes: [0; N], len: 0, dropped: 0 }
+    }
+
+    fn flush_into(&mut self, out: &mut dyn Write) {
+        if self.len > 0 {
+            // only whole characters are ever kept, so this is always valid
+            let text = core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("");
+            let _ = out.write_str(text);
+            self.len = 0;
+        }
+        if self.dropped > 0 {
+            let _ = write!(out, "\n[{} bytes of output were dropped]\n", self.dropped);
+            self.dropped = 0;
+        }
+    }
+}
+
+impl<const N: usize> fmt::Write for HeldOutput<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(N - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.bytes[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        self.dropped += s.len() - end;
+        Ok(())
+    }
+}
+
+/// Whether output is paused, see `set_output_paused`.
+pub fn output_paused() -> bool {
+    OUTPUT_PAUSED.load(Ordering::Relaxed)
+}
+
+/// Brings the screen back to how it was at boot: default colors and palette, no status line,
+/// output not paused, and nothing on the screen.
+pub fn reset_terminal() {
+    reset_palette();
+    set_output_paused(false);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        match crate::framebuffer::FRAMEBUFFER_WRITER.try_get() {
+            Ok(framebuffer) => framebuffer.lock().clear(),
+            Err(_) => WRITER.lock().reset(),
+        }
+    });
+}
+
+/// Erases the character before the writing position, on the framebuffer if there is one.
+pub fn backspace() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        match crate::framebuffer::FRAMEBUFFER_WRITER.try_get() {
+            Ok(framebuffer) => framebuffer.lock().backspace(),
+            Err(_) => WRITER.lock().backspace(),
+        }
+    });
+}
+
+/// Clears the screen, on the framebuffer if there is one. Unlike `reset_terminal`, the colors
+/// and the status line are kept.
+pub fn reset_screen() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        match crate::framebuffer::FRAMEBUFFER_WRITER.try_get() {
+            Ok(framebuffer) => framebuffer.lock().clear(),
+            Err(_) => WRITER.lock().reset_screen(),
+        }
+    });
+}
+
+/// Remembers the writing position as where the command being typed starts.
+///
+/// Only text mode uses it, to mark the cursor, so on the framebuffer this does nothing.
+pub fn reset_cmd_start() {
+    if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_err() {
+        x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().reset_cmd_start());
+    }
+}
+
+/// Marks the cursor `offset` characters into the command being typed. The framebuffer has
+/// no cursor to mark, so there the line is just drawn without one.
+pub fn mark_cursor(offset: usize) {
+    if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_err() {
+        x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().mark_cursor(offset));
+    }
+}
+
+/// Shows `text` on the status line, if it's on. The framebuffer has no status line, so there
+/// the text is dropped.
+pub fn set_status_line(text: &str) {
+    if crate::framebuffer::FRAMEBUFFER_WRITER.try_get().is_err() {
+        x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().set_status_line(text));
+    }
+}
+
+/// Pauses output from `print!`, or resumes it, like Scroll Lock on a terminal.
+///
+/// While paused, output is held (up to `PAUSED_OUTPUT_SIZE` bytes) instead of scrolling what's
+/// on the screen away. Resuming writes out what was held, right away if the writer is free and
+/// otherwise by the next print or timer tick. Safe to call from interrupt handlers.
+pub fn set_output_paused(paused: bool) {
+    OUTPUT_PAUSED.store(paused, Ordering::Relaxed);
+    if !paused {
+        x86_64::instructions::interrupts::without_interrupts(flush_pending_output);
+    }
+}
+
+/// Writes out whatever output is held back, if the writer is free.
+///
+/// Called by the timer interrupt handler with interrupts off, so kept output shows up within a
+/// tick even if nothing else is printed.
+pub(crate) fn flush_pending_output() {
+    if output_paused() {
+        return;
+    }
+    if let Ok(framebuffer) = crate::framebuffer::FRAMEBUFFER_WRITER.try_get() {
+        if let Some(mut framebuffer) = framebuffer.try_lock() {
+            flush_held_output(&mut *framebuffer);
+        }
+    } else if let Some(mut writer) = WRITER.try_lock() {
+        flush_held_output(&mut *writer);
+    }
+}
+
+/// Writes the held output to `out` in the order it was printed: what interrupted a print came
+/// before the pause, if there was one.
+fn flush_held_output(out: &mut dyn Write) {
+    if let Some(mut output) = INTERRUPT_OUTPUT.try_lock() {
+        output.flush_into(out);
+    }
+    if let Some(mut output) = PAUSED_OUTPUT.try_lock() {
+        output.flush_into(out);
+    }
+}
+
+/// Prints to whichever writer is in use, with interrupts off.
+///
+/// Writers are only locked with interrupts off and there's one core, so if the lock is taken,
+/// it's held by the code this print interrupted (an exception handler, usually) and waiting
+/// for it would never end. The output is kept in `INTERRUPT_OUTPUT` instead. Whoever gets the
+/// lock next writes the kept output before their own, so everything comes out in the order it
+/// was printed.
+fn print_to_screen(args: fmt::Arguments, recolor: impl Fn(ColorCode) -> ColorCode) {
+    use x86_64::instructions::interrupts;
+
+    // a formatting error is dropped rather than unwrapped: panicking here would happen with
+    // interrupts off and a writer locked, and the panic handler prints through this same path
+    interrupts::without_interrupts(|| {
+        if output_paused() {
+            if let Some(mut output) = PAUSED_OUTPUT.try_lock() {
+                let _ = output.write_fmt(args);
+            }
+        } else if let Ok(framebuffer) = crate::framebuffer::FRAMEBUFFER_WRITER.try_get() {
+            // the framebuffer has no colors
+            match framebuffer.try_lock() {
+                Some(mut framebuffer) => {
+                    flush_held_output(&mut *framebuffer);
+                    let _ = framebuffer.write_fmt(args);
+                }
+                None => keep_output(args),
+            }
+        } else {
+            match WRITER.try_lock() {
+                Some(mut writer) => {
+                    flush_held_output(&mut *writer);
+                    let color_code = writer.color_code;
+                    writer.color_code = recolor(color_code);
+                    let _ = writer.write_fmt(args);
+                    writer.color_code = color_code;
+                }
+                None => keep_output(args),
+            }
+        }
+    });
+}
+
+fn keep_output(args: fmt::Arguments) {
+    // taken by a print this one interrupted in turn, there's nothing to do but drop it
+    if let Some(mut output) = INTERRUPT_OUTPUT.try_lock() {
+        let _ = output.write_fmt(args);
+    }
+}
+
 /// Prints the given formatted string to the VGA text buffer
 /// through the global `WRITER` instance.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    use x86_64::instructions::interrupts;
+    print_to_screen(args, |color| color);
+}
 
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    });
+/// Like `_print`, but in the given foreground color.
+///
+/// The framebuffer has no colors, so there it's the same as `_print`.
+pub fn print_colored(foreground: Color, args: fmt::Arguments) {
+    print_to_screen(args, |color| color.with_foreground(foreground));
+}
+
+/// Like `_print`, but with the colors swapped, the way a cursor is marked.
+///
+/// The framebuffer has no colors, so there it's the same as `_print`.
+pub fn print_inverted(args: fmt::Arguments) {
+    print_to_screen(args, ColorCode::inverted);
 }
 
-/* 
 #[test_case]
 fn test_println_simple() {
     println!("test_println_simple output");
@@ -309,9 +814,264 @@ fn test_println_output() {
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.read_cell(BUFFER_HEIGHT - 2, i);
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
 }
-*/
+
+#[test_case]
+fn test_line_wrap() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer).expect("writeln failed");
+        for _ in 0..BUFFER_WIDTH + 3 {
+            write!(writer, "x").expect("write failed");
+        }
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(writer.read_cell(BUFFER_HEIGHT - 2, col).ascii_character, b'x');
+        }
+        for col in 0..3 {
+            assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, col).ascii_character, b'x');
+        }
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 3).ascii_character, b' ');
+        assert_eq!(writer.column_position, 3);
+    });
+}
+
+#[test_case]
+fn test_scroll_shifts_rows_up() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nfirst\nsecond").expect("write failed");
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 2, 0).ascii_character, b'f');
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, b's');
+        writeln!(writer).expect("writeln failed");
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 3, 0).ascii_character, b'f');
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 2, 0).ascii_character, b's');
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, b' ');
+    });
+}
+
+#[test_case]
+fn test_backspace_across_wrapped_line() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer).expect("writeln failed");
+        for _ in 0..BUFFER_WIDTH {
+            write!(writer, "y").expect("write failed");
+        }
+        write!(writer, "z").expect("write failed");
+        writer.backspace();
+        assert_eq!(writer.column_position, 0);
+        // at the start of the row, backspace goes back to the end of the wrapped row above
+        writer.backspace();
+        assert_eq!(writer.column_position, BUFFER_WIDTH - 1);
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 2).ascii_character, b'y');
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).ascii_character, 0);
+    });
+}
+
+#[test_case]
+fn test_form_feed_clears_screen() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "some text\nmore\x0Cab").expect("write failed");
+        assert_eq!(writer.column_position, 2);
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, b'a');
+        for row in 0..BUFFER_HEIGHT - 1 {
+            assert_eq!(writer.read_cell(row, 0).ascii_character, b' ');
+        }
+    });
+}
+
+#[test_case]
+fn test_backspace_byte_erases() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\nab\x08c").expect("writeln failed");
+        let row = BUFFER_HEIGHT - 2;
+        assert_eq!(writer.read_cell(row, 0).ascii_character, b'a');
+        assert_eq!(writer.read_cell(row, 1).ascii_character, b'c');
+        assert_eq!(writer.read_cell(row, 2).ascii_character, b' ');
+    });
+}
+
+#[test_case]
+fn test_backspace_byte_on_blank_screen() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\x0C\x08\x08a").expect("write failed");
+        assert_eq!(writer.column_position, 1);
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, b'a');
+    });
+}
+
+#[test_case]
+fn test_backspace_below_blank_line() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nx\n\n").expect("write failed");
+        // the row above is blank, so there's nothing to go back to
+        writer.backspace();
+        assert_eq!(writer.column_position, 0);
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 3, 0).ascii_character, b'x');
+        // a row with only its first cell used is gone back into at column 0
+        write!(writer, "\x0Cy\n").expect("write failed");
+        writer.backspace();
+        assert_eq!(writer.column_position, 0);
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, 0);
+    });
+}
+
+#[test_case]
+fn test_status_line_survives_scrolling() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_status_enabled(true);
+        writer.set_status_line("status");
+        for _ in 0..BUFFER_HEIGHT {
+            writeln!(writer, "scrolling").expect("writeln failed");
+        }
+        let status = writer.read_cell(BUFFER_HEIGHT - 1, 0);
+        assert_eq!(status.ascii_character, b's');
+        assert_eq!(status.color_code, ColorCode::new(Color::Black, Color::LightGray));
+        assert_eq!(writer.read_cell(BUFFER_HEIGHT - 3, 0).ascii_character, b's');
+        writer.set_status_enabled(false);
+    });
+}
+
+#[test_case]
+fn test_write_at_keeps_position() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nab").expect("write failed");
+        writer.write_at(0, BUFFER_WIDTH - 2, "xyz");
+        assert_eq!(writer.read_cell(0, BUFFER_WIDTH - 2).ascii_character, b'x');
+        assert_eq!(writer.read_cell(0, BUFFER_WIDTH - 1).ascii_character, b'y');
+        assert_eq!(writer.column_position, 2);
+    });
+}
+
+#[test_case]
+fn test_palette_set_and_reset() {
+    set_palette_color(1, 0x3F, 0, 0x20);
+    assert_eq!(palette()[1], (0xFF, 0, 0x82));
+    set_palette_color(16, 0, 0, 0);
+    reset_palette();
+    assert_eq!(palette(), COLOR_RGB);
+}
+
+#[test_case]
+fn test_print_while_locked_is_flushed_later() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        // what a handler interrupting the holder of the lock would do
+        print!("\nkept while locked");
+        drop(writer);
+    });
+    println!("\nprinted after");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        for (i, c) in "kept while locked".chars().enumerate() {
+            assert_eq!(char::from(writer.read_cell(BUFFER_HEIGHT - 3, i).ascii_character), c);
+        }
+        for (i, c) in "printed after".chars().enumerate() {
+            assert_eq!(char::from(writer.read_cell(BUFFER_HEIGHT - 2, i).ascii_character), c);
+        }
+    });
+}
+
+#[test_case]
+fn test_held_output_counts_dropped_bytes() {
+    let mut held = HeldOutput::<4>::new();
+    write!(held, "abcdef").unwrap();
+    let mut out = String::new();
+    held.flush_into(&mut out);
+    assert_eq!(out, "abcd\n[2 bytes of output were dropped]\n");
+    out.clear();
+    held.flush_into(&mut out);
+    assert!(out.is_empty());
+}
+
+#[test_case]
+fn test_paused_output_is_held_until_resumed() {
+    use x86_64::instructions::interrupts;
+
+    println!();
+    set_output_paused(true);
+    print!("held while paused");
+    interrupts::without_interrupts(|| {
+        assert_eq!(WRITER.lock().read_cell(BUFFER_HEIGHT - 1, 0).ascii_character, b' ');
+    });
+    set_output_paused(false);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        for (i, c) in "held while paused".chars().enumerate() {
+            assert_eq!(char::from(writer.read_cell(BUFFER_HEIGHT - 1, i).ascii_character), c);
+        }
+    });
+}
+
+#[test_case]
+fn test_fill_screen_keeps_characters() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\nab").expect("writeln failed");
+        let row = BUFFER_HEIGHT - 2;
+        let color = ColorCode::new(Color::White, Color::Blue);
+        writer.fill_screen(color);
+        assert_eq!(writer.read_cell(row, 0), ScreenChar { ascii_character: b'a', color_code: color });
+        assert_eq!(writer.read_cell(row, 1).ascii_character, b'b');
+        assert_eq!(writer.read_cell(0, BUFFER_WIDTH - 1).color_code, color);
+        writer.fill_screen(DEFAULT_COLOR);
+    });
+}
+
+#[test_case]
+fn test_reset_restores_defaults() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_color(ColorCode::new(Color::Blue, Color::White));
+        writer.set_status_enabled(true);
+        writer.set_cursor_shape(CursorShape::Block);
+        writer.reset();
+        assert_eq!(writer.color(), DEFAULT_COLOR);
+        assert_eq!(writer.cursor_shape(), DEFAULT_CURSOR_SHAPE);
+        assert_eq!(writer.text_rows(), BUFFER_HEIGHT);
+        assert_eq!(writer.current_pos().1, 0);
+    });
+}